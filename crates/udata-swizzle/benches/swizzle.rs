@@ -0,0 +1,53 @@
+//! Compares the reference swizzle implementation with the word-at-a-time one.
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use udata_swizzle::swizzle::{self, fast};
+
+fn data(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect()
+}
+
+fn bench_swizzle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("swizzle");
+    for length in [1 << 10, 1 << 16, 1 << 22] {
+        let input = data(length);
+        group.throughput(Throughput::Bytes(length as u64));
+
+        group.bench_with_input(BenchmarkId::new("reference", length), &input, |b, input| {
+            b.iter(|| swizzle::swizzle(black_box(input)))
+        });
+        group.bench_with_input(BenchmarkId::new("fast", length), &input, |b, input| {
+            b.iter(|| fast::swizzle(black_box(input)))
+        });
+        group.bench_with_input(BenchmarkId::new("fast_in_place", length), &input, |b, input| {
+            let mut buffer = input.clone();
+            b.iter(|| fast::swizzle_pairs(black_box(&mut buffer)))
+        });
+        #[cfg(feature = "parallel")]
+        group.bench_with_input(BenchmarkId::new("parallel_in_place", length), &input, |b, input| {
+            let mut buffer = input.clone();
+            b.iter(|| fast::swizzle_parallel(black_box(&mut buffer)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_unswizzle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unswizzle");
+    for length in [1 << 10, 1 << 16, 1 << 22] {
+        let input = swizzle::swizzle(&data(length));
+        group.throughput(Throughput::Bytes(length as u64));
+
+        group.bench_with_input(BenchmarkId::new("reference", length), &input, |b, input| {
+            b.iter(|| swizzle::unswizzle(black_box(input)))
+        });
+        group.bench_with_input(BenchmarkId::new("fast", length), &input, |b, input| {
+            b.iter(|| fast::unswizzle(black_box(input)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_swizzle, bench_unswizzle);
+criterion_main!(benches);
@@ -0,0 +1,162 @@
+/* udata swizzle codec, C API. Generated from src/ffi.rs by cbindgen, do not edit. */
+
+#ifndef UDATA_SWIZZLE_H
+#define UDATA_SWIZZLE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Version of the C API, bumped on incompatible changes.
+#define UDATA_SWIZZLE_ABI_VERSION 1
+
+// Detect the text encoding, only when decoding
+#define UDATA_SWIZZLE_ENCODING_AUTO 0
+
+// Standard base64 with padding
+#define UDATA_SWIZZLE_ENCODING_BASE64 1
+
+// Standard base64 without padding
+#define UDATA_SWIZZLE_ENCODING_BASE64_NOPAD 2
+
+// URL-safe base64 with padding
+#define UDATA_SWIZZLE_ENCODING_BASE64URL 3
+
+// URL-safe base64 without padding
+#define UDATA_SWIZZLE_ENCODING_BASE64URL_NOPAD 4
+
+// Lowercase hex
+#define UDATA_SWIZZLE_ENCODING_HEX 5
+
+// RFC 4648 base32 with padding
+#define UDATA_SWIZZLE_ENCODING_BASE32 6
+
+// Result of the C API functions.
+typedef enum UdataSwizzleStatus {
+  UDATA_SWIZZLE_STATUS_OK = 0,
+  // A required pointer is null
+  UDATA_SWIZZLE_STATUS_NULL_POINTER = 1,
+  // `output` is null or smaller than `output_len`
+  UDATA_SWIZZLE_STATUS_BUFFER_TOO_SMALL = 2,
+  // Unknown encoding constant, or `AUTO` when encoding
+  UDATA_SWIZZLE_STATUS_INVALID_ARGUMENT = 3,
+  // The text isn't valid in the given encoding
+  UDATA_SWIZZLE_STATUS_INVALID_TEXT = 4,
+  // The text isn't in any of the supported encodings
+  UDATA_SWIZZLE_STATUS_UNKNOWN_ENCODING = 5,
+  // Swizzled data is made of whole pairs
+  UDATA_SWIZZLE_STATUS_BAD_LENGTH = 6,
+  // The data doesn't match the checksum of its container
+  UDATA_SWIZZLE_STATUS_CHECKSUM_MISMATCH = 7,
+  // The input isn't a swizzle container
+  UDATA_SWIZZLE_STATUS_BAD_MAGIC = 8,
+  // The container was written by a newer version
+  UDATA_SWIZZLE_STATUS_UNSUPPORTED_VERSION = 9,
+  // The container is keyed, but no key was given
+  UDATA_SWIZZLE_STATUS_KEY_REQUIRED = 10,
+  // The container was keyed with another key
+  UDATA_SWIZZLE_STATUS_WRONG_KEY = 11,
+  // The container uses a scheme added by a newer version
+  UDATA_SWIZZLE_STATUS_UNSUPPORTED_VARIANT = 12,
+} UdataSwizzleStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the version of the C API, see `UDATA_SWIZZLE_ABI_VERSION`.
+uint32_t udata_swizzle_abi_version(void);
+
+// Returns a static, NUL-terminated description of a `UdataSwizzleStatus`.
+const char *udata_swizzle_status_message(int32_t status);
+
+// Swizzles `input`, like `swizzle::swizzle` or `swizzle::swizzle_keyed`.
+//
+// Odd inputs are padded to whole pairs, so the output is `input_len` rounded up to
+// an even length.
+//
+// # Safety
+// `input` and `key` must be valid for their lengths, `output` for `output_capacity`
+// bytes, and `output_len` for a write.
+enum UdataSwizzleStatus udata_swizzle_encode(const uint8_t *input,
+                                             size_t input_len,
+                                             const uint8_t *key,
+                                             size_t key_len,
+                                             uint8_t *output,
+                                             size_t output_capacity,
+                                             size_t *output_len);
+
+// Reverses `udata_swizzle_encode`.
+//
+// # Safety
+// Same as `udata_swizzle_encode`.
+enum UdataSwizzleStatus udata_swizzle_decode(const uint8_t *input,
+                                             size_t input_len,
+                                             const uint8_t *key,
+                                             size_t key_len,
+                                             uint8_t *output,
+                                             size_t output_capacity,
+                                             size_t *output_len);
+
+// Swizzles `input` into a container, like `swizzle::seal`, so that
+// `udata_swizzle_unseal` returns exactly `input`.
+//
+// # Safety
+// Same as `udata_swizzle_encode`.
+enum UdataSwizzleStatus udata_swizzle_seal(const uint8_t *input,
+                                           size_t input_len,
+                                           const uint8_t *key,
+                                           size_t key_len,
+                                           uint8_t *output,
+                                           size_t output_capacity,
+                                           size_t *output_len);
+
+// Reverses `udata_swizzle_seal`, checking the length, key and checksum.
+//
+// # Safety
+// Same as `udata_swizzle_encode`.
+enum UdataSwizzleStatus udata_swizzle_unseal(const uint8_t *input,
+                                             size_t input_len,
+                                             const uint8_t *key,
+                                             size_t key_len,
+                                             uint8_t *output,
+                                             size_t output_capacity,
+                                             size_t *output_len);
+
+// Swizzles `input` and writes it as text in `encoding`, one of the
+// `UDATA_SWIZZLE_ENCODING_*` constants other than `AUTO`.
+//
+// The text is ASCII and isn't NUL-terminated.
+//
+// # Safety
+// Same as `udata_swizzle_encode`.
+enum UdataSwizzleStatus udata_swizzle_encode_text(const uint8_t *input,
+                                                  size_t input_len,
+                                                  const uint8_t *key,
+                                                  size_t key_len,
+                                                  uint32_t encoding,
+                                                  uint8_t *output,
+                                                  size_t output_capacity,
+                                                  size_t *output_len);
+
+// Reverses `udata_swizzle_encode_text`.
+//
+// With `UDATA_SWIZZLE_ENCODING_AUTO`, the encodings are tried in the order of
+// `swizzle::Encoding::ALL` and the first one that unswizzles is used. Surrounding
+// whitespace is ignored.
+//
+// # Safety
+// Same as `udata_swizzle_encode`, with `input` holding `input_len` bytes of text.
+enum UdataSwizzleStatus udata_swizzle_decode_text(const uint8_t *input,
+                                                  size_t input_len,
+                                                  const uint8_t *key,
+                                                  size_t key_len,
+                                                  uint32_t encoding,
+                                                  uint8_t *output,
+                                                  size_t output_capacity,
+                                                  size_t *output_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UDATA_SWIZZLE_H */
@@ -0,0 +1,186 @@
+//! Encodes and decodes swizzled payloads outside the browser.
+//!
+//! ```text
+//! udata-swizzle encode [INPUT] [-o OUTPUT] [--format raw|base64|base64url|hex|base32|...] [--keyed KEY] [--container]
+//! udata-swizzle decode [INPUT] [-o OUTPUT] [--format raw|auto|...] [--keyed KEY] [--container] [--hexdump]
+//! ```
+//!
+//! Input is read from a file or stdin, output is written to a file or stdout. With
+//! `--format auto`, decoding tries every text encoding and keeps the first one that
+//! unswizzles.
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use pretty_hex::PrettyHex;
+use std::io::{Read, Write};
+
+use udata_swizzle::swizzle::{self, Encoding, SwizzleError};
+
+#[derive(Parser)]
+#[command(name = "udata-swizzle", about = "Encode and decode swizzled payloads")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Swizzle data
+    Encode(CodecArgs),
+    /// Unswizzle data
+    Decode(CodecArgs),
+}
+
+#[derive(Args)]
+struct CodecArgs {
+    /// Input file, stdin when missing or `-`
+    input: Option<String>,
+    /// Output file, stdout when missing or `-`
+    #[arg(long, short)]
+    output: Option<String>,
+    /// Text encoding of the swizzled data
+    #[arg(long, short, value_enum, default_value_t = Format::Raw)]
+    format: Format,
+    /// Use the keyed variant with this key
+    #[arg(long, value_name = "KEY", conflicts_with = "key_file")]
+    keyed: Option<String>,
+    /// Use the keyed variant with the content of this file as the key
+    #[arg(long, value_name = "PATH")]
+    key_file: Option<String>,
+    /// Wrap the swizzled data in a container recording its length and checksum
+    #[arg(long)]
+    container: bool,
+    /// Write a hex dump instead of the bytes
+    #[arg(long)]
+    hexdump: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Raw,
+    /// Detect the text encoding, only when decoding
+    Auto,
+    Base64,
+    Base64Nopad,
+    Base64url,
+    Base64urlNopad,
+    Hex,
+    Base32,
+}
+
+impl Format {
+    fn encoding(self) -> Option<Encoding> {
+        match self {
+            Format::Raw | Format::Auto => None,
+            Format::Base64 => Some(Encoding::Base64),
+            Format::Base64Nopad => Some(Encoding::Base64NoPad),
+            Format::Base64url => Some(Encoding::Base64Url),
+            Format::Base64urlNopad => Some(Encoding::Base64UrlNoPad),
+            Format::Hex => Some(Encoding::Hex),
+            Format::Base32 => Some(Encoding::Base32),
+        }
+    }
+
+    /// Decodes the input into the possible swizzled data, most likely first.
+    fn candidates(self, data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        if self == Format::Raw {
+            return Ok(vec![data.to_vec()]);
+        }
+
+        let text = std::str::from_utf8(data).map_err(|_| SwizzleError::UnknownEncoding.to_string())?;
+        match self.encoding() {
+            Some(encoding) => Ok(vec![encoding.decode(text).map_err(|e| e.to_string())?]),
+            None => Ok(Encoding::candidates(text).map(|(_, data)| data).collect()),
+        }
+    }
+}
+
+fn main() {
+    let result = match Cli::parse().command {
+        Command::Encode(args) => encode(&args),
+        Command::Decode(args) => decode(&args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("[{}] {}", "error".red(), e);
+        std::process::exit(1);
+    }
+}
+
+fn encode(args: &CodecArgs) -> Result<(), String> {
+    let input = read_input(args)?;
+    let key = key(args)?;
+
+    let swizzled = match (args.container, key.as_deref()) {
+        (true, key) => swizzle::seal(&input, key),
+        (false, Some(key)) => swizzle::swizzle_keyed(&input, key),
+        (false, None) => swizzle::fast::swizzle(&input),
+    };
+
+    let output = match args.format {
+        Format::Raw => swizzled,
+        Format::Auto => return Err(String::from("--format auto only applies to decode")),
+        format => {
+            let encoding = format.encoding().expect("text format");
+            format!("{}\n", encoding.encode(&swizzled)).into_bytes()
+        }
+    };
+    write_output(args, &output)
+}
+
+fn decode(args: &CodecArgs) -> Result<(), String> {
+    let candidates = args.format.candidates(&read_input(args)?)?;
+    let key = key(args)?;
+
+    let mut result = Err(SwizzleError::UnknownEncoding);
+    for input in candidates {
+        result = match (args.container, key.as_deref()) {
+            (true, key) => swizzle::unseal(&input, key),
+            (false, Some(key)) => swizzle::try_unswizzle_keyed(&input, key),
+            (false, None) => swizzle::try_unswizzle(&input),
+        };
+        if result.is_ok() {
+            break;
+        }
+    }
+    write_output(args, &result.map_err(|e| e.to_string())?)
+}
+
+/// Reads the key of the keyed variant, if one is given.
+fn key(args: &CodecArgs) -> Result<Option<Vec<u8>>, String> {
+    if let Some(key) = args.keyed.as_ref() {
+        return Ok(Some(key.clone().into_bytes()));
+    }
+
+    args.key_file
+        .as_ref()
+        .map(|path| std::fs::read(path).map_err(|e| format!("Failed to read key file {}: {}", path, e)))
+        .transpose()
+}
+
+fn read_input(args: &CodecArgs) -> Result<Vec<u8>, String> {
+    let mut input = Vec::new();
+    match args.input.as_deref() {
+        None | Some("-") => std::io::stdin().lock().read_to_end(&mut input).map(|_| input),
+        Some(path) => std::fs::read(path),
+    }
+    .map_err(|e| format!("Failed to read {}: {}", args.input.as_deref().unwrap_or("stdin"), e))
+}
+
+fn write_output(args: &CodecArgs, data: &[u8]) -> Result<(), String> {
+    let dump;
+    let data = if args.hexdump {
+        dump = format!("{:?}\n", data.hex_dump());
+        dump.as_bytes()
+    } else {
+        data
+    };
+
+    match args.output.as_deref() {
+        None | Some("-") => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(data).and_then(|_| stdout.flush())
+        }
+        Some(path) => std::fs::write(path, data),
+    }
+    .map_err(|e| format!("Failed to write {}: {}", args.output.as_deref().unwrap_or("stdout"), e))
+}
@@ -0,0 +1,431 @@
+//! C API of the swizzle codec, for tools that can't link Rust directly.
+//!
+//! The library is built as a `cdylib` (`libudata_swizzle.so`, `udata_swizzle.dll`,
+//! `libudata_swizzle.dylib`), and `include/udata_swizzle.h` declares these functions. The
+//! header is generated by cbindgen from this file with `cbindgen.toml`; `test_header`
+//! fails when it's out of date, and rewrites it with `UDATA_UPDATE_HEADER=1`.
+//!
+//! Every function writes into a buffer owned by the caller and returns a status.
+//! `output_len` always receives the length the output needs, so a first call with a
+//! null `output` reports the size to allocate. Keys are optional: a null `key` selects
+//! the unkeyed scheme, a non-null one with `key_len` 0 is the empty key.
+#![allow(clippy::too_many_arguments)]
+use std::ffi::{CStr, c_char};
+
+use crate::swizzle::{self, Encoding, SwizzleError};
+
+/// Version of the C API, bumped on incompatible changes.
+pub const UDATA_SWIZZLE_ABI_VERSION: u32 = 1;
+
+/// Detect the text encoding, only when decoding
+pub const UDATA_SWIZZLE_ENCODING_AUTO: u32 = 0;
+/// Standard base64 with padding
+pub const UDATA_SWIZZLE_ENCODING_BASE64: u32 = 1;
+/// Standard base64 without padding
+pub const UDATA_SWIZZLE_ENCODING_BASE64_NOPAD: u32 = 2;
+/// URL-safe base64 with padding
+pub const UDATA_SWIZZLE_ENCODING_BASE64URL: u32 = 3;
+/// URL-safe base64 without padding
+pub const UDATA_SWIZZLE_ENCODING_BASE64URL_NOPAD: u32 = 4;
+/// Lowercase hex
+pub const UDATA_SWIZZLE_ENCODING_HEX: u32 = 5;
+/// RFC 4648 base32 with padding
+pub const UDATA_SWIZZLE_ENCODING_BASE32: u32 = 6;
+
+/// Result of the C API functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdataSwizzleStatus {
+    Ok = 0,
+    /// A required pointer is null
+    NullPointer = 1,
+    /// `output` is null or smaller than `output_len`
+    BufferTooSmall = 2,
+    /// Unknown encoding constant, or `AUTO` when encoding
+    InvalidArgument = 3,
+    /// The text isn't valid in the given encoding
+    InvalidText = 4,
+    /// The text isn't in any of the supported encodings
+    UnknownEncoding = 5,
+    /// Swizzled data is made of whole pairs
+    BadLength = 6,
+    /// The data doesn't match the checksum of its container
+    ChecksumMismatch = 7,
+    /// The input isn't a swizzle container
+    BadMagic = 8,
+    /// The container was written by a newer version
+    UnsupportedVersion = 9,
+    /// The container is keyed, but no key was given
+    KeyRequired = 10,
+    /// The container was keyed with another key
+    WrongKey = 11,
+    /// The container uses a scheme added by a newer version
+    UnsupportedVariant = 12,
+}
+
+impl From<SwizzleError> for UdataSwizzleStatus {
+    fn from(e: SwizzleError) -> Self {
+        match e {
+            SwizzleError::InvalidBase64(_) | SwizzleError::InvalidText(_) => UdataSwizzleStatus::InvalidText,
+            SwizzleError::UnknownEncoding => UdataSwizzleStatus::UnknownEncoding,
+            SwizzleError::BadLength { .. } => UdataSwizzleStatus::BadLength,
+            SwizzleError::ChecksumMismatch { .. } => UdataSwizzleStatus::ChecksumMismatch,
+            SwizzleError::BadMagic => UdataSwizzleStatus::BadMagic,
+            SwizzleError::UnsupportedVersion(_) => UdataSwizzleStatus::UnsupportedVersion,
+            SwizzleError::UnsupportedVariant(_) => UdataSwizzleStatus::UnsupportedVariant,
+            SwizzleError::KeyRequired => UdataSwizzleStatus::KeyRequired,
+            SwizzleError::WrongKey => UdataSwizzleStatus::WrongKey,
+        }
+    }
+}
+
+/// Returns the version of the C API, see `UDATA_SWIZZLE_ABI_VERSION`.
+#[unsafe(no_mangle)]
+pub extern "C" fn udata_swizzle_abi_version() -> u32 {
+    UDATA_SWIZZLE_ABI_VERSION
+}
+
+/// Returns a static, NUL-terminated description of a `UdataSwizzleStatus`.
+#[unsafe(no_mangle)]
+pub extern "C" fn udata_swizzle_status_message(status: i32) -> *const c_char {
+    // Taken as an integer, as C callers may pass values outside of the enum
+    let messages: [(UdataSwizzleStatus, &'static CStr); 13] = [
+        (UdataSwizzleStatus::Ok, c"ok"),
+        (UdataSwizzleStatus::NullPointer, c"null pointer"),
+        (UdataSwizzleStatus::BufferTooSmall, c"output buffer too small"),
+        (UdataSwizzleStatus::InvalidArgument, c"invalid argument"),
+        (UdataSwizzleStatus::InvalidText, c"invalid text"),
+        (UdataSwizzleStatus::UnknownEncoding, c"not base64, hex or base32"),
+        (UdataSwizzleStatus::BadLength, c"bad length"),
+        (UdataSwizzleStatus::ChecksumMismatch, c"checksum mismatch"),
+        (UdataSwizzleStatus::BadMagic, c"not a swizzle container"),
+        (UdataSwizzleStatus::UnsupportedVersion, c"unsupported container version"),
+        (UdataSwizzleStatus::KeyRequired, c"the container needs a key"),
+        (UdataSwizzleStatus::WrongKey, c"the container was keyed with another key"),
+        (UdataSwizzleStatus::UnsupportedVariant, c"unsupported container variant"),
+    ];
+
+    messages
+        .iter()
+        .find(|(known, _)| *known as i32 == status)
+        .map_or(c"unknown status", |(_, message)| message)
+        .as_ptr()
+}
+
+/// Swizzles `input`, like `swizzle::swizzle` or `swizzle::swizzle_keyed`.
+///
+/// Odd inputs are padded to whole pairs, so the output is `input_len` rounded up to
+/// an even length.
+///
+/// # Safety
+/// `input` and `key` must be valid for their lengths, `output` for `output_capacity`
+/// bytes, and `output_len` for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_encode(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.map(|(input, key)| match key {
+        Some(key) => swizzle::swizzle_keyed(input, key),
+        None => swizzle::fast::swizzle(input),
+    });
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Reverses `udata_swizzle_encode`.
+///
+/// # Safety
+/// Same as `udata_swizzle_encode`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_decode(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.and_then(|(input, key)| {
+        let plain = match key {
+            Some(key) => swizzle::try_unswizzle_keyed(input, key),
+            None => swizzle::try_unswizzle(input),
+        };
+        Ok(plain?)
+    });
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Swizzles `input` into a container, like `swizzle::seal`, so that
+/// `udata_swizzle_unseal` returns exactly `input`.
+///
+/// # Safety
+/// Same as `udata_swizzle_encode`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_seal(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.map(|(input, key)| swizzle::seal(input, key));
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Reverses `udata_swizzle_seal`, checking the length, key and checksum.
+///
+/// # Safety
+/// Same as `udata_swizzle_encode`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_unseal(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.and_then(|(input, key)| Ok(swizzle::unseal(input, key)?));
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Swizzles `input` and writes it as text in `encoding`, one of the
+/// `UDATA_SWIZZLE_ENCODING_*` constants other than `AUTO`.
+///
+/// The text is ASCII and isn't NUL-terminated.
+///
+/// # Safety
+/// Same as `udata_swizzle_encode`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_encode_text(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    encoding: u32,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.and_then(|(input, key)| {
+        let encoding = text_encoding(encoding)?.ok_or(UdataSwizzleStatus::InvalidArgument)?;
+        let swizzled = match key {
+            Some(key) => swizzle::swizzle_keyed(input, key),
+            None => swizzle::fast::swizzle(input),
+        };
+        Ok(encoding.encode(&swizzled).into_bytes())
+    });
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Reverses `udata_swizzle_encode_text`.
+///
+/// With `UDATA_SWIZZLE_ENCODING_AUTO`, the encodings are tried in the order of
+/// `swizzle::Encoding::ALL` and the first one that unswizzles is used. Surrounding
+/// whitespace is ignored.
+///
+/// # Safety
+/// Same as `udata_swizzle_encode`, with `input` holding `input_len` bytes of text.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn udata_swizzle_decode_text(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+    encoding: u32,
+    output: *mut u8,
+    output_capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    let result = unsafe { arguments(input, input_len, key, key_len) }.and_then(|(input, key)| {
+        let encoding = text_encoding(encoding)?;
+        let text = String::from_utf8_lossy(input);
+        let unswizzle = |data: &[u8]| match key {
+            Some(key) => swizzle::try_unswizzle_keyed(data, key),
+            None => swizzle::try_unswizzle(data),
+        };
+
+        let plain = match encoding {
+            Some(encoding) => unswizzle(&encoding.decode(&text)?)?,
+            None => Encoding::candidates(&text)
+                .find_map(|(_, data)| unswizzle(&data).ok())
+                .ok_or(SwizzleError::UnknownEncoding)?,
+        };
+        Ok(plain)
+    });
+    unsafe { write_output(result, output, output_capacity, output_len) }
+}
+
+/// Reads the input and key arguments.
+///
+/// # Safety
+/// Non-null pointers must be valid for their lengths.
+unsafe fn arguments<'a>(
+    input: *const u8,
+    input_len: usize,
+    key: *const u8,
+    key_len: usize,
+) -> Result<(&'a [u8], Option<&'a [u8]>), UdataSwizzleStatus> {
+    let input = match input.is_null() {
+        // An empty input may come without a buffer
+        true if input_len == 0 => &[][..],
+        true => return Err(UdataSwizzleStatus::NullPointer),
+        false => unsafe { std::slice::from_raw_parts(input, input_len) },
+    };
+    let key = match key.is_null() {
+        true => None,
+        false => Some(unsafe { std::slice::from_raw_parts(key, key_len) }),
+    };
+    Ok((input, key))
+}
+
+/// Maps an `UDATA_SWIZZLE_ENCODING_*` constant, `None` being `AUTO`.
+fn text_encoding(encoding: u32) -> Result<Option<Encoding>, UdataSwizzleStatus> {
+    match encoding {
+        UDATA_SWIZZLE_ENCODING_AUTO => Ok(None),
+        UDATA_SWIZZLE_ENCODING_BASE64 => Ok(Some(Encoding::Base64)),
+        UDATA_SWIZZLE_ENCODING_BASE64_NOPAD => Ok(Some(Encoding::Base64NoPad)),
+        UDATA_SWIZZLE_ENCODING_BASE64URL => Ok(Some(Encoding::Base64Url)),
+        UDATA_SWIZZLE_ENCODING_BASE64URL_NOPAD => Ok(Some(Encoding::Base64UrlNoPad)),
+        UDATA_SWIZZLE_ENCODING_HEX => Ok(Some(Encoding::Hex)),
+        UDATA_SWIZZLE_ENCODING_BASE32 => Ok(Some(Encoding::Base32)),
+        _ => Err(UdataSwizzleStatus::InvalidArgument),
+    }
+}
+
+/// Copies a result into the caller's buffer.
+///
+/// # Safety
+/// `output` must be null or valid for `capacity` bytes, `output_len` null or valid
+/// for a write.
+unsafe fn write_output(
+    result: Result<Vec<u8>, UdataSwizzleStatus>,
+    output: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> UdataSwizzleStatus {
+    if output_len.is_null() {
+        return UdataSwizzleStatus::NullPointer;
+    }
+    let data = match result {
+        Ok(data) => data,
+        Err(status) => {
+            unsafe { *output_len = 0 };
+            return status;
+        }
+    };
+
+    unsafe { *output_len = data.len() };
+    if data.is_empty() {
+        return UdataSwizzleStatus::Ok;
+    }
+    if output.is_null() || capacity < data.len() {
+        return UdataSwizzleStatus::BufferTooSmall;
+    }
+
+    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), output, data.len()) };
+    UdataSwizzleStatus::Ok
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Calls a C API function the way C code would: once for the length, once more
+    /// with a buffer of that size.
+    fn call(f: impl Fn(*mut u8, usize, *mut usize) -> UdataSwizzleStatus) -> Result<Vec<u8>, UdataSwizzleStatus> {
+        let mut length = 0;
+        match f(std::ptr::null_mut(), 0, &mut length) {
+            UdataSwizzleStatus::Ok | UdataSwizzleStatus::BufferTooSmall => {}
+            status => return Err(status),
+        }
+
+        let mut output = vec![0; length];
+        match f(output.as_mut_ptr(), output.len(), &mut length) {
+            UdataSwizzleStatus::Ok => Ok(output),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn test_ffi() {
+        let data = b"{\"id\":42}";
+        let key = b"secret";
+        let no_key = std::ptr::null();
+
+        unsafe {
+            let swizzled = call(|o, c, l| udata_swizzle_encode(data.as_ptr(), data.len(), no_key, 0, o, c, l)).unwrap();
+            assert_eq!(swizzled, swizzle::swizzle(data));
+            let plain = call(|o, c, l| udata_swizzle_decode(swizzled.as_ptr(), swizzled.len(), no_key, 0, o, c, l)).unwrap();
+            assert_eq!(plain, swizzle::unswizzle(&swizzled));
+            assert_eq!(
+                call(|o, c, l| udata_swizzle_decode(swizzled.as_ptr(), 3, no_key, 0, o, c, l)),
+                Err(UdataSwizzleStatus::BadLength)
+            );
+
+            let sealed = call(|o, c, l| udata_swizzle_seal(data.as_ptr(), data.len(), key.as_ptr(), key.len(), o, c, l)).unwrap();
+            assert_eq!(sealed, swizzle::seal(data, Some(key)));
+            let plain = call(|o, c, l| udata_swizzle_unseal(sealed.as_ptr(), sealed.len(), key.as_ptr(), key.len(), o, c, l)).unwrap();
+            assert_eq!(plain, data);
+            assert_eq!(
+                call(|o, c, l| udata_swizzle_unseal(sealed.as_ptr(), sealed.len(), no_key, 0, o, c, l)),
+                Err(UdataSwizzleStatus::KeyRequired)
+            );
+
+            let text = call(|o, c, l| udata_swizzle_encode_text(data.as_ptr(), data.len(), key.as_ptr(), key.len(), UDATA_SWIZZLE_ENCODING_HEX, o, c, l)).unwrap();
+            assert_eq!(text, Encoding::Hex.encode(&swizzle::swizzle_keyed(data, key)).as_bytes());
+            for encoding in [UDATA_SWIZZLE_ENCODING_HEX, UDATA_SWIZZLE_ENCODING_AUTO] {
+                let plain = call(|o, c, l| udata_swizzle_decode_text(text.as_ptr(), text.len(), key.as_ptr(), key.len(), encoding, o, c, l)).unwrap();
+                assert_eq!(plain[..data.len()], *data);
+            }
+            assert_eq!(
+                call(|o, c, l| udata_swizzle_encode_text(data.as_ptr(), data.len(), no_key, 0, UDATA_SWIZZLE_ENCODING_AUTO, o, c, l)),
+                Err(UdataSwizzleStatus::InvalidArgument)
+            );
+            assert_eq!(
+                call(|o, c, l| udata_swizzle_decode_text(c"!!".as_ptr().cast(), 2, no_key, 0, UDATA_SWIZZLE_ENCODING_AUTO, o, c, l)),
+                Err(UdataSwizzleStatus::UnknownEncoding)
+            );
+
+            // Too small a buffer is reported with the size needed
+            let mut output = [0u8; 4];
+            let mut length = 0;
+            let status = udata_swizzle_encode(data.as_ptr(), data.len(), no_key, 0, output.as_mut_ptr(), output.len(), &mut length);
+            assert_eq!((status, length), (UdataSwizzleStatus::BufferTooSmall, 10));
+
+            assert_eq!(udata_swizzle_encode(std::ptr::null(), 1, no_key, 0, output.as_mut_ptr(), 4, &mut length), UdataSwizzleStatus::NullPointer);
+            assert_eq!(call(|o, c, l| udata_swizzle_encode(std::ptr::null(), 0, no_key, 0, o, c, l)).unwrap(), Vec::<u8>::new());
+            assert_eq!(CStr::from_ptr(udata_swizzle_status_message(UdataSwizzleStatus::WrongKey as i32)).to_str().unwrap(), "the container was keyed with another key");
+            assert_eq!(CStr::from_ptr(udata_swizzle_status_message(-1)).to_str().unwrap(), "unknown status");
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", dir))
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+
+        let path = format!("{}/include/udata_swizzle.h", dir);
+        if std::env::var_os("UDATA_UPDATE_HEADER").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+        }
+        let current = std::fs::read(&path).unwrap_or_default();
+        assert!(current == generated, "{} is out of date, run the tests with UDATA_UPDATE_HEADER=1", path);
+    }
+}
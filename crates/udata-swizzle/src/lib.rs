@@ -0,0 +1,5 @@
+//! The swizzle codec, kept free of CEF so it builds on its own. Shared by the `udata-rs`
+//! browser and the `udata-swizzle` tool (`src/bin`, built with the `cli` feature), and
+//! exported to other languages through the C API in `ffi`.
+pub mod ffi;
+pub mod swizzle;
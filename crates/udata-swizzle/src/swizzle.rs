@@ -0,0 +1,1250 @@
+#![allow(dead_code)]
+#![allow(unused_imports)]
+use base64::engine::GeneralPurpose;
+use base64::prelude::*;
+use base64::read::DecoderReader;
+use base64::write::EncoderWriter;
+use pretty_hex::*;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+pub mod fast;
+
+/// Reasons untrusted data can't be unswizzled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwizzleError {
+    /// The input isn't valid base64
+    InvalidBase64(base64::DecodeError),
+    /// The input isn't valid hex or base32
+    InvalidText(data_encoding::DecodeError),
+    /// The input isn't in any of the supported text encodings
+    UnknownEncoding,
+    /// The input can't have been produced by the encoder, swizzled data is made of
+    /// whole pairs
+    BadLength { length: usize },
+    /// The data doesn't match the checksum stored alongside it
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The input isn't a swizzle container
+    BadMagic,
+    /// The container was written by a newer version
+    UnsupportedVersion(u8),
+    /// The container uses a scheme added by a newer version
+    UnsupportedVariant(u8),
+    /// The container is keyed, but no key was given
+    KeyRequired,
+    /// The container was keyed with another key
+    WrongKey,
+}
+
+impl std::fmt::Display for SwizzleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SwizzleError::InvalidBase64(e) => write!(f, "invalid base64: {}", e),
+            SwizzleError::InvalidText(e) => write!(f, "invalid text: {}", e),
+            SwizzleError::UnknownEncoding => write!(f, "not base64, hex or base32"),
+            SwizzleError::BadLength { length } => write!(f, "bad length {}, expected whole pairs", length),
+            SwizzleError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch, expected {:08x} but got {:08x}", expected, actual)
+            }
+            SwizzleError::BadMagic => write!(f, "not a swizzle container"),
+            SwizzleError::UnsupportedVersion(version) => write!(f, "unsupported container version {}", version),
+            SwizzleError::UnsupportedVariant(variant) => write!(f, "unsupported container variant {}", variant),
+            SwizzleError::KeyRequired => write!(f, "the container is keyed, a key is required"),
+            SwizzleError::WrongKey => write!(f, "the container was keyed with another key"),
+        }
+    }
+}
+
+impl std::error::Error for SwizzleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SwizzleError::InvalidBase64(e) => Some(e),
+            SwizzleError::InvalidText(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<base64::DecodeError> for SwizzleError {
+    fn from(e: base64::DecodeError) -> Self {
+        SwizzleError::InvalidBase64(e)
+    }
+}
+
+impl From<data_encoding::DecodeError> for SwizzleError {
+    fn from(e: data_encoding::DecodeError) -> Self {
+        SwizzleError::InvalidText(e)
+    }
+}
+
+impl From<SwizzleError> for io::Error {
+    fn from(e: SwizzleError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Swizzles `input` and encodes it as base64.
+///
+/// Odd inputs are padded like `swizzle`; use `seal` for exact round trips.
+pub fn base64_swizzle(input: &[u8]) -> String {
+    let mut output = input.to_vec();
+
+    // If input is odd length, we need to add a padding byte
+    if input.len() % 2 == 1 {
+        output.push(output[0]);
+    }
+
+    for pair in output.chunks_mut(2) {
+        if let [x, y] = pair {
+            let n1 = *x % 8;
+
+            *y = y.rotate_right(n1.into());
+
+            let n2 = *y % 8;
+
+            *x = x.rotate_right(n2.into());
+        }
+    }
+
+    BASE64_STANDARD.encode(&output)
+}
+
+/// Reverses `base64_swizzle`.
+///
+/// Like `unswizzle`, an odd number of decoded bytes is padded before the pairs are
+/// reversed, and the padding byte added by `base64_swizzle` is returned with the data.
+/// Only `seal`/`unseal` give exact round trips of odd-length inputs.
+///
+/// # Returns
+/// An empty `Vec` if `input` isn't valid base64, see `try_base64_unswizzle`.
+pub fn base64_unswizzle(input: &str) -> Vec<u8> {
+    let Ok(mut output) = BASE64_STANDARD.decode(input) else {
+        return Vec::new();
+    };
+
+    // If the decoded data is odd length, we need to add a padding byte
+    if output.len() % 2 == 1
+        && let Some(&first) = output.first()
+    {
+        output.push(first);
+    }
+
+    for pair in output.chunks_mut(2) {
+        if let [x, y] = pair {
+            let n1 = *y % 8;
+
+            *x = x.rotate_left(n1.into());
+
+            let n2 = *x % 8;
+
+            *y = y.rotate_left(n2.into());
+        }
+    }
+
+    output.to_vec()
+}
+
+/// Swizzles `input`.
+///
+/// Odd inputs are padded with a copy of their first byte, which `unswizzle` keeps, so
+/// their round trip returns one extra byte. `seal` records the original length.
+pub fn swizzle(input: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+
+    // If input is odd length, we need to add a padding byte
+    if input.len() % 2 == 1 {
+        output.push(output[0]);
+    }
+
+    for pair in output.chunks_mut(2) {
+        if let [x, y] = pair {
+            let n1 = *x % 8;
+
+            *y = y.rotate_right(n1.into());
+
+            let n2 = *y % 8;
+
+            *x = x.rotate_right(n2.into());
+        }
+    }
+
+    output.to_vec()
+}
+
+/// Reverses `swizzle`.
+pub fn unswizzle(input: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+    // If input is odd length, we need to add a padding byte
+    if input.len() % 2 == 1 {
+        output.push(output[0]);
+    }
+
+    for pair in output.chunks_mut(2) {
+        if let [x, y] = pair {
+            let n1 = *y % 8;
+
+            *x = x.rotate_left(n1.into());
+
+            let n2 = *x % 8;
+
+            *y = y.rotate_left(n2.into());
+        }
+    }
+
+    output.to_vec()
+}
+
+/// Reverses `base64_swizzle`, rejecting input it can't have produced.
+pub fn try_base64_unswizzle(input: &str) -> Result<Vec<u8>, SwizzleError> {
+    let data = BASE64_STANDARD.decode(input)?;
+    try_unswizzle(&data)
+}
+
+/// Text encoding of swizzled bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Standard base64 with padding, as written by `base64_swizzle`
+    Base64,
+    /// Standard base64 without padding
+    Base64NoPad,
+    /// URL-safe base64 with padding
+    Base64Url,
+    /// URL-safe base64 without padding
+    Base64UrlNoPad,
+    /// Lowercase hex, uppercase is accepted too
+    Hex,
+    /// RFC 4648 base32 with padding, missing padding is accepted too
+    Base32,
+}
+
+impl Encoding {
+    /// Every encoding, in the order `Encoding::detect` tries them.
+    ///
+    /// Hex and base32 come first: their alphabets are subsets of base64, and data
+    /// that happens to be valid in them is very unlikely to be base64.
+    pub const ALL: [Encoding; 6] = [
+        Encoding::Hex,
+        Encoding::Base32,
+        Encoding::Base64,
+        Encoding::Base64NoPad,
+        Encoding::Base64Url,
+        Encoding::Base64UrlNoPad,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Base64 => "base64",
+            Encoding::Base64NoPad => "base64-nopad",
+            Encoding::Base64Url => "base64url",
+            Encoding::Base64UrlNoPad => "base64url-nopad",
+            Encoding::Hex => "hex",
+            Encoding::Base32 => "base32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|encoding| encoding.name() == name)
+    }
+
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => BASE64_STANDARD.encode(data),
+            Encoding::Base64NoPad => BASE64_STANDARD_NO_PAD.encode(data),
+            Encoding::Base64Url => BASE64_URL_SAFE.encode(data),
+            Encoding::Base64UrlNoPad => BASE64_URL_SAFE_NO_PAD.encode(data),
+            Encoding::Hex => data_encoding::HEXLOWER.encode(data),
+            Encoding::Base32 => data_encoding::BASE32.encode(data),
+        }
+    }
+
+    /// Decodes `text`, ignoring surrounding whitespace.
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, SwizzleError> {
+        let text = text.trim().as_bytes();
+        match self {
+            Encoding::Base64 => Ok(BASE64_STANDARD.decode(text)?),
+            Encoding::Base64NoPad => Ok(BASE64_STANDARD_NO_PAD.decode(text)?),
+            Encoding::Base64Url => Ok(BASE64_URL_SAFE.decode(text)?),
+            Encoding::Base64UrlNoPad => Ok(BASE64_URL_SAFE_NO_PAD.decode(text)?),
+            Encoding::Hex => Ok(data_encoding::HEXLOWER_PERMISSIVE.decode(text)?),
+            Encoding::Base32 => match data_encoding::BASE32.decode(text) {
+                Ok(data) => Ok(data),
+                Err(_) => Ok(data_encoding::BASE32_NOPAD.decode(text)?),
+            },
+        }
+    }
+
+    /// Finds the encoding of `text`, see `Encoding::ALL` for the order.
+    pub fn detect(text: &str) -> Option<Self> {
+        Self::candidates(text).next().map(|(encoding, _)| encoding)
+    }
+
+    /// Decodes `text` with every encoding it's valid in, in the order of `ALL`.
+    pub fn candidates(text: &str) -> impl Iterator<Item = (Self, Vec<u8>)> + '_ {
+        Self::ALL
+            .into_iter()
+            .filter(|_| !text.trim().is_empty())
+            .filter_map(move |encoding| encoding.decode(text).ok().map(|data| (encoding, data)))
+    }
+}
+
+/// Swizzles `input` and writes it in `encoding`, like `base64_swizzle`.
+pub fn encoded_swizzle(input: &[u8], encoding: Encoding) -> String {
+    encoding.encode(&swizzle(input))
+}
+
+/// Reverses `encoded_swizzle`.
+///
+/// # Parameters
+/// - `input`: The encoded swizzled data.
+/// - `encoding`: The encoding of `input`, or `None` to detect it.
+///
+/// # Returns
+/// The encoding used and the unswizzled data. With detection, the first encoding
+/// `input` is valid in and that decodes to whole pairs is used.
+pub fn encoded_unswizzle(input: &str, encoding: Option<Encoding>) -> Result<(Encoding, Vec<u8>), SwizzleError> {
+    if let Some(encoding) = encoding {
+        return Ok((encoding, try_unswizzle(&encoding.decode(input)?)?));
+    }
+
+    Encoding::candidates(input)
+        .find_map(|(encoding, data)| try_unswizzle(&data).ok().map(|plain| (encoding, plain)))
+        .ok_or(SwizzleError::UnknownEncoding)
+}
+
+/// Reverses `swizzle`, rejecting input it can't have produced.
+pub fn try_unswizzle(input: &[u8]) -> Result<Vec<u8>, SwizzleError> {
+    check_pairs(input)?;
+    Ok(fast::unswizzle(input))
+}
+
+/// Reverses `swizzle_keyed`, rejecting input it can't have produced.
+pub fn try_unswizzle_keyed(input: &[u8], key: &[u8]) -> Result<Vec<u8>, SwizzleError> {
+    check_pairs(input)?;
+    Ok(unswizzle_keyed(input, key))
+}
+
+/// Checks that `input` is made of whole pairs, as every swizzled output is.
+fn check_pairs(input: &[u8]) -> Result<(), SwizzleError> {
+    match input.len() % 2 {
+        0 => Ok(()),
+        _ => Err(SwizzleError::BadLength { length: input.len() }),
+    }
+}
+
+/// Byte stream derived from a swizzle key.
+///
+/// Blocks of 32 bytes are `SHA-256(key || counter)`, with the counter starting at 0
+/// as a big endian `u64`.
+#[derive(Debug, Clone)]
+pub struct KeyStream {
+    key: Vec<u8>,
+    counter: u64,
+    block: [u8; 32],
+    position: usize,
+}
+
+impl KeyStream {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.to_vec(),
+            counter: 0,
+            block: [0; 32],
+            position: 32,
+        }
+    }
+}
+
+impl Iterator for KeyStream {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.position == self.block.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(&self.key);
+            hasher.update(self.counter.to_be_bytes());
+            self.block = hasher.finalize().into();
+            self.counter = self.counter.wrapping_add(1);
+            self.position = 0;
+        }
+
+        let byte = self.block[self.position];
+        self.position += 1;
+        Some(byte)
+    }
+}
+
+/// Swizzles `input` with a secret key.
+///
+/// Each pair consumes one byte `k` of the key stream: bit 6 swaps the two bytes
+/// first, bits 0-2 and 3-5 are added to the rotation amounts of the second and
+/// first byte. Odd inputs are padded like `swizzle`.
+pub fn swizzle_keyed(input: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+
+    // If input is odd length, we need to add a padding byte
+    if input.len() % 2 == 1 {
+        output.push(output[0]);
+    }
+
+    for (pair, k) in output.chunks_mut(2).zip(KeyStream::new(key)) {
+        if let [x, y] = pair {
+            swizzle_pair(x, y, k);
+        }
+    }
+
+    output
+}
+
+/// Reverses `swizzle_keyed` with the same key.
+pub fn unswizzle_keyed(input: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+    // If input is odd length, we need to add a padding byte
+    if input.len() % 2 == 1 {
+        output.push(output[0]);
+    }
+
+    for (pair, k) in output.chunks_mut(2).zip(KeyStream::new(key)) {
+        if let [x, y] = pair {
+            unswizzle_pair(x, y, k);
+        }
+    }
+
+    output
+}
+
+/// Swizzles a single pair with the key stream byte `k`. A zero `k` is the unkeyed
+/// scheme.
+fn swizzle_pair(x: &mut u8, y: &mut u8, k: u8) {
+    if k & 0x40 != 0 {
+        std::mem::swap(x, y);
+    }
+
+    let n1 = x.wrapping_add(k) % 8;
+
+    *y = y.rotate_right(n1.into());
+
+    let n2 = y.wrapping_add(k >> 3) % 8;
+
+    *x = x.rotate_right(n2.into());
+}
+
+/// Reverses `swizzle_pair`.
+fn unswizzle_pair(x: &mut u8, y: &mut u8, k: u8) {
+    let n2 = y.wrapping_add(k >> 3) % 8;
+
+    *x = x.rotate_left(n2.into());
+
+    let n1 = x.wrapping_add(k) % 8;
+
+    *y = y.rotate_left(n1.into());
+
+    if k & 0x40 != 0 {
+        std::mem::swap(x, y);
+    }
+}
+
+/// Magic bytes starting every swizzle container.
+pub const CONTAINER_MAGIC: &[u8; 4] = b"USWZ";
+
+/// Current version of the container format.
+pub const CONTAINER_VERSION: u8 = 1;
+
+/// Size of the container header, see `Header`.
+pub const HEADER_LEN: usize = 26;
+
+/// Scheme of the payload of a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// See `swizzle`
+    Plain,
+    /// See `swizzle_keyed`
+    Keyed,
+}
+
+/// Header of a swizzle container.
+///
+/// All integers are big endian:
+///
+/// ```text
+/// magic "USWZ" (4) | version (1) | variant (1) | key id (8) | length (8) | CRC-32 (4)
+/// ```
+///
+/// followed by the swizzled payload, padded to whole pairs. The key id is the start
+/// of the SHA-256 of the key, zero for the plain variant. The checksum is the CRC-32
+/// of the original data, which is `length` bytes long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub variant: Variant,
+    pub key_id: [u8; 8],
+    pub length: u64,
+    pub checksum: u32,
+}
+
+impl Header {
+    /// Reads the header at the start of `data`.
+    pub fn parse(data: &[u8]) -> Result<Self, SwizzleError> {
+        if !is_container(data) {
+            return Err(SwizzleError::BadMagic);
+        }
+        let Some(header) = data.get(..HEADER_LEN) else {
+            return Err(SwizzleError::BadLength { length: data.len() });
+        };
+
+        let version = header[4];
+        if version != CONTAINER_VERSION {
+            return Err(SwizzleError::UnsupportedVersion(version));
+        }
+        let variant = match header[5] {
+            0 => Variant::Plain,
+            1 => Variant::Keyed,
+            variant => return Err(SwizzleError::UnsupportedVariant(variant)),
+        };
+
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&header[6..14]);
+        let mut length = [0; 8];
+        length.copy_from_slice(&header[14..22]);
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&header[22..26]);
+
+        Ok(Self {
+            version,
+            variant,
+            key_id,
+            length: u64::from_be_bytes(length),
+            checksum: u32::from_be_bytes(checksum),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(CONTAINER_MAGIC);
+        header[4] = self.version;
+        header[5] = match self.variant {
+            Variant::Plain => 0,
+            Variant::Keyed => 1,
+        };
+        header[6..14].copy_from_slice(&self.key_id);
+        header[14..22].copy_from_slice(&self.length.to_be_bytes());
+        header[22..26].copy_from_slice(&self.checksum.to_be_bytes());
+        header
+    }
+}
+
+/// Returns the id of `key` stored in containers, to tell keys apart without
+/// revealing them.
+pub fn key_id(key: &[u8]) -> [u8; 8] {
+    let digest = Sha256::digest(key);
+    let mut id = [0; 8];
+    id.copy_from_slice(&digest[..8]);
+    id
+}
+
+/// Returns `true` if `data` starts like a swizzle container.
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(CONTAINER_MAGIC)
+}
+
+/// Swizzles `input` into a container recording its length, variant and checksum, so
+/// that `unseal` returns exactly `input`.
+///
+/// # Parameters
+/// - `input`: The data to swizzle.
+/// - `key`: Secret of the keyed variant, or `None` for the plain one.
+pub fn seal(input: &[u8], key: Option<&[u8]>) -> Vec<u8> {
+    let header = Header {
+        version: CONTAINER_VERSION,
+        variant: if key.is_some() { Variant::Keyed } else { Variant::Plain },
+        key_id: key.map(key_id).unwrap_or_default(),
+        length: input.len() as u64,
+        checksum: crc32fast::hash(input),
+    };
+
+    let mut output = header.to_bytes().to_vec();
+    match key {
+        Some(key) => output.extend(swizzle_keyed(input, key)),
+        None => output.extend(swizzle(input)),
+    }
+    output
+}
+
+/// Reverses `seal`, checking the length, key and checksum.
+///
+/// # Parameters
+/// - `container`: A container written by `seal`.
+/// - `key`: Secret of the keyed variant. Ignored for plain containers.
+pub fn unseal(container: &[u8], key: Option<&[u8]>) -> Result<Vec<u8>, SwizzleError> {
+    let header = Header::parse(container)?;
+    let payload = &container[HEADER_LEN..];
+
+    // The payload is the data padded to whole pairs
+    let length = usize::try_from(header.length).map_err(|_| SwizzleError::BadLength { length: payload.len() })?;
+    if length.checked_add(length % 2) != Some(payload.len()) {
+        return Err(SwizzleError::BadLength { length: payload.len() });
+    }
+
+    let mut output = match header.variant {
+        Variant::Plain => unswizzle(payload),
+        Variant::Keyed => {
+            let key = key.ok_or(SwizzleError::KeyRequired)?;
+            if key_id(key) != header.key_id {
+                return Err(SwizzleError::WrongKey);
+            }
+            unswizzle_keyed(payload, key)
+        }
+    };
+    output.truncate(length);
+
+    let actual = crc32fast::hash(&output);
+    if actual != header.checksum {
+        return Err(SwizzleError::ChecksumMismatch {
+            expected: header.checksum,
+            actual,
+        });
+    }
+    Ok(output)
+}
+
+/// Pairs up a stream of bytes split at arbitrary positions, and swizzles or
+/// unswizzles them.
+#[derive(Debug, Clone)]
+struct Pairs {
+    keys: Option<KeyStream>,
+    unswizzle: bool,
+    /// First byte of the stream, used to pad odd lengths
+    first: Option<u8>,
+    /// Byte waiting for the other half of its pair
+    pending: Option<u8>,
+}
+
+impl Pairs {
+    fn new(key: Option<&[u8]>, unswizzle: bool) -> Self {
+        Self {
+            keys: key.map(KeyStream::new),
+            unswizzle,
+            first: None,
+            pending: None,
+        }
+    }
+
+    /// Appends the processed complete pairs of `data` to `output`.
+    fn push(&mut self, mut data: &[u8], output: &mut Vec<u8>) {
+        if self.first.is_none() {
+            self.first = data.first().copied();
+        }
+
+        if let Some(x) = self.pending
+            && let Some((&y, rest)) = data.split_first()
+        {
+            self.pending = None;
+            self.pair(x, y, output);
+            data = rest;
+        }
+
+        let pairs = data.chunks_exact(2);
+        self.pending = pairs.remainder().first().copied();
+        for pair in pairs {
+            self.pair(pair[0], pair[1], output);
+        }
+    }
+
+    /// Appends what's left at the end of the stream to `output`.
+    ///
+    /// # Parameters
+    /// - `pad`: Whether a lone last byte is paired with the first byte of the stream,
+    ///   like `swizzle` does, or passed through unchanged, like `base64_unswizzle`.
+    fn finish(&mut self, pad: bool, output: &mut Vec<u8>) {
+        let Some(x) = self.pending.take() else {
+            return;
+        };
+
+        match self.first {
+            Some(first) if pad => self.pair(x, first, output),
+            _ => output.push(x),
+        }
+    }
+
+    fn pair(&mut self, mut x: u8, mut y: u8, output: &mut Vec<u8>) {
+        let k = self.keys.as_mut().and_then(Iterator::next).unwrap_or(0);
+        if self.unswizzle {
+            unswizzle_pair(&mut x, &mut y, k);
+        } else {
+            swizzle_pair(&mut x, &mut y, k);
+        }
+        output.extend([x, y]);
+    }
+}
+
+/// Swizzles everything written to it into `W`, like `swizzle` or `swizzle_keyed`.
+///
+/// Writes may split pairs anywhere. The last byte of an odd length stream is only
+/// written by `finish`.
+#[derive(Debug)]
+pub struct SwizzleWriter<W: Write> {
+    inner: W,
+    pairs: Pairs,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> SwizzleWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pairs: Pairs::new(None, false),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn keyed(inner: W, key: &[u8]) -> Self {
+        Self {
+            inner,
+            pairs: Pairs::new(Some(key), false),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Writes the padded last pair, if any, and flushes.
+    ///
+    /// # Returns
+    /// The inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.buffer.clear();
+        self.pairs.finish(true, &mut self.buffer);
+        self.inner.write_all(&self.buffer)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SwizzleWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.clear();
+        self.pairs.push(buf, &mut self.buffer);
+        self.inner.write_all(&self.buffer)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Unswizzles the data read from `R`, like `unswizzle` or `unswizzle_keyed`.
+#[derive(Debug)]
+pub struct UnswizzleReader<R: Read> {
+    inner: R,
+    pairs: Pairs,
+    /// Whether a lone last byte is paired with the first one, see `Pairs::finish`
+    pad: bool,
+    buffer: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> UnswizzleReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_pairs(inner, Pairs::new(None, true), true)
+    }
+
+    pub fn keyed(inner: R, key: &[u8]) -> Self {
+        Self::with_pairs(inner, Pairs::new(Some(key), true), true)
+    }
+
+    fn with_pairs(inner: R, pairs: Pairs, pad: bool) -> Self {
+        Self {
+            inner,
+            pairs,
+            pad,
+            buffer: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for UnswizzleReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0u8; 8192];
+        while self.position == self.buffer.len() && !self.done {
+            self.buffer.clear();
+            self.position = 0;
+
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    self.pairs.finish(self.pad, &mut self.buffer);
+                    self.done = true;
+                }
+                Ok(n) => self.pairs.push(&chunk[..n], &mut self.buffer),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Swizzles everything written to it and writes it as base64 into `W`, like
+/// `base64_swizzle`.
+#[derive(Debug)]
+pub struct Base64SwizzleWriter<W: Write> {
+    inner: SwizzleWriter<EncoderWriter<'static, GeneralPurpose, W>>,
+}
+
+impl<W: Write> Base64SwizzleWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: SwizzleWriter::new(EncoderWriter::new(inner, &BASE64_STANDARD)),
+        }
+    }
+
+    pub fn keyed(inner: W, key: &[u8]) -> Self {
+        Self {
+            inner: SwizzleWriter::keyed(EncoderWriter::new(inner, &BASE64_STANDARD), key),
+        }
+    }
+
+    /// Writes the padded last pair and the end of the base64, and flushes.
+    ///
+    /// # Returns
+    /// The inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()?.finish()
+    }
+}
+
+impl<W: Write> Write for Base64SwizzleWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes base64 read from `R` and unswizzles it, like `base64_unswizzle`.
+#[derive(Debug)]
+pub struct Base64UnswizzleReader<R: Read> {
+    inner: UnswizzleReader<DecoderReader<'static, GeneralPurpose, R>>,
+}
+
+impl<R: Read> Base64UnswizzleReader<R> {
+    pub fn new(inner: R) -> Self {
+        let pairs = Pairs::new(None, true);
+        Self {
+            inner: UnswizzleReader::with_pairs(DecoderReader::new(inner, &BASE64_STANDARD), pairs, false),
+        }
+    }
+
+    pub fn keyed(inner: R, key: &[u8]) -> Self {
+        let pairs = Pairs::new(Some(key), true);
+        Self {
+            inner: UnswizzleReader::with_pairs(DecoderReader::new(inner, &BASE64_STANDARD), pairs, false),
+        }
+    }
+}
+
+impl<R: Read> Read for Base64UnswizzleReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    #[allow(clippy::manual_rotate)]
+    fn test_swizzle() {
+        let x0: u8 = 0b0001_0101;
+        let y0 : u8 = 0b1011_1100;
+
+        let mut x: u8 = 0b0001_0101;
+        let mut y: u8 = 0b1011_1100;
+
+        // swizzle
+        {
+            let n1 = x % 8;
+
+            y = (y >> n1) | (y << (8 - n1));
+
+            let n2 = y % 8;
+
+            x = (x >> n2) | (x << (8 - n2));
+        }
+        
+        // unswizzle
+        {
+            let n1 = y % 8;
+
+            x = (x << n1) | (x >> (8 - n1));
+
+            let n2 = x % 8;
+
+            y = (y << n2) | (y >> (8 - n2));
+        }
+
+        assert_eq!(x, x0);
+        assert_eq!(y, y0);
+
+        let data = vec![0x01u8, 0x02u8, 0x03u8, 0x04u8, 0x05u8, 0x06u8, 0x07u8, 0x08u8];
+        
+        let swizzled = swizzle(&data);
+        let unswizzled = unswizzle(&swizzled);
+
+        assert_eq!(data, unswizzled);
+    }
+
+    #[test]
+    fn test_base64_swizzle() {
+        let data = "A quick brown fox jumps over the lazy dog!";
+        let swizzled = base64_swizzle(data.as_bytes());
+        let unswizzled = base64_unswizzle(&swizzled);
+        assert_eq!(data.as_bytes(), unswizzled.as_slice());
+        assert_eq!(data.len(), unswizzled.len());
+        assert_eq!(data, String::from_utf8(unswizzled.clone()).unwrap());
+
+        let udata = BASE64_STANDARD.decode(&swizzled).unwrap();
+        assert_ne!(data.as_bytes(), udata.as_slice());
+        eprintln!("{:?}\n", udata.as_slice().hex_dump());
+        eprintln!("{:?}\n", unswizzled.as_slice().hex_dump());
+    }
+
+    #[test]
+    fn test_keyed_swizzle() {
+        // The unkeyed functions must keep producing the same bytes
+        assert_eq!(swizzle(&[1, 2, 3, 4, 5, 6, 7, 8]), [0x80, 0x01, 0x03, 0x80, 0x05, 0x30, 0x07, 0x10]);
+        assert_eq!(
+            swizzle(b"udata swizzle"),
+            [0xae, 0x23, 0x58, 0x3a, 0x61, 0x10, 0xcd, 0xee, 0x4b, 0x3d, 0x4f, 0x1b, 0xac, 0xab]
+        );
+
+        assert_eq!(KeyStream::new(b"secret").take(8).collect::<Vec<_>>(), [0x01, 0x48, 0x07, 0x40, 0x18, 0x38, 0x66, 0x6c]);
+
+        let vectors: [(&[u8], &[u8], &[u8]); 2] = [
+            (
+                b"secret",
+                b"udata swizzle",
+                &[0xba, 0x91, 0xe8, 0x16, 0x61, 0x20, 0xdd, 0xe6, 0x69, 0x3d, 0x9e, 0x1b, 0x75, 0xac],
+            ),
+            (
+                b"partner-key",
+                br#"{"id":42}"#,
+                &[0x88, 0xdb, 0x32, 0x4b, 0x47, 0x44, 0x0d, 0x91, 0xbe, 0x7b],
+            ),
+        ];
+        for (key, plain, swizzled) in vectors {
+            assert_eq!(swizzle_keyed(plain, key), swizzled);
+            // Odd inputs keep their padding byte, like `unswizzle`
+            assert_eq!(&unswizzle_keyed(swizzled, key)[..plain.len()], plain);
+            assert_ne!(&unswizzle_keyed(swizzled, b"wrong")[..plain.len()], plain);
+        }
+    }
+
+    #[test]
+    fn test_streams() {
+        let data = (0..1001u32).map(|i| (i * 7 + i / 3) as u8).collect::<Vec<_>>();
+
+        for size in [1, 2, 3, 7, 64, 4096] {
+            for data in [&data[..], &data[..1000]] {
+                let mut writer = SwizzleWriter::new(Vec::new());
+                for chunk in data.chunks(size) {
+                    writer.write_all(chunk).unwrap();
+                }
+                let swizzled = writer.finish().unwrap();
+                assert_eq!(swizzled, swizzle(data));
+
+                let mut unswizzled = Vec::new();
+                UnswizzleReader::new(ChunkedReader(&swizzled, size)).read_to_end(&mut unswizzled).unwrap();
+                assert_eq!(unswizzled, unswizzle(&swizzled));
+
+                let mut writer = SwizzleWriter::keyed(Vec::new(), b"secret");
+                for chunk in data.chunks(size) {
+                    writer.write_all(chunk).unwrap();
+                }
+                let swizzled = writer.finish().unwrap();
+                assert_eq!(swizzled, swizzle_keyed(data, b"secret"));
+
+                let mut unswizzled = Vec::new();
+                UnswizzleReader::keyed(ChunkedReader(&swizzled, size), b"secret").read_to_end(&mut unswizzled).unwrap();
+                assert_eq!(unswizzled, unswizzle_keyed(&swizzled, b"secret"));
+
+                let mut writer = Base64SwizzleWriter::new(Vec::new());
+                for chunk in data.chunks(size) {
+                    writer.write_all(chunk).unwrap();
+                }
+                let encoded = String::from_utf8(writer.finish().unwrap()).unwrap();
+                assert_eq!(encoded, base64_swizzle(data));
+
+                let mut unswizzled = Vec::new();
+                Base64UnswizzleReader::new(ChunkedReader(encoded.as_bytes(), size)).read_to_end(&mut unswizzled).unwrap();
+                assert_eq!(unswizzled, base64_unswizzle(&encoded));
+            }
+        }
+    }
+
+    /// Returns at most `.1` bytes per read.
+    struct ChunkedReader<'a>(&'a [u8], usize);
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.1).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(try_base64_unswizzle("not base64!"), Err(SwizzleError::InvalidBase64(_))));
+        assert_eq!(try_base64_unswizzle("AAA=").unwrap(), [0, 0]);
+        assert_eq!(try_base64_unswizzle("AA=="), Err(SwizzleError::BadLength { length: 1 }));
+        assert_eq!(try_unswizzle(&[1, 2, 3]), Err(SwizzleError::BadLength { length: 3 }));
+        assert_eq!(try_unswizzle_keyed(&swizzle_keyed(b"ab", b"k"), b"k").unwrap(), b"ab");
+        assert_eq!(try_base64_unswizzle(&base64_swizzle(b"data")).unwrap(), b"data");
+
+        // The convenience wrappers don't panic either
+        assert!(base64_unswizzle("not base64!").is_empty());
+        assert!(base64_unswizzle("").is_empty());
+        assert!(unswizzle(&[]).is_empty());
+        assert_eq!(unswizzle(&[7]).len(), 2);
+
+        let e = io::Error::from(SwizzleError::BadLength { length: 3 });
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_container() {
+        for data in [&b""[..], b"a", b"ab", b"odd length", b"even length!"] {
+            for key in [None, Some(&b"secret"[..])] {
+                let container = seal(data, key);
+                assert!(is_container(&container));
+                assert_eq!(container.len(), HEADER_LEN + data.len() + data.len() % 2);
+                assert_eq!(unseal(&container, key).unwrap(), data);
+            }
+        }
+
+        let container = seal(b"odd length", Some(b"secret"));
+        let header = Header::parse(&container).unwrap();
+        assert_eq!(header.variant, Variant::Keyed);
+        assert_eq!(header.length, 10);
+        assert_eq!(header.key_id, key_id(b"secret"));
+        assert_eq!(unseal(&container, None), Err(SwizzleError::KeyRequired));
+        assert_eq!(unseal(&container, Some(b"other")), Err(SwizzleError::WrongKey));
+
+        let mut corrupt = container.clone();
+        corrupt[HEADER_LEN] ^= 1;
+        assert!(matches!(unseal(&corrupt, Some(b"secret")), Err(SwizzleError::ChecksumMismatch { .. })));
+        assert!(matches!(unseal(&container[..container.len() - 2], Some(b"secret")), Err(SwizzleError::BadLength { .. })));
+
+        let mut newer = container.clone();
+        newer[4] = 2;
+        assert_eq!(unseal(&newer, Some(b"secret")), Err(SwizzleError::UnsupportedVersion(2)));
+        let mut variant = container.clone();
+        variant[5] = 7;
+        assert_eq!(unseal(&variant, Some(b"secret")), Err(SwizzleError::UnsupportedVariant(7)));
+        assert_eq!(unseal(b"USW", None), Err(SwizzleError::BadMagic));
+        assert_eq!(unseal(b"USWZ", None), Err(SwizzleError::BadLength { length: 4 }));
+        assert_eq!(unseal(&swizzle(b"data"), None), Err(SwizzleError::BadMagic));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let data = b"{\"id\":42,\"name\":\"udata\"}";
+
+        for encoding in Encoding::ALL {
+            assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+
+            let text = encoded_swizzle(data, encoding);
+            assert_eq!(encoded_unswizzle(&text, Some(encoding)).unwrap(), (encoding, data.to_vec()));
+            assert_eq!(encoded_unswizzle(&text, None).unwrap().1, data);
+        }
+
+        assert_eq!(encoded_swizzle(data, Encoding::Base64), base64_swizzle(data));
+        assert_eq!(Encoding::detect("0aff"), Some(Encoding::Hex));
+        assert_eq!(Encoding::detect("0AFF\n"), Some(Encoding::Hex));
+        assert_eq!(Encoding::detect("MZXW6==="), Some(Encoding::Base32));
+        assert_eq!(Encoding::detect("MZXW6"), Some(Encoding::Base32));
+        assert_eq!(Encoding::detect("+/8="), Some(Encoding::Base64));
+        assert_eq!(Encoding::detect("+/8"), Some(Encoding::Base64NoPad));
+        assert_eq!(Encoding::detect("-_8="), Some(Encoding::Base64Url));
+        assert_eq!(Encoding::detect("-_8"), Some(Encoding::Base64UrlNoPad));
+        assert_eq!(Encoding::detect("not encoded!"), None);
+        assert_eq!(Encoding::detect(""), None);
+        assert_eq!(encoded_unswizzle("not encoded!", None), Err(SwizzleError::UnknownEncoding));
+    }
+
+    /// Expected result of an unpadded round trip: odd inputs gain a copy of their
+    /// first byte.
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        if data.len() % 2 == 1 {
+            padded.push(data[0]);
+        }
+        padded
+    }
+
+    fn write_chunks<W: Write>(mut writer: W, data: &[u8], size: usize) -> W {
+        for chunk in data.chunks(size) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer
+    }
+
+    fn read_all(mut reader: impl Read) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).map(|_| output)
+    }
+
+    fn encoding() -> impl Strategy<Value = Encoding> {
+        prop::sample::select(Encoding::ALL.to_vec())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_swizzle(data in vec(any::<u8>(), 0..512)) {
+            let swizzled = swizzle(&data);
+            prop_assert_eq!(swizzled.len(), data.len() + data.len() % 2);
+            prop_assert_eq!(unswizzle(&swizzled), padded(&data));
+            prop_assert_eq!(try_unswizzle(&swizzled).unwrap(), padded(&data));
+
+            // Pairs map one to one, so unswizzling first works too
+            let even = &data[..data.len() - data.len() % 2];
+            prop_assert_eq!(swizzle(&unswizzle(even)), even);
+        }
+
+        #[test]
+        fn prop_fast(data in vec(any::<u8>(), 0..512)) {
+            prop_assert_eq!(fast::swizzle(&data), swizzle(&data));
+            prop_assert_eq!(fast::unswizzle(&data), unswizzle(&data));
+
+            let mut in_place = data.clone();
+            fast::swizzle_pairs(&mut in_place);
+            fast::unswizzle_pairs(&mut in_place);
+            prop_assert_eq!(&in_place, &data);
+
+            #[cfg(feature = "parallel")]
+            {
+                fast::swizzle_parallel(&mut in_place);
+                fast::unswizzle_parallel(&mut in_place);
+                prop_assert_eq!(&in_place, &data);
+            }
+        }
+
+        #[test]
+        fn prop_keyed(
+            data in vec(any::<u8>(), 0..512),
+            key in vec(any::<u8>(), 0..40),
+        ) {
+            let swizzled = swizzle_keyed(&data, &key);
+            prop_assert_eq!(unswizzle_keyed(&swizzled, &key), padded(&data));
+            prop_assert_eq!(try_unswizzle_keyed(&swizzled, &key).unwrap(), padded(&data));
+        }
+
+        #[test]
+        fn prop_base64(data in vec(any::<u8>(), 0..512)) {
+            let encoded = base64_swizzle(&data);
+            prop_assert_eq!(base64_unswizzle(&encoded), padded(&data));
+            prop_assert_eq!(try_base64_unswizzle(&encoded).unwrap(), padded(&data));
+        }
+
+        #[test]
+        fn prop_encodings(data in vec(any::<u8>(), 0..256), encoding in encoding()) {
+            let text = encoded_swizzle(&data, encoding);
+            prop_assert_eq!(encoding.decode(&text).unwrap(), swizzle(&data));
+            prop_assert_eq!(encoded_unswizzle(&text, Some(encoding)).unwrap(), (encoding, padded(&data)));
+
+            // Short texts can be valid in several encodings, detection must still pick
+            // one that decodes
+            match encoded_unswizzle(&text, None) {
+                Ok((detected, plain)) => {
+                    prop_assert_eq!(plain, try_unswizzle(&detected.decode(&text).unwrap()).unwrap());
+                    // Hex comes first and is never mistaken for another encoding
+                    if encoding == Encoding::Hex {
+                        prop_assert_eq!(detected, Encoding::Hex);
+                    }
+                }
+                Err(e) => {
+                    prop_assert!(data.is_empty(), "{}", e);
+                }
+            }
+        }
+
+        #[test]
+        fn prop_container(
+            data in vec(any::<u8>(), 0..512),
+            key in prop::option::of(vec(any::<u8>(), 0..40)),
+        ) {
+            let container = seal(&data, key.as_deref());
+            prop_assert!(is_container(&container));
+            prop_assert_eq!(unseal(&container, key.as_deref()).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_streams(
+            data in vec(any::<u8>(), 0..512),
+            key in prop::option::of(vec(any::<u8>(), 0..40)),
+            size in 1usize..64,
+        ) {
+            let unswizzled = match key.as_deref() {
+                Some(key) => {
+                    let swizzled = write_chunks(SwizzleWriter::keyed(Vec::new(), key), &data, size).finish().unwrap();
+                    prop_assert_eq!(&swizzled, &swizzle_keyed(&data, key));
+                    read_all(UnswizzleReader::keyed(ChunkedReader(&swizzled, size), key)).unwrap()
+                }
+                None => {
+                    let swizzled = write_chunks(SwizzleWriter::new(Vec::new()), &data, size).finish().unwrap();
+                    prop_assert_eq!(&swizzled, &swizzle(&data));
+                    read_all(UnswizzleReader::new(ChunkedReader(&swizzled, size))).unwrap()
+                }
+            };
+            prop_assert_eq!(unswizzled, padded(&data));
+
+            let encoded = match key.as_deref() {
+                Some(key) => write_chunks(Base64SwizzleWriter::keyed(Vec::new(), key), &data, size).finish().unwrap(),
+                None => write_chunks(Base64SwizzleWriter::new(Vec::new()), &data, size).finish().unwrap(),
+            };
+            let unswizzled = match key.as_deref() {
+                Some(key) => read_all(Base64UnswizzleReader::keyed(ChunkedReader(&encoded, size), key)).unwrap(),
+                None => read_all(Base64UnswizzleReader::new(ChunkedReader(&encoded, size))).unwrap(),
+            };
+            prop_assert_eq!(unswizzled, padded(&data));
+        }
+
+        /// Decoders reject bad input with an error, never a panic.
+        #[test]
+        fn prop_decoders_dont_panic(data in vec(any::<u8>(), 0..512), key in vec(any::<u8>(), 0..8)) {
+            let text = String::from_utf8_lossy(&data);
+
+            let _ = unswizzle(&data);
+            let _ = unswizzle_keyed(&data, &key);
+            let _ = try_unswizzle(&data);
+            let _ = try_unswizzle_keyed(&data, &key);
+            let _ = base64_unswizzle(&text);
+            let _ = try_base64_unswizzle(&text);
+            let _ = encoded_unswizzle(&text, None);
+            for encoding in Encoding::ALL {
+                let _ = encoded_unswizzle(&text, Some(encoding));
+            }
+            let _ = Header::parse(&data);
+            let _ = unseal(&data, None);
+            let _ = unseal(&data, Some(&key));
+            let _ = read_all(UnswizzleReader::keyed(&data[..], &key));
+            let _ = read_all(Base64UnswizzleReader::new(&data[..]));
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,17 @@
+//! Parses and opens arbitrary bytes as swizzle containers.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udata_swizzle::swizzle;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = swizzle::Header::parse(data);
+    let _ = swizzle::is_container(data);
+
+    for key in [None, Some(&b"secret"[..])] {
+        if let Ok(plain) = swizzle::unseal(data, key) {
+            // Only a well-formed container opens, and it holds the recorded length
+            assert_eq!(swizzle::Header::parse(data).unwrap().length, plain.len() as u64);
+        }
+    }
+});
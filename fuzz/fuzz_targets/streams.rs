@@ -0,0 +1,22 @@
+//! Reads arbitrary bytes through the unswizzling readers and checks they agree with
+//! the buffer functions.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Read;
+use udata_swizzle::swizzle::{self, Base64UnswizzleReader, UnswizzleReader};
+
+fuzz_target!(|data: &[u8]| {
+    let mut plain = Vec::new();
+    UnswizzleReader::new(data).read_to_end(&mut plain).unwrap();
+    assert_eq!(plain, swizzle::unswizzle(data));
+
+    let mut plain = Vec::new();
+    UnswizzleReader::keyed(data, b"secret").read_to_end(&mut plain).unwrap();
+    assert_eq!(plain, swizzle::unswizzle_keyed(data, b"secret"));
+
+    // Invalid base64 is an error, not a panic
+    let mut plain = Vec::new();
+    let _ = Base64UnswizzleReader::new(data).read_to_end(&mut plain);
+    let _ = Base64UnswizzleReader::keyed(data, b"secret").read_to_end(&mut plain);
+});
@@ -0,0 +1,18 @@
+//! Decodes arbitrary text in every encoding, and with detection.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udata_swizzle::swizzle::{self, Encoding};
+
+fuzz_target!(|text: &str| {
+    let _ = swizzle::base64_unswizzle(text);
+    let _ = swizzle::try_base64_unswizzle(text);
+
+    for encoding in Encoding::ALL {
+        let _ = swizzle::encoded_unswizzle(text, Some(encoding));
+    }
+
+    if let Ok((encoding, plain)) = swizzle::encoded_unswizzle(text, None) {
+        assert_eq!(swizzle::encoded_unswizzle(text, Some(encoding)).unwrap().1, plain);
+    }
+});
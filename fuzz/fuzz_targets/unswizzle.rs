@@ -0,0 +1,27 @@
+//! Unswizzles arbitrary bytes, keyed and unkeyed, and checks the fast path agrees
+//! with the reference implementation.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udata_swizzle::swizzle::{self, fast};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks how much of the rest is the key
+    let (key, data) = match data.split_first() {
+        Some((&length, rest)) => rest.split_at((length as usize % 33).min(rest.len())),
+        None => (&[][..], data),
+    };
+
+    let plain = swizzle::unswizzle(data);
+    assert_eq!(fast::unswizzle(data), plain);
+    assert_eq!(swizzle::try_unswizzle(data).is_ok(), data.len() % 2 == 0);
+
+    let keyed = swizzle::unswizzle_keyed(data, key);
+    assert_eq!(swizzle::try_unswizzle_keyed(data, key).is_ok(), data.len() % 2 == 0);
+
+    // Whole pairs map one to one
+    if data.len() % 2 == 0 {
+        assert_eq!(swizzle::swizzle(&plain), data);
+        assert_eq!(swizzle::swizzle_keyed(&keyed, key), data);
+    }
+});
@@ -7,12 +7,12 @@
 use std::sync::{Arc, Mutex};
 
 use cef::{
-    App, BrowserProcessHandler, ImplApp, Window,
+    App, BrowserProcessHandler, ImplApp, RenderProcessHandler, Window,
     rc::RcImpl,
     sys,
 };
 
-use crate::{config::Config, process::DemoBrowserProcessHandler};
+use crate::{binding::DemoRenderProcessHandler, config::Config, process::DemoBrowserProcessHandler, session::Session};
 
 /// Main CEF application implementation.
 ///
@@ -22,10 +22,12 @@ use crate::{config::Config, process::DemoBrowserProcessHandler};
 /// # Fields
 /// * `object` - The raw CEF object pointer for reference counting
 /// * `window` - A thread-safe reference to the application's main window
+/// * `session` - Capture state shared by every request, only set in the browser process
 pub struct DemoApp {
     pub object: *mut RcImpl<sys::_cef_app_t, Self>,
     pub window: Arc<Mutex<Option<Window>>>,
     pub config: Option<Config>,
+    pub session: Arc<Mutex<Option<Arc<Session>>>>,
 }
 
 impl DemoApp {
@@ -33,14 +35,17 @@ impl DemoApp {
     ///
     /// # Arguments
     /// * `window` - A thread-safe reference to the application's main window (initially None)
+    /// * `session` - Capture state shared by every request (initially None, set once
+    ///   `execute_process` has returned in the browser process)
     ///
     /// # Returns
     /// A new `App` instance wrapping the `DemoApp` implementation
-    pub fn new(window: Arc<Mutex<Option<Window>>>, config: Option<Config>) -> App {
+    pub fn new(window: Arc<Mutex<Option<Window>>>, config: Option<Config>, session: Arc<Mutex<Option<Arc<Session>>>>) -> App {
         App::new(Self {
             object: std::ptr::null_mut(),
             window,
             config,
+            session,
         })
     }
 }
@@ -60,8 +65,22 @@ impl ImplApp for DemoApp {
     /// that will handle browser process-specific callbacks.
     ///
     /// # Returns
-    /// An instance of `DemoBrowserProcessHandler` wrapped in `BrowserProcessHandler`
+    /// An instance of `DemoBrowserProcessHandler` wrapped in `BrowserProcessHandler`, or
+    /// None before the session is created
     fn get_browser_process_handler(&self) -> Option<BrowserProcessHandler> {
-        Some(DemoBrowserProcessHandler::new(self.window.clone(), self.config.clone()))
+        let session = self.session.lock().ok()?.clone()?;
+        Some(DemoBrowserProcessHandler::new(self.window.clone(), self.config.clone(), session))
+    }
+
+    /// Provides the render process handler for this application.
+    ///
+    /// This method is called by the CEF framework in each render process. The handler
+    /// exposes `window.udata` to pages when `js_binding` is set.
+    ///
+    /// # Returns
+    /// An instance of `DemoRenderProcessHandler` wrapped in `RenderProcessHandler`
+    fn get_render_process_handler(&self) -> Option<RenderProcessHandler> {
+        let enabled = self.config.as_ref().is_some_and(|config| config.js_binding);
+        Some(DemoRenderProcessHandler::new(enabled))
     }
 }
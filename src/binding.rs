@@ -0,0 +1,350 @@
+#![allow(clippy::new_ret_no_self)]
+//! JavaScript binding of the swizzle functions, for debugging target sites.
+//!
+//! With `js_binding` set in the configuration, the render process handler adds a
+//! `window.udata` object to every page, so the codec can be called from the devtools
+//! console:
+//!
+//! ```text
+//! udata.swizzle(data, key?)        // string: UTF-8 text, returns base64
+//!                                  // ArrayBuffer: returns an ArrayBuffer
+//! udata.unswizzle(data, key?)      // string: encoded text, in any of the
+//!                                  // `swizzle::Encoding`s, returns text
+//!                                  // ArrayBuffer: returns an ArrayBuffer
+//! udata.unswizzleBytes(data, key?) // same as `unswizzle`, always returns an ArrayBuffer
+//! ```
+//!
+//! The optional key, a string or an ArrayBuffer, selects the keyed variant.
+//! Swizzle containers are recognised and opened by the unswizzle functions. Errors
+//! are thrown as JavaScript exceptions.
+//!
+//! The calls themselves are plain Rust, see `call`; the handlers only convert between
+//! V8 values and `JsValue`.
+
+use std::ffi::c_int;
+
+use cef::rc::RcImpl;
+use cef::{
+    CefString, ImplBrowser, ImplFrame, ImplRenderProcessHandler, ImplV8ArrayBufferReleaseCallback, ImplV8Context,
+    ImplV8Handler, ImplV8Value, RenderProcessHandler, V8ArrayBufferReleaseCallback, V8Handler, V8Value, sys,
+    v8_value_create_array_buffer, v8_value_create_function, v8_value_create_object, v8_value_create_string,
+};
+use udata_swizzle::swizzle::{self, Encoding, SwizzleError};
+
+/// Name of the global object.
+pub const OBJECT: &str = "udata";
+
+/// Functions of the global object.
+pub const FUNCTIONS: [&str; 3] = ["swizzle", "unswizzle", "unswizzleBytes"];
+
+/// A JavaScript argument or result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsValue {
+    Text(String),
+    /// Content of an `ArrayBuffer`
+    Bytes(Vec<u8>),
+}
+
+impl JsValue {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            JsValue::Text(text) => text.as_bytes(),
+            JsValue::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+/// Runs one of the `FUNCTIONS`.
+///
+/// # Parameters
+/// - `name`: Name of the function.
+/// - `arguments`: The data, then the optional key. `None` stands for `undefined` and
+///   `null`.
+///
+/// # Returns
+/// The result, or the message of the exception to throw.
+pub fn call(name: &str, arguments: &[Option<JsValue>]) -> Result<JsValue, String> {
+    let Some(Some(data)) = arguments.first() else {
+        return Err(format!("{}.{}: expected a string or an ArrayBuffer", OBJECT, name));
+    };
+    let key = arguments.get(1).and_then(Option::as_ref).map(JsValue::as_bytes);
+
+    let result = match (name, data) {
+        ("swizzle", JsValue::Text(text)) => Ok(JsValue::Text(Encoding::Base64.encode(&swizzle_with(text.as_bytes(), key)))),
+        ("swizzle", JsValue::Bytes(bytes)) => Ok(JsValue::Bytes(swizzle_with(bytes, key))),
+        ("unswizzle", JsValue::Text(text)) => {
+            unswizzle_text(text, key).map(|plain| JsValue::Text(String::from_utf8_lossy(&plain).into_owned()))
+        }
+        ("unswizzle" | "unswizzleBytes", JsValue::Bytes(bytes)) => unswizzle_with(bytes, key).map(JsValue::Bytes),
+        ("unswizzleBytes", JsValue::Text(text)) => unswizzle_text(text, key).map(JsValue::Bytes),
+        _ => return Err(format!("{}.{} is not a function", OBJECT, name)),
+    };
+    result.map_err(|e| format!("{}.{}: {}", OBJECT, name, e))
+}
+
+fn swizzle_with(data: &[u8], key: Option<&[u8]>) -> Vec<u8> {
+    match key {
+        Some(key) => swizzle::swizzle_keyed(data, key),
+        None => swizzle::fast::swizzle(data),
+    }
+}
+
+fn unswizzle_with(data: &[u8], key: Option<&[u8]>) -> Result<Vec<u8>, SwizzleError> {
+    if swizzle::is_container(data) {
+        return swizzle::unseal(data, key);
+    }
+
+    match key {
+        Some(key) => swizzle::try_unswizzle_keyed(data, key),
+        None => swizzle::try_unswizzle(data),
+    }
+}
+
+/// Decodes text in whichever encoding it's in, then unswizzles it.
+fn unswizzle_text(text: &str, key: Option<&[u8]>) -> Result<Vec<u8>, SwizzleError> {
+    Encoding::candidates(text)
+        .find_map(|(_, data)| unswizzle_with(&data, key).ok())
+        .ok_or(SwizzleError::UnknownEncoding)
+}
+
+//
+// Render process
+//
+
+/// Handler for render process events.
+///
+/// `DemoRenderProcessHandler` adds the `window.udata` object to each new JavaScript
+/// context when the binding is enabled.
+///
+/// # Fields
+/// * `object` - The raw CEF object pointer for reference counting
+/// * `enabled` - Whether `js_binding` is set in the configuration
+pub struct DemoRenderProcessHandler {
+    pub object: *mut RcImpl<sys::_cef_render_process_handler_t, Self>,
+    pub enabled: bool,
+}
+
+impl DemoRenderProcessHandler {
+    /// Creates a new render process handler instance.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to expose `window.udata` to pages
+    ///
+    /// # Returns
+    /// A new `RenderProcessHandler` instance wrapping the `DemoRenderProcessHandler` implementation
+    pub fn new(enabled: bool) -> RenderProcessHandler {
+        RenderProcessHandler::new(Self {
+            object: std::ptr::null_mut(),
+            enabled,
+        })
+    }
+}
+
+impl ImplRenderProcessHandler for DemoRenderProcessHandler {
+    /// Returns the raw CEF render process handler pointer.
+    fn get_raw(&self) -> *mut sys::_cef_render_process_handler_t {
+        self.object.cast()
+    }
+
+    /// Called when the JavaScript context of a frame has been created.
+    ///
+    /// Adds the `udata` object, with one function per entry of `FUNCTIONS`, to the
+    /// global object of the context.
+    fn on_context_created(
+        &self,
+        _browser: Option<&mut impl ImplBrowser>,
+        _frame: Option<&mut impl ImplFrame>,
+        context: Option<&mut impl ImplV8Context>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let Some(global) = context.and_then(|context| context.get_global()) else {
+            return;
+        };
+        let Some(mut object) = v8_value_create_object(Option::<&mut V8Value>::None, Option::<&mut V8Value>::None) else {
+            return;
+        };
+
+        let mut handler = DemoV8Handler::new();
+        for name in FUNCTIONS {
+            let name = CefString::from(name);
+            if let Some(mut function) = v8_value_create_function(Some(&name), Some(&mut handler)) {
+                object.set_value_bykey(Some(&name), Some(&mut function), Default::default());
+            }
+        }
+        global.set_value_bykey(Some(&CefString::from(OBJECT)), Some(&mut object), Default::default());
+    }
+}
+
+//
+// V8 functions
+//
+
+/// Runs the `udata.*` functions.
+pub struct DemoV8Handler {
+    pub object: *mut RcImpl<sys::_cef_v8_handler_t, Self>,
+}
+
+impl DemoV8Handler {
+    pub fn new() -> V8Handler {
+        V8Handler::new(Self {
+            object: std::ptr::null_mut(),
+        })
+    }
+}
+
+impl ImplV8Handler for DemoV8Handler {
+    fn get_raw(&self) -> *mut sys::_cef_v8_handler_t {
+        self.object.cast()
+    }
+
+    /// Called when one of the functions is called from JavaScript.
+    ///
+    /// # Parameters
+    /// - `name`: Name of the function, one of `FUNCTIONS`.
+    /// - `arguments`: The JavaScript arguments.
+    /// - `retval`: Receives the result.
+    /// - `exception`: Receives the message of the exception to throw instead.
+    ///
+    /// # Returns
+    /// 1 if the call was handled.
+    fn execute(
+        &self,
+        name: Option<&CefString>,
+        _object: Option<&mut impl ImplV8Value>,
+        arguments: Option<&[Option<impl ImplV8Value>]>,
+        retval: Option<&mut Option<V8Value>>,
+        exception: Option<&mut CefString>,
+    ) -> c_int {
+        let Some(name) = name.map(CefString::to_string) else {
+            return 0;
+        };
+
+        let arguments = arguments
+            .unwrap_or_default()
+            .iter()
+            .map(|argument| argument.as_ref().map(from_v8).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .map(|arguments| arguments.into_iter().map(Option::flatten).collect::<Vec<_>>());
+
+        match arguments.and_then(|arguments| call(&name, &arguments)) {
+            Ok(result) => {
+                if let Some(retval) = retval {
+                    *retval = to_v8(result);
+                }
+            }
+            Err(message) => {
+                if let Some(exception) = exception {
+                    *exception = CefString::from(message.as_str());
+                }
+            }
+        }
+        1
+    }
+}
+
+/// Reads a JavaScript argument.
+///
+/// # Returns
+/// `None` for `undefined` and `null`, an error for other types.
+fn from_v8(value: &impl ImplV8Value) -> Result<Option<JsValue>, String> {
+    if value.is_undefined() != 0 || value.is_null() != 0 {
+        Ok(None)
+    } else if value.is_string() != 0 {
+        Ok(Some(JsValue::Text(CefString::from(&value.get_string_value()).to_string())))
+    } else if value.is_array_buffer() != 0 {
+        let data = value.get_array_buffer_data();
+        let length = value.get_array_buffer_byte_length();
+        let bytes = match data.is_null() {
+            true => Vec::new(),
+            // SAFETY: V8 keeps the buffer alive during the call
+            false => unsafe { std::slice::from_raw_parts(data, length) }.to_vec(),
+        };
+        Ok(Some(JsValue::Bytes(bytes)))
+    } else {
+        Err(format!("{}: expected a string or an ArrayBuffer", OBJECT))
+    }
+}
+
+/// Creates the JavaScript value of a result.
+fn to_v8(value: JsValue) -> Option<V8Value> {
+    match value {
+        JsValue::Text(text) => v8_value_create_string(Some(&CefString::from(text.as_str()))),
+        JsValue::Bytes(bytes) => {
+            // V8 owns the memory until it calls `release_buffer`
+            let length = bytes.len();
+            let buffer = Box::into_raw(bytes.into_boxed_slice()).cast::<u8>();
+            let mut release = DemoArrayBufferRelease::new(length);
+            v8_value_create_array_buffer(buffer, length, Some(&mut release))
+        }
+    }
+}
+
+/// Frees the memory of an `ArrayBuffer` created by `to_v8`.
+pub struct DemoArrayBufferRelease {
+    pub object: *mut RcImpl<sys::_cef_v8_array_buffer_release_callback_t, Self>,
+    /// Length of the buffer, needed to free it
+    pub length: usize,
+}
+
+impl DemoArrayBufferRelease {
+    pub fn new(length: usize) -> V8ArrayBufferReleaseCallback {
+        V8ArrayBufferReleaseCallback::new(Self {
+            object: std::ptr::null_mut(),
+            length,
+        })
+    }
+}
+
+impl ImplV8ArrayBufferReleaseCallback for DemoArrayBufferRelease {
+    fn get_raw(&self) -> *mut sys::_cef_v8_array_buffer_release_callback_t {
+        self.object.cast()
+    }
+
+    fn release_buffer(&self, buffer: *mut u8) {
+        // SAFETY: `buffer` comes from the boxed slice of `length` bytes leaked by `to_v8`
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer, self.length)) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(value: &str) -> Option<JsValue> {
+        Some(JsValue::Text(value.to_string()))
+    }
+
+    fn bytes(value: &[u8]) -> Option<JsValue> {
+        Some(JsValue::Bytes(value.to_vec()))
+    }
+
+    #[test]
+    fn test_call() {
+        let JsValue::Text(encoded) = call("swizzle", &[text("{\"id\":42}!")]).unwrap() else {
+            panic!("swizzle of a string should return a string");
+        };
+        assert_eq!(encoded, swizzle::base64_swizzle(b"{\"id\":42}!"));
+        assert_eq!(call("unswizzle", &[text(&encoded)]).unwrap(), JsValue::Text(String::from("{\"id\":42}!")));
+        assert_eq!(call("unswizzleBytes", &[text(&encoded), None]).unwrap(), JsValue::Bytes(b"{\"id\":42}!".to_vec()));
+
+        // Any text encoding is accepted
+        let hex = Encoding::Hex.encode(&swizzle::swizzle(b"data"));
+        assert_eq!(call("unswizzle", &[text(&hex)]).unwrap(), JsValue::Text(String::from("data")));
+
+        let swizzled = call("swizzle", &[bytes(&[0, 1, 2, 255]), text("secret")]).unwrap();
+        assert_eq!(swizzled, JsValue::Bytes(swizzle::swizzle_keyed(&[0, 1, 2, 255], b"secret")));
+        assert_eq!(call("unswizzle", &[Some(swizzled), text("secret")]).unwrap(), JsValue::Bytes(vec![0, 1, 2, 255]));
+
+        let sealed = swizzle::seal(b"odd", Some(b"secret"));
+        assert_eq!(call("unswizzle", &[bytes(&sealed), bytes(b"secret")]).unwrap(), JsValue::Bytes(b"odd".to_vec()));
+
+        assert_eq!(call("unswizzle", &[bytes(&sealed)]).unwrap_err(), "udata.unswizzle: the container is keyed, a key is required");
+        assert_eq!(call("unswizzle", &[text("not encoded!")]).unwrap_err(), "udata.unswizzle: not base64, hex or base32");
+        assert!(call("unswizzle", &[bytes(&[1, 2, 3])]).is_err());
+        assert!(call("swizzle", &[None]).is_err());
+        assert!(call("swizzle", &[]).is_err());
+        assert_eq!(call("eval", &[text("x")]).unwrap_err(), "udata.eval is not a function");
+    }
+}
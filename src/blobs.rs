@@ -0,0 +1,189 @@
+//! Content-addressed storage of captured bodies.
+//!
+//! Pages that poll an endpoint capture the same body over and over. A host entry with
+//! `dedup` enabled stores each distinct body once, under its SHA-256 hash, and its
+//! capture records refer to the hash instead of carrying the body:
+//!
+//! ```text
+//! <dir>/ab/cdef0123...[.gz|.zst]
+//! ```
+//!
+//! The hash is the one of the uncompressed body, the file is compressed and encrypted
+//! according to the `compression` and `encryption` configuration.
+//!
+//! With `only_on_change`, an exchange is only recorded if its body differs from the
+//! previous one captured for the same URL pattern.
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+use crate::compress::{Codec, Compressor};
+use crate::crypt;
+
+/// Deduplication settings of a host entry.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DedupConfig {
+    /// Directory of the blob store
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// Skip exchanges whose body is the same as the previous one
+    #[serde(default)]
+    pub only_on_change: bool,
+    /// What "the same" exchange means for `only_on_change`
+    #[serde(default)]
+    pub change_key: ChangeKey,
+}
+
+/// Default directory of the blob store.
+pub const DEFAULT_DIR: &str = ".udata/blobs";
+
+fn default_dir() -> String {
+    String::from(DEFAULT_DIR)
+}
+
+/// Grouping of exchanges compared by `only_on_change`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKey {
+    /// Every request matching the host entry's `xhr` pattern
+    #[default]
+    Pattern,
+    /// Requests to the exact same URL
+    Url,
+}
+
+/// Returns the content address of `data`, as `sha256:<hex>`.
+pub fn hash(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    let hex = digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("sha256:{}", hex)
+}
+
+/// Directory of bodies stored under their hash.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    /// Opens the blob store in `dir`. The directory is created on the first write.
+    pub fn new(dir: &str) -> Self {
+        Self { dir: PathBuf::from(dir) }
+    }
+
+    /// Returns the path of the blob with the given hash, without extension.
+    pub fn path(&self, hash: &str) -> PathBuf {
+        let hex = hash.strip_prefix("sha256:").unwrap_or(hash);
+        let (prefix, rest) = hex.split_at(hex.len().min(2));
+        self.dir.join(prefix).join(rest)
+    }
+
+    /// Stores `data` unless a blob with the same content already exists, in which case
+    /// its modification time is refreshed so retention keeps it as long as it's referenced.
+    ///
+    /// # Parameters
+    /// - `data`: The body to store.
+    /// - `compressor`: Compresses and encrypts the blob.
+    /// - `name`: Host entry the body belongs to, selects the compression dictionary.
+    ///
+    /// # Returns
+    /// The hash of the data.
+    pub fn put(&self, data: &[u8], compressor: &Compressor, name: &str) -> std::io::Result<String> {
+        let hash = hash(data);
+
+        if let Some((path, _)) = self.find(&hash) {
+            // Retention deletes the least recently modified files first
+            let _ = std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+        } else {
+            let path = PathBuf::from(compressor.path(&self.path(&hash).to_string_lossy()));
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+
+            // Write under a temporary name first, so a blob is never seen half-written
+            let temp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+            let mut blob = compressor.compress(name, data)?;
+            if let Some(keyring) = compressor.keyring() {
+                blob = keyring.encrypt(&blob)?;
+            }
+            std::fs::write(&temp, blob)?;
+            std::fs::rename(&temp, &path)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Reads the blob with the given hash, whatever it was compressed with.
+    pub fn get(&self, hash: &str, compressor: &Compressor) -> std::io::Result<Vec<u8>> {
+        let (path, codec) = self
+            .find(hash)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("no blob {}", hash)))?;
+
+        let mut blob = std::fs::read(&path)?;
+        if crypt::is_encrypted(&path) {
+            let keyring = compressor
+                .keyring()
+                .ok_or_else(|| std::io::Error::other(format!("blob {} is encrypted and no key is configured", hash)))?;
+            blob = keyring.decrypt(&blob)?;
+        }
+        compressor.decompress(codec, &blob)
+    }
+
+    /// Returns the file of a blob and its compression.
+    fn find(&self, hash: &str) -> Option<(PathBuf, Codec)> {
+        let path = self.path(hash).to_string_lossy().into_owned();
+        [Codec::None, Codec::Gzip, Codec::Zstd]
+            .into_iter()
+            .flat_map(|codec| {
+                let path = codec.path(&path);
+                [(crypt::path(&path), codec), (path, codec)]
+            })
+            .map(|(path, codec)| (PathBuf::from(path), codec))
+            .find(|(path, _)| path.exists())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::CompressionConfig;
+
+    #[test]
+    fn test_blob_store() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let store = BlobStore::new(&dir.to_string_lossy());
+        let compressor = Compressor::new(
+            &CompressionConfig {
+                codec: Codec::Zstd,
+                ..Default::default()
+            },
+            None,
+        );
+
+        let first = store.put(b"{\"items\":[]}", &compressor, "example.com").unwrap();
+        let second = store.put(b"{\"items\":[]}", &Compressor::default(), "example.com").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, hash(b"{\"items\":[]}"));
+        assert_eq!(store.get(&first, &Compressor::default()).unwrap(), b"{\"items\":[]}");
+
+        let blobs = walk(&dir);
+        assert_eq!(blobs.len(), 1);
+        assert!(blobs[0].ends_with(format!("{}.zst", &first["sha256:".len() + 2..])));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn walk(dir: &std::path::Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .flat_map(|entry| match entry.path() {
+                path if path.is_dir() => walk(&path),
+                path => vec![path],
+            })
+            .collect()
+    }
+}
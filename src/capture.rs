@@ -0,0 +1,417 @@
+//! Capture records produced by the response filter.
+//!
+//! A `CaptureRecord` is a self-contained, serializable snapshot of captured response
+//! data tied to the request `uuid` assigned by `DemoResponseFilter`. The `Exchange`
+//! tracks a request while it is being captured and drives its processor pipeline.
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::blobs::{BlobStore, ChangeKey};
+use crate::config::HostEntry;
+use crate::decode::Scheme;
+use crate::limits::Limiter;
+use crate::pipeline::Pipeline;
+use crate::session::Session;
+use crate::stream::{StreamEvent, StreamMode};
+
+/// A single captured piece of response data.
+#[derive(Debug, Serialize, Clone)]
+pub struct CaptureRecord {
+    /// UUID of the request this record belongs to
+    pub uuid: uuid::Uuid,
+    /// Time at which the data was captured
+    pub timestamp: DateTime<Utc>,
+    /// Host entry (`HostEntry::host`) that matched the request
+    pub host: String,
+    /// URL of the request
+    pub url: String,
+    /// HTTP method of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// HTTP status of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    /// Request headers, as `[name, value]` pairs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub request_headers: Vec<(String, String)>,
+    /// Response headers, as `[name, value]` pairs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub response_headers: Vec<(String, String)>,
+    /// Event details, for records split out of a streaming response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<EventInfo>,
+    /// Captured data, see `encoding`. Empty when the body is in the blob store.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub body: String,
+    /// Encoding of `body`: `utf8` or `base64`
+    pub encoding: &'static str,
+    /// Hash of the body in the blob store, see `blobs.rs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// Plaintext of a swizzled body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<DecodedBody>,
+    /// Whether part of the body was dropped by the host's size limit
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Length of the body before truncation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_length: Option<u64>,
+}
+
+/// Position and metadata of an event within a streaming response.
+#[derive(Debug, Serialize, Clone)]
+pub struct EventInfo {
+    /// Zero-based index of the event within the response
+    pub sequence: u64,
+    /// Event type (SSE `event:` field)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Event id (SSE `id:` field)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+/// Plaintext recovered from an obfuscated body.
+#[derive(Debug, Serialize, Clone)]
+pub struct DecodedBody {
+    /// Detected scheme, see `decode::Scheme`
+    pub scheme: &'static str,
+    /// Decoded data, see `encoding`
+    pub body: String,
+    /// Encoding of `body`: `utf8` or `base64`
+    pub encoding: &'static str,
+}
+
+impl DecodedBody {
+    /// Creates a decoded body from the plaintext produced by `scheme`.
+    pub fn new(scheme: Scheme, plain: &[u8]) -> Self {
+        let (body, encoding) = encode_body(plain);
+
+        Self {
+            scheme: scheme.name(),
+            body,
+            encoding,
+        }
+    }
+}
+
+impl CaptureRecord {
+    /// Creates a record for `body`, stamped with the current time.
+    ///
+    /// Bodies that are valid UTF-8 are stored as text, anything else as base64.
+    pub fn new(uuid: uuid::Uuid, host: &str, url: &str, body: &[u8]) -> Self {
+        let (body, encoding) = encode_body(body);
+
+        Self {
+            uuid,
+            timestamp: Utc::now(),
+            host: host.to_string(),
+            url: url.to_string(),
+            method: None,
+            status: None,
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
+            event: None,
+            body,
+            encoding,
+            blob: None,
+            decoded: None,
+            truncated: false,
+            original_length: None,
+        }
+    }
+
+    /// Creates a record for the processed body of an exchange.
+    pub fn from_exchange(exchange: &Exchange, body: &[u8]) -> Self {
+        let mut record = Self::new(exchange.uuid, exchange.host_name(), &exchange.url, body);
+        if let Some(request) = exchange.request.as_ref() {
+            record.method = Some(request.method.clone());
+            record.request_headers = request.headers.clone();
+        }
+        if let Some(response) = exchange.response.as_ref() {
+            record.status = Some(response.status);
+            record.response_headers = response.headers.clone();
+        }
+        record.decoded = exchange.decoded.clone();
+        record.truncated = exchange.truncated;
+        record.original_length = exchange.original_length;
+        record
+    }
+
+    /// Creates a record for an event split out of a streaming response.
+    pub fn from_event(exchange: &Exchange, sequence: u64, event: StreamEvent) -> Self {
+        let mut record = Self::new(exchange.uuid, exchange.host_name(), &exchange.url, &event.data);
+        record.event = Some(EventInfo {
+            sequence,
+            kind: event.kind,
+            id: event.id,
+        });
+        record
+    }
+
+    /// Writes the record to stderr as a single JSON line.
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => eprintln!("{}", json),
+            Err(e) => eprintln!("Failed to serialize capture record {}: {}", self.uuid, e),
+        }
+    }
+}
+
+/// Request details recorded by the resource request handler.
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    /// HTTP method
+    pub method: String,
+    /// URL of the request
+    pub url: String,
+    /// Request headers, in order
+    pub headers: Vec<(String, String)>,
+    /// Request body, as sent
+    pub body: Option<Vec<u8>>,
+    /// URL of the page that issued the request
+    pub page: Option<String>,
+    /// Time at which the request was sent
+    pub started: DateTime<Utc>,
+    /// Monotonic time at which the request was sent, for timings
+    pub sent: Instant,
+}
+
+/// Response details recorded by the resource request handler.
+#[derive(Debug, Clone)]
+pub struct ResponseInfo {
+    /// HTTP status code
+    pub status: i32,
+    /// HTTP status text
+    pub status_text: String,
+    /// Response headers, in order
+    pub headers: Vec<(String, String)>,
+    /// Mime type of the response
+    pub mime_type: String,
+    /// Target of a redirect response
+    pub redirect_url: Option<String>,
+    /// Monotonic time at which the response headers were received, for timings
+    pub received: Instant,
+}
+
+/// Per-request capture state, shared between a resource request handler and its
+/// response filter.
+#[derive(Default)]
+pub struct Exchange {
+    /// UUID assigned by the response filter
+    pub uuid: uuid::Uuid,
+    /// Host entry that matched the request
+    pub host: Option<HostEntry>,
+    /// URL of the request
+    pub url: String,
+    /// Mime type of the response
+    pub mime_type: String,
+    /// Charset of the response
+    pub charset: String,
+    /// Plaintext recovered by the decode processor
+    pub decoded: Option<DecodedBody>,
+    /// Whether part of the body was dropped by the host's size limit
+    pub truncated: bool,
+    /// Length of the body before truncation
+    pub original_length: Option<u64>,
+    /// Size limit applied to the body
+    pub limiter: Option<Limiter>,
+    /// Processor chain, present while the exchange is being captured
+    pub pipeline: Option<Pipeline>,
+    /// Request details, set before the request is sent
+    pub request: Option<RequestInfo>,
+    /// Response details, set once the response headers are received
+    pub response: Option<ResponseInfo>,
+    /// Redirects followed before the final response
+    pub redirects: Vec<(RequestInfo, ResponseInfo)>,
+    /// Body handed to the sink by the processor chain, kept when a session output
+    /// needs it
+    pub body: Option<Vec<u8>>,
+    /// Session the exchange is recorded in, set when the capture starts
+    pub session: Option<Arc<Session>>,
+    /// Capture records emitted so far, kept when a session output needs them
+    pub records: Vec<CaptureRecord>,
+    /// Set when the body is the same as last time and the exchange isn't recorded
+    pub unchanged: bool,
+    /// Plaintext of the request body, set once it has been swizzled. Redirects send the
+    /// swizzled body again as is, and record this instead
+    pub request_plaintext: Option<Vec<u8>>,
+}
+
+/// Thread-safe handle to an `Exchange`.
+pub type SharedExchange = Arc<Mutex<Exchange>>;
+
+impl Exchange {
+    /// Returns the name of the matched host entry, or an empty string.
+    pub fn host_name(&self) -> &str {
+        self.host.as_ref().map(|host| host.host.as_str()).unwrap_or("")
+    }
+
+    /// Builds the processor pipeline for the matched host entry and starts it.
+    ///
+    /// Requests left out by the host's sampling rate are not captured.
+    pub fn start(&mut self, session: &Arc<Session>) {
+        let Some(host) = self.host.as_ref() else {
+            return;
+        };
+
+        if !session.sample(host) {
+            return;
+        }
+
+        self.session = Some(session.clone());
+        // Streamed responses are limited per event by the stream processor
+        if host.stream.or_else(|| StreamMode::from_mime_type(&self.mime_type)).is_none() {
+            self.limiter = Limiter::new(&host.limits);
+        }
+        self.pipeline = Some(Pipeline::for_host(host, &self.mime_type));
+        self.with_pipeline(|pipeline, exchange| pipeline.start(exchange));
+    }
+
+    /// Runs a chunk of response data through the pipeline.
+    pub fn chunk(&mut self, data: &[u8]) {
+        let data = match self.limiter.as_mut() {
+            Some(limiter) => limiter.chunk(data),
+            None => data,
+        };
+
+        self.process(data);
+    }
+
+    /// Completes the pipeline and records the exchange in the session outputs, unless a
+    /// processor failed. The exchange is no longer captured afterwards.
+    pub fn complete(&mut self) {
+        if let Some(mut limiter) = self.limiter.take() {
+            let Some(rest) = limiter.finish() else {
+                eprintln!(
+                    "Skipped capture of {} ({}): {} bytes exceed the size limit",
+                    self.url,
+                    self.uuid,
+                    limiter.original_length()
+                );
+                self.pipeline = None;
+                self.session = None;
+                return;
+            };
+
+            if limiter.is_truncated() {
+                self.truncated = true;
+                self.original_length = Some(limiter.original_length());
+            }
+
+            self.process(&rest);
+        }
+
+        self.with_pipeline(|pipeline, exchange| pipeline.complete(exchange));
+        let failed = self.pipeline.take().is_some_and(|pipeline| pipeline.failed());
+
+        // A failed chain leaves a partial body, so the exchange isn't recorded
+        if let Some(session) = self.session.take()
+            && !self.unchanged
+            && !failed
+        {
+            session.record(self);
+        }
+    }
+
+    /// Writes a capture record to the host entry's output, or to stderr if it has none.
+    pub fn emit(&mut self, record: CaptureRecord) {
+        let Some(session) = self.session.as_ref() else {
+            record.emit();
+            return;
+        };
+
+        let written = self.host.as_ref().is_some_and(|host| session.write_record(host, &record));
+        if !written {
+            record.emit();
+        }
+
+        if session.keeps_records() {
+            self.records.push(record);
+        }
+    }
+
+    /// Records the processed body of the exchange.
+    ///
+    /// Host entries with `dedup` store the body in the blob store and record its hash.
+    /// With `only_on_change`, a body identical to the previous one for the same URL
+    /// pattern isn't recorded at all, and neither is the exchange.
+    pub fn emit_body(&mut self, body: &[u8]) {
+        if self.session.as_ref().is_some_and(|session| session.needs_body()) {
+            self.body.get_or_insert_default().extend_from_slice(body);
+        }
+
+        let Some((dedup, session)) = self
+            .host
+            .as_ref()
+            .and_then(|host| host.dedup.clone())
+            .zip(self.session.clone())
+        else {
+            self.emit(CaptureRecord::from_exchange(self, body));
+            return;
+        };
+
+        let hash = crate::blobs::hash(body);
+        if dedup.only_on_change {
+            let key = match dedup.change_key {
+                ChangeKey::Pattern => format!("{}\n{}", self.host_name(), self.host.as_ref().map_or("", |host| &host.xhr)),
+                ChangeKey::Url => self.url.clone(),
+            };
+
+            if !session.changed(key, &hash) {
+                self.unchanged = true;
+                return;
+            }
+        }
+
+        let mut record = CaptureRecord::from_exchange(self, b"");
+        match BlobStore::new(&dedup.dir).put(body, session.compressor(), self.host_name()) {
+            Ok(hash) => record.blob = Some(hash),
+            Err(e) => {
+                eprintln!("Failed to store body of {} in {}: {}", self.uuid, dedup.dir, e);
+                record = CaptureRecord::from_exchange(self, body);
+            }
+        }
+        self.emit(record);
+    }
+
+    /// Reports an error to the pipeline. The exchange is no longer captured afterwards.
+    pub fn error(&mut self, error: &str) {
+        self.with_pipeline(|pipeline, exchange| pipeline.error(exchange, error));
+        self.pipeline = None;
+        self.session = None;
+    }
+
+    /// Runs data that passed the size limit through the pipeline.
+    fn process(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        self.with_pipeline(|pipeline, exchange| pipeline.chunk(exchange, data));
+    }
+
+    /// Runs `f` with the pipeline temporarily taken out of the exchange, so the
+    /// processors can borrow the exchange mutably.
+    fn with_pipeline(&mut self, f: impl FnOnce(&mut Pipeline, &mut Exchange)) {
+        if let Some(mut pipeline) = self.pipeline.take() {
+            f(&mut pipeline, self);
+            self.pipeline = Some(pipeline);
+        }
+    }
+}
+
+/// Encodes a body for a capture record.
+///
+/// # Returns
+/// The body as text if it's valid UTF-8, otherwise as base64, along with the encoding used.
+pub fn encode_body(body: &[u8]) -> (String, &'static str) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_string(), "utf8"),
+        Err(_) => (BASE64_STANDARD.encode(body), "base64"),
+    }
+}
@@ -0,0 +1,467 @@
+//! Command line subcommands.
+//!
+//! Without a subcommand `udata` starts the browser. CEF starts its subprocesses from
+//! the same executable with `--type=...` arguments, so the command line interface is
+//! only used when the first argument is one of the subcommands below.
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::blobs::{BlobStore, DEFAULT_DIR};
+use crate::compress::{self, Compressor};
+use crate::config::Config;
+use crate::crypt::{self, KeySource, Keyring};
+use crate::helpers::LimitString;
+use crate::search::{self, SearchConfig, SearchIndex};
+use crate::store::{self, Filter, Store, StoredExchange};
+
+/// Names of the subcommands, as they appear on the command line.
+const COMMANDS: &[&str] = &["query", "search", "cat", "decrypt", "rekey"];
+
+/// Location of the configuration file.
+const SETTINGS: &str = ".udata/settings.json";
+
+#[derive(Parser)]
+#[command(name = "udata", about = "Inspect captured traffic")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Query the SQLite capture store
+    Query(QueryArgs),
+    /// Search captured URLs and bodies
+    Search(SearchArgs),
+    /// Print capture files and blobs, decrypting and decompressing them
+    Cat(CatArgs),
+    /// Decrypt capture files, leaving them compressed
+    Decrypt(DecryptArgs),
+    /// Encrypt the data keys of capture files again with the current key
+    Rekey(RekeyArgs),
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// Path of the capture database, defaults to the configured store
+    #[arg(long)]
+    db: Option<String>,
+    /// Host entry the exchange matched
+    #[arg(long)]
+    host: Option<String>,
+    /// URL pattern, `*` matches any sequence of characters
+    #[arg(long)]
+    url: Option<String>,
+    /// Earliest start time, RFC 3339, YYYY-MM-DD or a duration ago like 2h
+    #[arg(long, value_parser = store::parse_time)]
+    since: Option<DateTime<Utc>>,
+    /// Latest start time, RFC 3339, YYYY-MM-DD or a duration ago like 2h
+    #[arg(long, value_parser = store::parse_time)]
+    until: Option<DateTime<Utc>>,
+    /// HTTP status of the response
+    #[arg(long)]
+    status: Option<i32>,
+    /// JSON field of a capture record, as `$.path=value` (repeatable)
+    #[arg(long = "field", value_parser = parse_field)]
+    fields: Vec<(String, String)>,
+    /// Maximum number of results
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Include the bodies in JSON output
+    #[arg(long)]
+    bodies: bool,
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// Words that must all appear in the URL or a body, "quoted phrases" stay together
+    terms: String,
+    /// Path of the search index, defaults to the configured one
+    #[arg(long)]
+    db: Option<String>,
+    /// Host entry the exchange matched
+    #[arg(long)]
+    host: Option<String>,
+    /// Earliest start time, RFC 3339, YYYY-MM-DD or a duration ago like 2h
+    #[arg(long, value_parser = store::parse_time)]
+    since: Option<DateTime<Utc>>,
+    /// Latest start time, RFC 3339, YYYY-MM-DD or a duration ago like 2h
+    #[arg(long, value_parser = store::parse_time)]
+    until: Option<DateTime<Utc>>,
+    /// Maximum number of results
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+#[derive(Args)]
+struct CatArgs {
+    /// Files written by the capture, or hashes of blobs (`sha256:...`)
+    #[arg(required = true)]
+    paths: Vec<String>,
+    /// Directory of the blob store, defaults to the one configured for the first host
+    #[arg(long)]
+    blobs: Option<String>,
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Args)]
+struct DecryptArgs {
+    /// Encrypted files (`*.enc`)
+    #[arg(required = true)]
+    paths: Vec<String>,
+    /// Directory the decrypted files are written to, defaults to the one of each file
+    #[arg(long, short)]
+    output: Option<String>,
+    /// Write the decrypted content to stdout instead
+    #[arg(long, conflicts_with = "output")]
+    stdout: bool,
+    #[command(flatten)]
+    keys: KeyArgs,
+}
+
+#[derive(Args)]
+struct RekeyArgs {
+    /// Encrypted files, or directories to search for them
+    #[arg(required = true)]
+    paths: Vec<String>,
+}
+
+/// Keys tried in addition to the configured ones.
+#[derive(Args)]
+struct KeyArgs {
+    /// Keyfile (repeatable)
+    #[arg(long = "keyfile")]
+    keyfiles: Vec<String>,
+    /// Environment variable holding a passphrase (repeatable)
+    #[arg(long = "passphrase-env")]
+    passphrase_envs: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+}
+
+/// Runs the subcommand given on the command line, if any.
+///
+/// # Returns
+/// The exit code of the subcommand, or `None` if the browser should be started.
+pub fn run() -> Option<i32> {
+    let command = std::env::args().nth(1)?;
+    if !COMMANDS.contains(&command.as_str()) {
+        return None;
+    }
+
+    let result = match Cli::parse().command {
+        Command::Query(args) => query(args),
+        Command::Search(args) => search(args),
+        Command::Cat(args) => cat(args),
+        Command::Decrypt(args) => decrypt(args),
+        Command::Rekey(args) => rekey(args),
+    };
+
+    Some(match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("[{}] {}", "error".red(), e);
+            1
+        }
+    })
+}
+
+/// Reads the configuration, if there is one.
+fn config() -> Option<Config> {
+    let file = std::fs::File::open(SETTINGS).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+fn parse_field(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(path, value)| (path.to_string(), value.to_string()))
+        .ok_or_else(|| format!("invalid field {:?}, expected $.path=value", value))
+}
+
+fn query(args: QueryArgs) -> Result<(), String> {
+    let path = args
+        .db
+        .or_else(|| config()?.store.map(|store| store.path))
+        .unwrap_or_else(|| String::from(store::DEFAULT_PATH));
+
+    if !std::path::Path::new(&path).exists() {
+        return Err(format!("No capture store at {}", path));
+    }
+
+    let compressor = Arc::new(compressor(keyring(&args.keys)?));
+    let store = Store::open(&path, compressor).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let filter = Filter {
+        host: args.host,
+        url: args.url,
+        since: args.since,
+        until: args.until,
+        status: args.status,
+        fields: args.fields,
+        limit: args.limit,
+    };
+
+    let bodies = args.bodies && matches!(args.format, Format::Json);
+    let exchanges = store.query(&filter, bodies).map_err(|e| format!("Query failed: {}", e))?;
+
+    match args.format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(&exchanges).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        Format::Table => print_table(&exchanges),
+    }
+
+    Ok(())
+}
+
+fn search(args: SearchArgs) -> Result<(), String> {
+    let config = args
+        .db
+        .map(|path| SearchConfig {
+            path,
+            max_body: 0,
+        })
+        .or_else(|| config()?.search)
+        .unwrap_or_else(|| SearchConfig {
+            path: String::from(search::DEFAULT_PATH),
+            max_body: 0,
+        });
+
+    if !std::path::Path::new(&config.path).exists() {
+        return Err(format!("No search index at {}", config.path));
+    }
+
+    let index = SearchIndex::open(&config).map_err(|e| format!("Failed to open {}: {}", config.path, e))?;
+    let filter = search::Filter {
+        host: args.host,
+        since: args.since,
+        until: args.until,
+        limit: args.limit,
+    };
+    let mut hits = index.search(&args.terms, &filter).map_err(|e| format!("Search failed: {}", e))?;
+
+    match args.format {
+        Format::Json => {
+            for hit in hits.iter_mut() {
+                hit.url.retain(|c| c != search::MATCH_START && c != search::MATCH_END);
+                hit.snippet.retain(|c| c != search::MATCH_START && c != search::MATCH_END);
+            }
+            let json = serde_json::to_string_pretty(&hits).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        Format::Table => {
+            for hit in &hits {
+                let status = hit.status.map(|status| status.to_string()).unwrap_or_default();
+                println!("{}  {}  {}  {}", hit.started.dimmed(), status, highlight(&hit.url), hit.uuid.dimmed());
+                println!("    {}", highlight(&hit.snippet.replace(['\n', '\r'], " ")));
+            }
+            println!("{} exchange(s)", hits.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the matches marked by the search index in bold yellow.
+fn highlight(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(search::MATCH_START) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + search::MATCH_START.len_utf8()..];
+        let end = rest.find(search::MATCH_END).unwrap_or(rest.len());
+        output.push_str(&rest[..end].yellow().bold().to_string());
+        rest = rest.get(end + search::MATCH_END.len_utf8()..).unwrap_or_default();
+    }
+    output.push_str(rest);
+    output
+}
+
+fn cat(args: CatArgs) -> Result<(), String> {
+    let config = config();
+    let compressor = compressor(keyring(&args.keys)?);
+    let blobs = args
+        .blobs
+        .or_else(|| config?.host.into_iter().find_map(|host| host.dedup).map(|dedup| dedup.dir))
+        .unwrap_or_else(|| String::from(DEFAULT_DIR));
+    let blobs = BlobStore::new(&blobs);
+
+    let mut stdout = std::io::stdout().lock();
+    for path in &args.paths {
+        if path.starts_with("sha256:") {
+            let data = blobs.get(path, &compressor).map_err(|e| format!("Failed to read blob {}: {}", path, e))?;
+            stdout.write_all(&data).map_err(|e| e.to_string())?;
+        } else {
+            let mut reader =
+                compress::open(path, compressor.keyring()).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+            std::io::copy(&mut reader, &mut stdout).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        }
+    }
+
+    stdout.flush().map_err(|e| e.to_string())
+}
+
+fn decrypt(args: DecryptArgs) -> Result<(), String> {
+    let keyring = keyring(&args.keys)?.ok_or("No key given or configured")?;
+
+    for path in &args.paths {
+        let stem = path
+            .strip_suffix(crypt::EXTENSION)
+            .ok_or_else(|| format!("{} isn't an encrypted file", path))?;
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let mut reader = keyring
+            .reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("Failed to decrypt {}: {}", path, e))?;
+
+        if args.stdout {
+            let mut stdout = std::io::stdout().lock();
+            std::io::copy(&mut reader, &mut stdout).map_err(|e| format!("Failed to decrypt {}: {}", path, e))?;
+            continue;
+        }
+
+        let output = match args.output.as_ref() {
+            Some(dir) => std::path::Path::new(dir).join(std::path::Path::new(stem).file_name().unwrap_or_default()),
+            None => std::path::PathBuf::from(stem),
+        };
+        // Never overwrite, the output might be a file the user still needs
+        let mut file = std::fs::File::create_new(&output).map_err(|e| format!("Failed to create {:?}: {}", output, e))?;
+        if let Err(e) = std::io::copy(&mut reader, &mut file) {
+            let _ = std::fs::remove_file(&output);
+            return Err(format!("Failed to decrypt {}: {}", path, e));
+        }
+        println!("{} -> {}", path, output.display());
+    }
+
+    Ok(())
+}
+
+fn rekey(args: RekeyArgs) -> Result<(), String> {
+    let encryption = config()
+        .and_then(|config| config.encryption)
+        .ok_or("No `encryption` configured, set the new key there and the old ones in `previous`")?;
+    let keyring = Keyring::new(&encryption)?;
+
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_encrypted(std::path::Path::new(path), &mut files);
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        match keyring.rekey(&file.to_string_lossy()) {
+            Ok(()) => println!("{}", file.display()),
+            Err(e) => {
+                eprintln!("[{}] {}: {}", "error".red(), file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} file(s) rekeyed", files.len() - failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} file(s) failed", failed)),
+    }
+}
+
+/// Lists the encrypted files under `path`.
+fn collect_encrypted(path: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+    match std::fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                collect_encrypted(&entry.path(), files);
+            }
+        }
+        Err(_) if crypt::is_encrypted(path) => files.push(path.to_path_buf()),
+        Err(_) => {}
+    }
+}
+
+/// Loads the keys given on the command line and the configured ones.
+fn keyring(keys: &KeyArgs) -> Result<Option<Arc<Keyring>>, String> {
+    let mut sources = keys
+        .keyfiles
+        .iter()
+        .map(|path| KeySource {
+            keyfile: Some(path.clone()),
+            passphrase_env: None,
+        })
+        .chain(keys.passphrase_envs.iter().map(|name| KeySource {
+            keyfile: None,
+            passphrase_env: Some(name.clone()),
+        }))
+        .collect::<Vec<_>>();
+
+    if let Some(encryption) = config().and_then(|config| config.encryption) {
+        sources.push(encryption.key);
+        sources.extend(encryption.previous);
+    }
+
+    if sources.is_empty() {
+        return Ok(None);
+    }
+    Keyring::open(&sources).map(|keyring| Some(Arc::new(keyring)))
+}
+
+/// Creates a compressor for reading, with the dictionaries of the configured directory.
+fn compressor(keyring: Option<Arc<Keyring>>) -> Compressor {
+    Compressor::new(&config().and_then(|config| config.compression).unwrap_or_default(), keyring)
+}
+
+fn print_table(exchanges: &[StoredExchange]) {
+    let header = ["STARTED", "STATUS", "METHOD", "HOST", "URL", "UUID"];
+    let rows = exchanges
+        .iter()
+        .map(|exchange| {
+            [
+                exchange.started.clone(),
+                exchange.status.map(|status| status.to_string()).unwrap_or_default(),
+                exchange.method.clone().unwrap_or_default(),
+                exchange.host.clone(),
+                exchange.url.limit(80),
+                exchange.uuid.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", line(header.to_vec()).bold());
+    for row in &rows {
+        println!("{}", line(row.iter().map(String::as_str).collect()));
+    }
+    println!("{} exchange(s)", rows.len());
+}
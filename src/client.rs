@@ -6,7 +6,9 @@
 //! interface between the application and the embedded browser instances.
 
 use cef::{Client, ImplClient, rc::RcImpl, sys, RequestHandler};
-use crate::{config::Config, xhr::DemoRequestHandler};
+use std::sync::Arc;
+
+use crate::{config::Config, session::Session, xhr::DemoRequestHandler};
 
 /// A custom implementation of `Client` for handling browser interactions.
 ///
@@ -16,6 +18,8 @@ use crate::{config::Config, xhr::DemoRequestHandler};
 pub struct DemoClient {
     pub base: *mut RcImpl<sys::_cef_client_t, Self>,
     pub config: Option<Config>,
+    /// Capture state shared by every request
+    pub session: Arc<Session>,
 }
 
 impl DemoClient {
@@ -26,10 +30,11 @@ impl DemoClient {
     /// Returns a new `Client` instance wrapping our custom implementation.
     /// This client can be used when creating browser instances.
     ///
-    pub fn new(config: Option<Config>) -> Client {
+    pub fn new(config: Option<Config>, session: Arc<Session>) -> Client {
         Client::new(Self {
             base: std::ptr::null_mut(),
-            config
+            config,
+            session,
         })
     }
 }
@@ -46,7 +51,7 @@ impl ImplClient for DemoClient {
     /// An optional `RequestHandler` instance. Returns `Some` with our custom
     /// request handler implementation.
     fn get_request_handler(&self) -> Option<RequestHandler> {
-        Some(DemoRequestHandler::new(self.config.clone()))
+        Some(DemoRequestHandler::new(self.config.clone(), self.session.clone()))
     }
 
     /// Returns the raw pointer to the underlying CEF client.
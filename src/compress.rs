@@ -0,0 +1,511 @@
+//! Compression of the capture outputs.
+//!
+//! A top-level `compression` section applies to every file the capture writes:
+//!
+//! - HAR and JSONL files are compressed as a single stream, and get a `.gz` or `.zst`
+//!   extension
+//! - WARC records stay individually compressed, with zstd instead of gzip when
+//!   configured
+//! - blobs and the bodies in the SQLite store are compressed one by one
+//!
+//! Small JSON bodies compress poorly on their own. With a `dictionary`, the first
+//! bodies of each host entry are used to train a zstd dictionary, which then
+//! compresses the following ones. Dictionaries are saved as `<name>-<id>.dict` and
+//! looked up by the identifier zstd writes in each frame, so older bodies stay
+//! readable after a new dictionary is trained.
+//!
+//! Files are encrypted after compression when `encryption` is configured, see
+//! `crypt.rs`. `open` and `Compressor::decompress` read the outputs back, see
+//! `udata cat`.
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::crypt::{self, Keyring, Sink};
+use crate::helpers::safe_file_name;
+
+/// Default directory of the trained dictionaries.
+pub const DEFAULT_DICTIONARIES: &str = ".udata/dictionaries";
+
+/// Largest body used as a dictionary training sample.
+const MAX_SAMPLE: usize = 64 * 1024;
+
+/// Shortest time between two flushes of a streamed output. Each flush ends a compressed
+/// block and an encrypted chunk, so flushing after every record would defeat both.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Compression format of an output.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    /// Returns the file extension of the format, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::None => "",
+            Codec::Gzip => ".gz",
+            Codec::Zstd => ".zst",
+        }
+    }
+
+    /// Returns the name stored alongside compressed data, `None` if it isn't compressed.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Codec::None => None,
+            Codec::Gzip => Some("gzip"),
+            Codec::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Parses a name returned by `name`.
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name {
+            Some("gzip") => Codec::Gzip,
+            Some("zstd") => Codec::Zstd,
+            _ => Codec::None,
+        }
+    }
+
+    /// Returns the format of a file, according to its extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Codec::Gzip,
+            Some("zst") => Codec::Zstd,
+            _ => Codec::None,
+        }
+    }
+
+    /// Replaces the compression extension of `path` with the one of this format.
+    pub fn path(self, path: &str) -> String {
+        let stem = path
+            .strip_suffix(Codec::Gzip.extension())
+            .or_else(|| path.strip_suffix(Codec::Zstd.extension()))
+            .unwrap_or(path);
+        format!("{}{}", stem, self.extension())
+    }
+
+    /// Returns the default compression level of the format.
+    pub fn default_level(self) -> i32 {
+        match self {
+            Codec::None => 0,
+            Codec::Gzip => 6,
+            Codec::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+/// Compression settings, as written in the configuration.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CompressionConfig {
+    /// Format of the outputs
+    #[serde(default)]
+    pub codec: Codec,
+    /// Compression level, 0-9 for gzip and 1-22 for zstd
+    #[serde(default)]
+    pub level: Option<i32>,
+    /// Trains a zstd dictionary per host entry
+    #[serde(default)]
+    pub dictionary: Option<DictionaryConfig>,
+}
+
+/// Dictionary training settings.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DictionaryConfig {
+    /// Directory the dictionaries are saved to
+    #[serde(default = "default_dictionaries")]
+    pub dir: String,
+    /// Number of bodies collected before training
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+    /// Maximum size of a dictionary, in bytes
+    #[serde(default = "default_size")]
+    pub size: usize,
+}
+
+fn default_dictionaries() -> String {
+    String::from(DEFAULT_DICTIONARIES)
+}
+
+fn default_samples() -> usize {
+    200
+}
+
+fn default_size() -> usize {
+    16 * 1024
+}
+
+impl CompressionConfig {
+    /// Returns the configured level, or the default one of the format.
+    pub fn level(&self) -> i32 {
+        self.level.unwrap_or(self.codec.default_level())
+    }
+}
+
+/// A writer that compresses its data in the configured format.
+///
+/// `flush` writes out everything received so far as a complete block, so a stream
+/// can be read back while it's still being written. `finish` must be called to
+/// terminate it.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Wraps `writer` in an encoder for `codec`.
+    pub fn new(writer: W, codec: Codec, level: i32) -> io::Result<Self> {
+        Ok(match codec {
+            Codec::None => Encoder::None(writer),
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::new(level.clamp(0, 9) as u32))),
+            Codec::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, level)?),
+        })
+    }
+
+    /// Terminates the stream.
+    ///
+    /// # Returns
+    /// The underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write> std::fmt::Debug for Encoder<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let codec = match self {
+            Encoder::None(_) => Codec::None,
+            Encoder::Gzip(_) => Codec::Gzip,
+            Encoder::Zstd(_) => Codec::Zstd,
+        };
+        f.debug_tuple("Encoder").field(&codec).finish()
+    }
+}
+
+/// Opens a file written by the capture, decrypting and decompressing it according to
+/// its extension.
+pub fn open(path: impl AsRef<Path>, keyring: Option<&Keyring>) -> io::Result<Box<dyn Read>> {
+    let path = path.as_ref().to_string_lossy();
+    let file = BufReader::new(File::open(path.as_ref())?);
+
+    let (file, path): (Box<dyn BufRead>, &str) = match path.strip_suffix(crypt::EXTENSION) {
+        Some(stem) => {
+            let keyring = keyring.ok_or_else(|| io::Error::other("the file is encrypted and no key is configured"))?;
+            (Box::new(BufReader::new(keyring.reader(file)?)), stem)
+        }
+        None => (Box::new(file), path.as_ref()),
+    };
+
+    Ok(match Codec::from_path(path) {
+        Codec::None => Box::new(file),
+        Codec::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Codec::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    })
+}
+
+/// Compresses a buffer on its own, without a dictionary.
+pub fn compress(codec: Codec, level: i32, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new(), codec, level)?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Trained dictionaries and the samples collected for the next ones.
+#[derive(Debug, Default)]
+struct Dictionaries {
+    /// Dictionaries by zstd identifier
+    by_id: HashMap<u32, Arc<[u8]>>,
+    /// Identifier of the current dictionary of each host entry
+    by_name: HashMap<String, u32>,
+    /// Bodies collected per host entry, until there are enough to train a dictionary
+    samples: HashMap<String, Vec<Vec<u8>>>,
+}
+
+/// Compresses and decompresses individual bodies, with per host dictionaries, and
+/// creates the output files.
+#[derive(Debug, Default)]
+pub struct Compressor {
+    config: CompressionConfig,
+    /// Encrypts the files, when configured
+    keyring: Option<Arc<Keyring>>,
+    dir: PathBuf,
+    dictionaries: Mutex<Dictionaries>,
+}
+
+impl Compressor {
+    /// Creates a compressor and loads the dictionaries trained so far.
+    ///
+    /// # Parameters
+    /// - `config`: The compression settings.
+    /// - `keyring`: Encrypts the files written, and decrypts the dictionaries.
+    pub fn new(config: &CompressionConfig, keyring: Option<Arc<Keyring>>) -> Self {
+        let dir = config
+            .dictionary
+            .as_ref()
+            .map_or(DEFAULT_DICTIONARIES, |dictionary| dictionary.dir.as_str());
+
+        Self {
+            config: config.clone(),
+            dictionaries: Mutex::new(load(Path::new(dir), keyring.as_deref())),
+            keyring,
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// Returns the compression settings.
+    pub fn config(&self) -> &CompressionConfig {
+        &self.config
+    }
+
+    /// Returns the format `compress` produces.
+    pub fn codec(&self) -> Codec {
+        self.config.codec
+    }
+
+    /// Returns the keyring files are encrypted with, if encryption is configured.
+    pub fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_deref()
+    }
+
+    /// Returns `path` with the extensions of the configured compression and encryption.
+    pub fn path(&self, path: &str) -> String {
+        let path = self.config.codec.path(path);
+        match self.keyring {
+            Some(_) => crypt::path(&path),
+            None => path,
+        }
+    }
+
+    /// Creates a file, compressing and encrypting everything written to it.
+    pub fn create(&self, path: &str) -> io::Result<Encoder<Sink>> {
+        Encoder::new(Sink::create(path, self.keyring())?, self.config.codec, self.config.level())
+    }
+
+    /// Compresses a body of the host entry `name`, with its dictionary once there is one.
+    pub fn compress(&self, name: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        if self.config.codec != Codec::Zstd {
+            return compress(self.config.codec, self.config.level(), data);
+        }
+
+        match self.dictionary(name, data) {
+            Some(dictionary) => zstd::bulk::Compressor::with_dictionary(self.config.level(), &dictionary)?.compress(data),
+            None => zstd::bulk::compress(data, self.config.level()),
+        }
+    }
+
+    /// Decompresses a body returned by `compress`.
+    pub fn decompress(&self, codec: Codec, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        match codec {
+            Codec::None => output.extend_from_slice(data),
+            Codec::Gzip => {
+                MultiGzDecoder::new(data).read_to_end(&mut output)?;
+            }
+            Codec::Zstd => match zstd::zstd_safe::get_dict_id_from_frame(data) {
+                Some(id) => {
+                    let dictionary = self.dictionaries.lock().unwrap().by_id.get(&id.get()).cloned();
+                    let dictionary = dictionary.ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, format!("missing zstd dictionary {}", id))
+                    })?;
+                    zstd::Decoder::with_dictionary(data, &dictionary)?.read_to_end(&mut output)?;
+                }
+                None => {
+                    zstd::Decoder::with_buffer(data)?.read_to_end(&mut output)?;
+                }
+            },
+        }
+        Ok(output)
+    }
+
+    /// Returns the dictionary of `name`, training it once enough samples were collected.
+    fn dictionary(&self, name: &str, data: &[u8]) -> Option<Arc<[u8]>> {
+        let config = self.config.dictionary.as_ref()?;
+        let name = safe_file_name(name);
+        let mut dictionaries = self.dictionaries.lock().unwrap();
+
+        if let Some(id) = dictionaries.by_name.get(&name) {
+            return dictionaries.by_id.get(id).cloned();
+        }
+
+        if data.len() > MAX_SAMPLE {
+            return None;
+        }
+
+        let samples = dictionaries.samples.entry(name.clone()).or_default();
+        samples.push(data.to_vec());
+        if samples.len() < config.samples {
+            return None;
+        }
+
+        let samples = std::mem::take(samples);
+        let dictionary: Arc<[u8]> = match zstd::dict::from_samples(&samples, config.size) {
+            Ok(dictionary) => dictionary.into(),
+            Err(e) => {
+                eprintln!("Failed to train a compression dictionary for {}: {}", name, e);
+                return None;
+            }
+        };
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary)?.get();
+
+        // Dictionaries are made of captured bodies, so they're encrypted like them
+        let path = self.dir.join(format!("{}-{}.dict", name, id));
+        let (path, data) = match self.keyring() {
+            Some(keyring) => (PathBuf::from(crypt::path(&path.to_string_lossy())), keyring.encrypt(&dictionary)),
+            None => (path, Ok(dictionary.to_vec())),
+        };
+        if let Err(e) = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, data?)) {
+            // Bodies compressed with a dictionary that isn't saved couldn't be read back
+            eprintln!("Failed to save compression dictionary {:?}: {}", path, e);
+            return None;
+        }
+
+        dictionaries.by_id.insert(id, dictionary.clone());
+        dictionaries.by_name.insert(name, id);
+        Some(dictionary)
+    }
+}
+
+/// Reads the dictionaries saved in `dir`. The most recent one of each host entry is
+/// used for new bodies.
+fn load(dir: &Path, keyring: Option<&Keyring>) -> Dictionaries {
+    let mut dictionaries = Dictionaries::default();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return dictionaries;
+    };
+
+    let mut files = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|path| Some((std::fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    for (_, path) in files {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let (stem, encrypted) = match file_name.strip_suffix(crypt::EXTENSION) {
+            Some(stem) => (stem, true),
+            None => (file_name.as_str(), false),
+        };
+        let Some(stem) = stem.strip_suffix(".dict") else {
+            continue;
+        };
+
+        let dictionary = match (std::fs::read(&path), keyring) {
+            (Ok(data), Some(keyring)) if encrypted => keyring.decrypt(&data),
+            (Ok(_), None) if encrypted => continue,
+            (data, _) => data,
+        };
+        let Ok(dictionary) = dictionary else {
+            eprintln!("Failed to read compression dictionary {:?}", path);
+            continue;
+        };
+        let Some(id) = zstd::zstd_safe::get_dict_id_from_dict(&dictionary) else {
+            continue;
+        };
+
+        if let Some((name, _)) = stem.rsplit_once('-') {
+            dictionaries.by_name.insert(name.to_string(), id.get());
+        }
+        dictionaries.by_id.insert(id.get(), dictionary.into());
+    }
+
+    dictionaries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_streams() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for codec in [Codec::None, Codec::Gzip, Codec::Zstd] {
+            let compressor = Compressor::new(&CompressionConfig { codec, ..Default::default() }, None);
+            let path = compressor.path(&dir.join("capture.jsonl.gz").to_string_lossy());
+            assert_eq!(Codec::from_path(&path), codec);
+
+            let mut encoder = compressor.create(&path).unwrap();
+            encoder.write_all(b"{\"a\":1}\n").unwrap();
+            encoder.flush().unwrap();
+            encoder.write_all(b"{\"b\":2}\n").unwrap();
+            encoder.finish().unwrap().finish().unwrap();
+
+            let mut content = String::new();
+            open(&path, None).unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, "{\"a\":1}\n{\"b\":2}\n");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let config = CompressionConfig {
+            codec: Codec::Zstd,
+            level: None,
+            dictionary: Some(DictionaryConfig {
+                dir: dir.to_string_lossy().into_owned(),
+                samples: 100,
+                size: 4096,
+            }),
+        };
+
+        let body = |index: usize| {
+            format!(r#"{{"id":{},"status":"active","name":"user-{}","tags":["alpha","beta"],"score":{}}}"#, index, index * 7, index % 13)
+        };
+
+        let compressor = Compressor::new(&config, None);
+        let mut compressed = Vec::new();
+        for index in 0..120 {
+            compressed.push(compressor.compress("https://example.com", body(index).as_bytes()).unwrap());
+        }
+        assert!(zstd::zstd_safe::get_dict_id_from_frame(&compressed[0]).is_none());
+        assert!(zstd::zstd_safe::get_dict_id_from_frame(&compressed[119]).is_some());
+        assert!(compressed[119].len() < compressed[0].len());
+
+        // A new compressor, as `udata cat` would create, finds the saved dictionary
+        let reader = Compressor::new(&config, None);
+        for (index, data) in compressed.iter().enumerate() {
+            assert_eq!(reader.decompress(Codec::Zstd, data).unwrap(), body(index).as_bytes());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,14 +2,103 @@
 #![allow(unused_imports)]
 use serde::Deserialize;
 
+use crate::blobs::DedupConfig;
+use crate::compress::CompressionConfig;
+use crate::crypt::EncryptionConfig;
+use crate::har::HarConfig;
+use crate::inject::InjectRule;
+use crate::jsonl::JsonlConfig;
+use crate::layout::LayoutConfig;
+use crate::limits::Limits;
+use crate::pipeline::ProcessorConfig;
+use crate::retention::RetentionConfig;
+use crate::search::SearchConfig;
+use crate::store::StoreConfig;
+use crate::stream::StreamMode;
+use crate::warc::WarcConfig;
+use crate::xhr::RequestSwizzle;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub version: u32,
     pub host: Vec<HostEntry>,
+    /// Writes captured traffic to a HAR file
+    #[serde(default)]
+    pub har: Option<HarConfig>,
+    /// Stores captured exchanges in a SQLite database
+    #[serde(default)]
+    pub store: Option<StoreConfig>,
+    /// Indexes captured URLs and bodies for `udata search`
+    #[serde(default)]
+    pub search: Option<SearchConfig>,
+    /// Archives captured exchanges as WARC records
+    #[serde(default)]
+    pub warc: Option<WarcConfig>,
+    /// Writes each exchange to its own files, see `layout.rs`
+    #[serde(default)]
+    pub layout: Option<LayoutConfig>,
+    /// Compresses the files written by the outputs above
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    /// Encrypts the files written by the outputs above, see `crypt.rs`
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Deletes old output files to bound their disk usage, see `retention.rs`
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+    /// Exposes the swizzle functions to page JavaScript as `window.udata`, see
+    /// `binding.rs`
+    #[serde(default)]
+    pub js_binding: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct HostEntry {
     pub host: String,
     pub xhr: String,
-}
\ No newline at end of file
+    /// Splits matching responses into events instead of capturing raw chunks.
+    /// When unset, the framing is inferred from the response mime type.
+    #[serde(default)]
+    pub stream: Option<StreamMode>,
+    /// Scripts and stylesheets injected into HTML documents served under `host`
+    #[serde(default)]
+    pub inject: Vec<InjectRule>,
+    /// Detects swizzled response bodies and captures their decoded plaintext.
+    /// Only applies to the default pipeline; custom pipelines list `decode` explicitly.
+    #[serde(default)]
+    pub decode: bool,
+    /// Swizzles outgoing request bodies, for hosts that expect that encoding: `raw`
+    /// sends the swizzled bytes, the name of a `swizzle::Encoding` (`base64`, `hex`,
+    /// ...) sends them as text. Odd-length bodies gain a padding byte, a copy of their
+    /// first byte, which the host has to drop, see `swizzle::swizzle`
+    #[serde(default)]
+    pub swizzle_requests: Option<RequestSwizzle>,
+    /// Secret of the keyed swizzle variant used by this host, for both `decode` and
+    /// `swizzle_requests`, see `swizzle::swizzle_keyed`
+    #[serde(default)]
+    pub swizzle_key: Option<String>,
+    /// Ordered processor chain applied to captured responses, see `pipeline.rs`
+    #[serde(default)]
+    pub processors: Vec<ProcessorConfig>,
+    /// Size limits and sampling applied to captured responses
+    #[serde(default)]
+    pub limits: Limits,
+    /// Writes capture records to rotating JSONL files instead of stderr
+    #[serde(default)]
+    pub jsonl: Option<JsonlConfig>,
+    /// Stores captured bodies once per distinct content, see `blobs.rs`
+    #[serde(default)]
+    pub dedup: Option<DedupConfig>,
+}
+
+impl HostEntry {
+    /// Returns `true` if `url` is an XHR endpoint of this host entry.
+    pub fn matches_xhr(&self, url: &str) -> bool {
+        url.contains(&self.xhr)
+    }
+
+    /// Returns `true` if `url` is a document served under this host entry.
+    pub fn matches_document(&self, url: &str) -> bool {
+        !self.host.is_empty() && url.starts_with(&self.host)
+    }
+}
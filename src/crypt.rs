@@ -0,0 +1,583 @@
+//! Encryption of the capture files at rest.
+//!
+//! With an `encryption` section in the configuration, every file the capture writes
+//! is encrypted and gets an `.enc` extension. Each file has its own random data key,
+//! which encrypts the content with XChaCha20-Poly1305 in chunks of up to 64 KiB. The
+//! data key is stored in the file header, wrapped with a key derived from either:
+//!
+//! - a keyfile, through HKDF-SHA256
+//! - a passphrase read from an environment variable, through Argon2id
+//!
+//! ```text
+//! magic "UDATAENC" | version | kdf | salt (16) | wrap nonce (24) | wrapped data key (48)
+//! | nonce prefix (16) | chunks: length (u32, high bit set on the last one) | ciphertext
+//! ```
+//!
+//! Chunk nonces are the prefix followed by the chunk index, and the last chunk is
+//! authenticated as such, so reordered or truncated files fail to decrypt.
+//!
+//! Rotating keys only rewrites the file headers: `udata rekey` unwraps the data key
+//! with a `previous` key and wraps it again with the current one. `udata decrypt`
+//! and `udata cat` accept any of the configured keys.
+//!
+//! This is unrelated to `swizzle.rs`, which undoes an obfuscation applied by the
+//! captured hosts and provides no confidentiality.
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::sync::Mutex;
+
+/// Extension added to encrypted files.
+pub const EXTENSION: &str = ".enc";
+
+const MAGIC: &[u8; 8] = b"UDATAENC";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 114;
+/// Part of the header that authenticates the wrapped data key
+const WRAP_AAD_LEN: usize = 26;
+const CHUNK: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const FINAL: u32 = 1 << 31;
+
+const KDF_KEYFILE: u8 = 1;
+const KDF_PASSPHRASE: u8 = 2;
+
+/// Encryption settings, as written in the configuration.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct EncryptionConfig {
+    /// Key new files are encrypted with
+    #[serde(flatten)]
+    pub key: KeySource,
+    /// Keys that older files may be encrypted with
+    #[serde(default)]
+    pub previous: Vec<KeySource>,
+}
+
+/// Where a key comes from. Exactly one of the fields is set.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct KeySource {
+    /// File holding at least 32 random bytes
+    #[serde(default)]
+    pub keyfile: Option<String>,
+    /// Environment variable holding a passphrase
+    #[serde(default)]
+    pub passphrase_env: Option<String>,
+}
+
+/// Secret material a key is derived from.
+enum Secret {
+    Keyfile(Vec<u8>),
+    Passphrase(Vec<u8>),
+}
+
+impl Secret {
+    fn load(source: &KeySource) -> Result<Self, String> {
+        match (source.keyfile.as_ref(), source.passphrase_env.as_ref()) {
+            (Some(path), None) => {
+                let key = std::fs::read(path).map_err(|e| format!("Failed to read keyfile {}: {}", path, e))?;
+                if key.len() < 32 {
+                    return Err(format!("Keyfile {} is shorter than 32 bytes", path));
+                }
+                Ok(Secret::Keyfile(key))
+            }
+            (None, Some(name)) => match std::env::var(name) {
+                Ok(passphrase) if !passphrase.is_empty() => Ok(Secret::Passphrase(passphrase.into_bytes())),
+                _ => Err(format!("Environment variable {} doesn't hold a passphrase", name)),
+            },
+            _ => Err(String::from("A key needs either `keyfile` or `passphrase_env`")),
+        }
+    }
+
+    fn kdf(&self) -> u8 {
+        match self {
+            Secret::Keyfile(_) => KDF_KEYFILE,
+            Secret::Passphrase(_) => KDF_PASSPHRASE,
+        }
+    }
+
+    /// Derives the key that wraps data keys.
+    fn derive(&self, salt: &[u8]) -> io::Result<[u8; 32]> {
+        let mut key = [0; 32];
+        match self {
+            Secret::Keyfile(material) => Hkdf::<Sha256>::new(Some(salt), material)
+                .expand(b"udata key wrapping", &mut key)
+                .map_err(|e| io::Error::other(e.to_string()))?,
+            Secret::Passphrase(passphrase) => Argon2::default()
+                .hash_password_into(passphrase, salt, &mut key)
+                .map_err(|e| io::Error::other(e.to_string()))?,
+        }
+        Ok(key)
+    }
+}
+
+/// Wrapping keys by index of their secret and salt.
+type WrappingKeys = HashMap<(usize, [u8; 16]), [u8; 32]>;
+
+/// The configured keys, and the wrapping key derived from the current one.
+pub struct Keyring {
+    /// Current key first, then the previous ones
+    secrets: Vec<Secret>,
+    /// Salt of the wrapping key used for new files, derived once per session
+    salt: Option<[u8; 16]>,
+    /// Wrapping keys derived so far
+    derived: Mutex<WrappingKeys>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring").field("keys", &self.secrets.len()).finish()
+    }
+}
+
+impl Keyring {
+    /// Loads the configured keys and derives the wrapping key of new files.
+    pub fn new(config: &EncryptionConfig) -> Result<Self, String> {
+        let mut keyring = Self::open(std::iter::once(&config.key).chain(&config.previous))?;
+
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+        keyring.wrapping_key(0, salt).map_err(|e| format!("Failed to derive the encryption key: {}", e))?;
+        keyring.salt = Some(salt);
+
+        Ok(keyring)
+    }
+
+    /// Loads keys for reading only.
+    pub fn open<'a>(sources: impl IntoIterator<Item = &'a KeySource>) -> Result<Self, String> {
+        let secrets = sources.into_iter().map(Secret::load).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            secrets,
+            salt: None,
+            derived: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Wraps `writer` in an encrypting writer, writing the file header right away.
+    pub fn writer<W: Write>(&self, mut writer: W) -> io::Result<EncryptWriter<W>> {
+        let salt = self
+            .salt
+            .ok_or_else(|| io::Error::other("no key to encrypt with"))?;
+
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut prefix = [0; 16];
+        OsRng.fill_bytes(&mut prefix);
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.push(self.secrets[0].kdf());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&self.wrap(0, salt, &header, &data_key)?);
+        header.extend_from_slice(&prefix);
+        writer.write_all(&header)?;
+
+        Ok(EncryptWriter {
+            inner: writer,
+            cipher: XChaCha20Poly1305::new(&data_key),
+            prefix,
+            counter: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Reads the header of an encrypted file and returns a reader of its content.
+    pub fn reader<R: Read>(&self, mut reader: R) -> io::Result<DecryptReader<R>> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let data_key = self.unwrap(&header)?;
+
+        Ok(DecryptReader {
+            inner: reader,
+            cipher: XChaCha20Poly1305::new(&data_key.into()),
+            prefix: header[HEADER_LEN - 16..].try_into().unwrap(),
+            counter: 0,
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+        })
+    }
+
+    /// Encrypts a buffer on its own.
+    pub fn encrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut writer = self.writer(Vec::with_capacity(data.len() + HEADER_LEN + 32))?;
+        writer.write_all(data)?;
+        writer.finish()
+    }
+
+    /// Decrypts a buffer returned by `encrypt`.
+    pub fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::with_capacity(data.len());
+        self.reader(data)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    /// Wraps the data key of an encrypted file again with the current key.
+    ///
+    /// Only the header changes, the content is copied as it is. The file is written
+    /// under a temporary name and renamed over the original, so an interrupted rekey
+    /// leaves the original untouched. Its modification time is kept for retention.
+    pub fn rekey(&self, path: &str) -> io::Result<()> {
+        let salt = self
+            .salt
+            .ok_or_else(|| io::Error::other("no key to encrypt with"))?;
+
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header)?;
+        let data_key = self.unwrap(&header)?;
+
+        header[9] = self.secrets[0].kdf();
+        header[10..26].copy_from_slice(&salt);
+        let wrapped = self.wrap(0, salt, &header[..WRAP_AAD_LEN], &data_key.into())?;
+        header[WRAP_AAD_LEN..HEADER_LEN - 16].copy_from_slice(&wrapped);
+
+        let temp = format!("{}.{}.tmp", path, uuid::Uuid::new_v4());
+        let copied = (|| {
+            let mut copy = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+            copy.write_all(&header)?;
+            io::copy(&mut file, &mut copy)?;
+            copy.set_permissions(metadata.permissions())?;
+            copy.set_modified(metadata.modified()?)?;
+            copy.sync_all()
+        })();
+        drop(file);
+
+        let result = copied.and_then(|()| std::fs::rename(&temp, path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Returns the wrapping key of a secret for a salt, deriving it on first use.
+    fn wrapping_key(&self, index: usize, salt: [u8; 16]) -> io::Result<[u8; 32]> {
+        if let Some(key) = self.derived.lock().unwrap().get(&(index, salt)) {
+            return Ok(*key);
+        }
+
+        let key = self.secrets[index].derive(&salt)?;
+        self.derived.lock().unwrap().insert((index, salt), key);
+        Ok(key)
+    }
+
+    /// Encrypts a data key with the wrapping key of a secret.
+    ///
+    /// # Returns
+    /// The wrap nonce followed by the wrapped key.
+    fn wrap(&self, index: usize, salt: [u8; 16], aad: &[u8], data_key: &chacha20poly1305::Key) -> io::Result<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new(&self.wrapping_key(index, salt)?.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let wrapped = cipher
+            .encrypt(&nonce, Payload { msg: data_key, aad })
+            .map_err(|_| io::Error::other("failed to wrap the data key"))?;
+
+        let mut output = nonce.to_vec();
+        output.extend_from_slice(&wrapped);
+        Ok(output)
+    }
+
+    /// Finds a key that decrypts the data key of a file header.
+    fn unwrap(&self, header: &[u8; HEADER_LEN]) -> io::Result<[u8; 32]> {
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an encrypted capture file"));
+        }
+        if header[8] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported encryption version {}", header[8]),
+            ));
+        }
+
+        let kdf = header[9];
+        let salt: [u8; 16] = header[10..26].try_into().unwrap();
+        let nonce = XNonce::from_slice(&header[26..50]);
+
+        for (index, secret) in self.secrets.iter().enumerate() {
+            if secret.kdf() != kdf {
+                continue;
+            }
+
+            let cipher = XChaCha20Poly1305::new(&self.wrapping_key(index, salt)?.into());
+            let payload = Payload {
+                msg: &header[50..HEADER_LEN - 16],
+                aad: &header[..WRAP_AAD_LEN],
+            };
+            if let Ok(data_key) = cipher.decrypt(nonce, payload)
+                && let Ok(data_key) = data_key.try_into()
+            {
+                return Ok(data_key);
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "none of the configured keys decrypts this file"))
+    }
+}
+
+/// Returns the path of the encrypted version of a file.
+pub fn path(path: &str) -> String {
+    format!("{}{}", path, EXTENSION)
+}
+
+/// Returns `true` if `path` names an encrypted file.
+pub fn is_encrypted(path: impl AsRef<std::path::Path>) -> bool {
+    path.as_ref().to_string_lossy().ends_with(EXTENSION)
+}
+
+/// Encrypts everything written to it in authenticated chunks. `finish` must be
+/// called to write the last chunk, without it the file doesn't decrypt.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; 16],
+    counter: u64,
+    /// Plaintext not encrypted yet
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Writes the last chunk.
+    ///
+    /// # Returns
+    /// The underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.chunk(&buffer, true)?;
+        Ok(self.inner)
+    }
+
+    fn chunk(&mut self, data: &[u8], last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.prefix, self.counter);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: data, aad: &[last as u8] })
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        let length = ciphertext.len() as u32 | if last { FINAL } else { 0 };
+        self.inner.write_all(&length.to_be_bytes())?;
+        self.inner.write_all(&ciphertext)?;
+        self.counter += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        // Keep the last full chunk buffered, it might be the final one
+        while self.buffer.len() > CHUNK {
+            let rest = self.buffer.split_off(CHUNK);
+            let chunk = std::mem::replace(&mut self.buffer, rest);
+            self.chunk(&chunk, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Encrypts the buffered data as a chunk of its own, so that it can be read back.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = std::mem::take(&mut self.buffer);
+            self.chunk(&buffer, false)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a file written by `EncryptWriter`.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; 16],
+    counter: u64,
+    plaintext: Vec<u8>,
+    /// Position of the next byte to read in `plaintext`
+    position: usize,
+    /// Whether the last chunk was read
+    done: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut length = [0; 4];
+        self.inner.read_exact(&mut length).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), "encrypted file is truncated"),
+            _ => e,
+        })?;
+        let length = u32::from_be_bytes(length);
+        let last = length & FINAL != 0;
+        let length = (length & !FINAL) as usize;
+        if length > CHUNK + TAG_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid encrypted chunk"));
+        }
+
+        let mut ciphertext = vec![0; length];
+        self.inner.read_exact(&mut ciphertext)?;
+
+        let nonce = chunk_nonce(&self.prefix, self.counter);
+        self.plaintext = self
+            .cipher
+            .decrypt(&nonce, Payload { msg: &ciphertext, aad: &[last as u8] })
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "encrypted file is corrupted"))?;
+        self.position = 0;
+        self.counter += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+
+        let length = buf.len().min(self.plaintext.len() - self.position);
+        buf[..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; 16], counter: u64) -> XNonce {
+    let mut nonce = [0; 24];
+    nonce[..16].copy_from_slice(prefix);
+    nonce[16..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+/// A file written by the capture, encrypted when a keyring is configured.
+pub enum Sink {
+    Plain(BufWriter<File>),
+    Encrypted(EncryptWriter<BufWriter<File>>),
+}
+
+impl Sink {
+    /// Creates a file, encrypting it if `keyring` is set.
+    pub fn create(path: &str, keyring: Option<&Keyring>) -> io::Result<Self> {
+        Self::wrap(File::create(path)?, keyring)
+    }
+
+    /// Creates a file like `create`, failing with `AlreadyExists` instead of truncating
+    /// an existing one.
+    pub fn create_new(path: &str, keyring: Option<&Keyring>) -> io::Result<Self> {
+        Self::wrap(File::create_new(path)?, keyring)
+    }
+
+    fn wrap(file: File, keyring: Option<&Keyring>) -> io::Result<Self> {
+        let file = BufWriter::new(file);
+        Ok(match keyring {
+            Some(keyring) => Sink::Encrypted(keyring.writer(file)?),
+            None => Sink::Plain(file),
+        })
+    }
+
+    /// Terminates the file and syncs it to disk.
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            Sink::Plain(file) => file,
+            Sink::Encrypted(writer) => writer.finish()?,
+        };
+        file.flush()?;
+        file.get_ref().sync_all()
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(file) => file.write(buf),
+            Sink::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sink::Plain(_) => f.write_str("Sink::Plain"),
+            Sink::Encrypted(_) => f.write_str("Sink::Encrypted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encryption() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let keyfile = |name: &str| {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            let mut key = [0; 32];
+            OsRng.fill_bytes(&mut key);
+            std::fs::write(&path, key).unwrap();
+            KeySource {
+                keyfile: Some(path),
+                passphrase_env: None,
+            }
+        };
+        let (old, new) = (keyfile("old.key"), keyfile("new.key"));
+
+        let keyring = Keyring::new(&EncryptionConfig {
+            key: old.clone(),
+            previous: Vec::new(),
+        })
+        .unwrap();
+
+        let data = (0..CHUNK * 2 + 10).map(|index| index as u8).collect::<Vec<_>>();
+        let encrypted = keyring.encrypt(&data).unwrap();
+        assert_eq!(keyring.decrypt(&encrypted).unwrap(), data);
+
+        // Flushed chunks are readable, a missing last chunk is detected
+        let mut writer = keyring.writer(Vec::new()).unwrap();
+        writer.write_all(b"first").unwrap();
+        writer.flush().unwrap();
+        let partial = writer.inner.clone();
+        let mut read = Vec::new();
+        assert!(keyring.reader(partial.as_slice()).unwrap().read_to_end(&mut read).is_err());
+        assert_eq!(read, b"first");
+
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN + 10] ^= 1;
+        assert!(keyring.decrypt(&tampered).is_err());
+
+        // Rotation rewrites the header, after which only the new key is needed
+        let path = dir.join("capture.jsonl.enc").to_string_lossy().into_owned();
+        std::fs::write(&path, &encrypted).unwrap();
+        let rotated = Keyring::new(&EncryptionConfig {
+            key: new.clone(),
+            previous: vec![old],
+        })
+        .unwrap();
+        rotated.rekey(&path).unwrap();
+        let names = std::fs::read_dir(&dir).unwrap().flatten().map(|entry| entry.file_name()).collect::<Vec<_>>();
+        assert!(names.iter().all(|name| !name.to_string_lossy().ends_with(".tmp")));
+
+        let reader = Keyring::open([&new]).unwrap();
+        assert_eq!(reader.decrypt(&std::fs::read(&path).unwrap()).unwrap(), data);
+        assert!(keyring.decrypt(&std::fs::read(&path).unwrap()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Detection and decoding of swizzled payloads.
+//!
+//! Some hosts obfuscate their payloads with the scheme implemented in `swizzle.rs`,
+//! either as raw bytes or wrapped in a text encoding, optionally keyed with a secret
+//! shared with the host, or sealed in a swizzle container. This module recognises
+//! such bodies so the decoded plaintext can be captured next to the original.
+use crate::swizzle::{Encoding, SwizzleError, is_container, try_unswizzle, try_unswizzle_keyed, unseal};
+
+/// Encoding detected on a captured body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Swizzled bytes in a text encoding, see `swizzle::encoded_swizzle`
+    Encoded(Encoding),
+    /// Raw swizzled bytes, see `swizzle::swizzle`
+    Swizzle,
+    /// Bytes swizzled with a key, in a text encoding, see `swizzle::swizzle_keyed`
+    KeyedEncoded(Encoding),
+    /// Raw bytes swizzled with a key, see `swizzle::swizzle_keyed`
+    KeyedSwizzle,
+    /// Swizzle container, keyed or not, see `swizzle::seal`
+    Container,
+}
+
+impl Scheme {
+    /// Returns the name used for this scheme in capture records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Encoded(Encoding::Base64) => "base64-swizzle",
+            Scheme::Encoded(Encoding::Base64NoPad) => "base64-nopad-swizzle",
+            Scheme::Encoded(Encoding::Base64Url) => "base64url-swizzle",
+            Scheme::Encoded(Encoding::Base64UrlNoPad) => "base64url-nopad-swizzle",
+            Scheme::Encoded(Encoding::Hex) => "hex-swizzle",
+            Scheme::Encoded(Encoding::Base32) => "base32-swizzle",
+            Scheme::Swizzle => "swizzle",
+            Scheme::KeyedEncoded(Encoding::Base64) => "base64-keyed-swizzle",
+            Scheme::KeyedEncoded(Encoding::Base64NoPad) => "base64-nopad-keyed-swizzle",
+            Scheme::KeyedEncoded(Encoding::Base64Url) => "base64url-keyed-swizzle",
+            Scheme::KeyedEncoded(Encoding::Base64UrlNoPad) => "base64url-nopad-keyed-swizzle",
+            Scheme::KeyedEncoded(Encoding::Hex) => "hex-keyed-swizzle",
+            Scheme::KeyedEncoded(Encoding::Base32) => "base32-keyed-swizzle",
+            Scheme::KeyedSwizzle => "keyed-swizzle",
+            Scheme::Container => "swizzle-container",
+        }
+    }
+}
+
+/// Attempts to decode a swizzled body.
+///
+/// Text encodings are tried first, in the order of `Encoding::ALL`, since an encoded
+/// body is itself valid text. A candidate is only accepted if the decoded output
+/// looks like plaintext. With a key, the keyed variant is tried before the unkeyed
+/// one.
+///
+/// # Parameters
+/// - `body`: The captured body.
+/// - `key`: Secret of the keyed variant, see `HostEntry::swizzle_key`.
+///
+/// # Returns
+/// The detected scheme and the decoded plaintext, or `None` if the body doesn't look
+/// swizzled.
+pub fn detect(body: &[u8], key: Option<&[u8]>) -> Option<(Scheme, Vec<u8>)> {
+    // Containers identify themselves, and their checksum rules out false positives
+    if is_container(body) {
+        return unseal(body, key).ok().map(|plain| (Scheme::Container, plain));
+    }
+
+    if let Some(key) = key {
+        let keyed = detect_with(body, |data| try_unswizzle_keyed(data, key), Scheme::KeyedEncoded, Scheme::KeyedSwizzle);
+        if keyed.is_some() {
+            return keyed;
+        }
+    }
+
+    detect_with(body, try_unswizzle, Scheme::Encoded, Scheme::Swizzle)
+}
+
+/// Attempts to decode a body with `unswizzle`, in a text encoding or raw. Bodies it
+/// rejects aren't swizzled.
+fn detect_with(
+    body: &[u8],
+    unswizzle: impl Fn(&[u8]) -> Result<Vec<u8>, SwizzleError>,
+    encoded: fn(Encoding) -> Scheme,
+    raw: Scheme,
+) -> Option<(Scheme, Vec<u8>)> {
+    let trimmed = body.trim_ascii();
+    let trimmed = trimmed
+        .strip_prefix(b"\"")
+        .and_then(|t| t.strip_suffix(b"\""))
+        .unwrap_or(trimmed);
+
+    if trimmed.len() >= 4
+        && let Ok(text) = std::str::from_utf8(trimmed)
+    {
+        let found = Encoding::candidates(text).find_map(|(encoding, data)| {
+            unswizzle(&data)
+                .ok()
+                .filter(|plain| looks_like_plaintext(plain))
+                .map(|plain| (encoded(encoding), plain))
+        });
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    if !body.is_empty()
+        && !looks_like_plaintext(body)
+        && let Ok(plain) = unswizzle(body)
+        && looks_like_plaintext(&plain)
+    {
+        return Some((raw, plain));
+    }
+
+    None
+}
+
+/// Heuristic check for human readable data.
+///
+/// The data must be valid UTF-8 with almost no control characters.
+pub fn looks_like_plaintext(data: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return false;
+    };
+
+    let mut total = 0usize;
+    let mut control = 0usize;
+    for c in text.chars() {
+        total += 1;
+        if c.is_control() && !matches!(c, '\t' | '\n' | '\r') {
+            control += 1;
+        }
+    }
+
+    total > 0 && control * 50 <= total
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::swizzle::{base64_swizzle, encoded_swizzle, swizzle, swizzle_keyed};
+
+    #[test]
+    fn test_detect() {
+        let data = b"{\"user\":\"alice\",\"items\":[1,2,3]}";
+
+        let (scheme, plain) = detect(base64_swizzle(data).as_bytes(), None).unwrap();
+        assert_eq!(scheme, Scheme::Encoded(Encoding::Base64));
+        assert_eq!(plain, data);
+
+        for encoding in Encoding::ALL {
+            let (scheme, plain) = detect(encoded_swizzle(data, encoding).as_bytes(), None).unwrap();
+            assert_eq!(scheme, Scheme::Encoded(encoding));
+            assert_eq!(plain, data);
+        }
+
+        let (scheme, plain) = detect(&swizzle(data), None).unwrap();
+        assert_eq!(scheme, Scheme::Swizzle);
+        assert_eq!(plain, data);
+
+        assert!(detect(data, None).is_none());
+
+        let keyed = swizzle_keyed(data, b"secret");
+        let (scheme, plain) = detect(&keyed, Some(b"secret")).unwrap();
+        assert_eq!(scheme, Scheme::KeyedSwizzle);
+        assert_eq!(plain, data);
+
+        let (scheme, plain) = detect(Encoding::Base64.encode(&keyed).as_bytes(), Some(b"secret")).unwrap();
+        assert_eq!(scheme, Scheme::KeyedEncoded(Encoding::Base64));
+        assert_eq!(plain, data);
+
+        let (scheme, plain) = detect(Encoding::Hex.encode(&keyed).as_bytes(), Some(b"secret")).unwrap();
+        assert_eq!(scheme, Scheme::KeyedEncoded(Encoding::Hex));
+        assert_eq!(plain, data);
+
+        let (scheme, plain) = detect(&crate::swizzle::seal(b"\x00binary", Some(b"secret")), Some(b"secret")).unwrap();
+        assert_eq!(scheme, Scheme::Container);
+        assert_eq!(plain, b"\x00binary");
+
+        // Unkeyed bodies are still recognised on hosts with a key
+        assert_eq!(detect(&swizzle(data), Some(b"secret")).unwrap().0, Scheme::Swizzle);
+    }
+}
@@ -11,7 +11,10 @@ use cef::sys::cef_response_filter_status_t::{
 };
 use std::sync::{Arc, Mutex};
 
+use crate::capture::SharedExchange;
 use crate::config::Config;
+use crate::session::Session;
+use crate::inject::Injector;
 
 //
 // ResponseFilter
@@ -40,6 +43,14 @@ pub struct DemoResponseFilter {
     pub uuid: uuid::Uuid,
     /// General configuration
     pub config: Option<Config>,
+    /// Mime type of the response
+    pub mime_type: String,
+    /// HTML rewriter, present when the response is a document with injection rules
+    pub injector: Arc<Mutex<Option<Injector>>>,
+    /// Capture state shared with the resource request handler
+    pub exchange: SharedExchange,
+    /// Capture state shared by every request
+    pub session: Arc<Session>,
 }
 
 impl DemoResponseFilter {
@@ -48,20 +59,108 @@ impl DemoResponseFilter {
     /// # Parameters
     /// - `request_headers`: The headers of the original request, useful for context.
     /// - `url`: The URL of the request, used for logging and conditional processing.
+    /// - `mime_type`: The mime type of the response, used to select the processors.
+    /// - `exchange`: Capture state shared with the resource request handler.
+    /// - `session`: Capture state shared by every request, used for sampling.
     ///
     /// # Returns
     /// A new `ResponseFilter` instance wrapping the `DemoResponseFilter` implementation.
     /// ```
-    pub fn new(request_headers: CefStringMultimap, config: Option<Config>, url: String) -> ResponseFilter {
+    pub fn new(
+        request_headers: CefStringMultimap,
+        config: Option<Config>,
+        url: String,
+        mime_type: String,
+        exchange: SharedExchange,
+        session: Arc<Session>,
+    ) -> ResponseFilter {
+        let host = config.as_ref().and_then(|config| {
+            config.host.iter().find(|host| host.matches_xhr(&url)).cloned()
+        });
+
+        let injector = config.as_ref().filter(|_| {
+            mime_type.eq_ignore_ascii_case("text/html")
+        }).and_then(|config| {
+            config.host.iter().find(|host| {
+                !host.inject.is_empty() && host.matches_document(&url)
+            })
+        }).and_then(|host| Injector::new(&host.inject));
+
+        let uuid = uuid::Uuid::new_v4();
+
+        if let Ok(mut exchange) = exchange.lock() {
+            exchange.uuid = uuid;
+            exchange.host = host;
+            exchange.url = url.clone();
+            exchange.mime_type = mime_type.clone();
+            exchange.start(&session);
+        }
+
         ResponseFilter::new(Self {
             object: std::ptr::null_mut(),
             buffer: Arc::new(Mutex::new(Vec::new())),
             request_headers,
             url,
-            uuid: uuid::Uuid::new_v4(),
+            uuid,
             config,
+            mime_type,
+            injector: Arc::new(Mutex::new(injector)),
+            exchange,
+            session,
         })
     }
+
+    /// Runs a chunk of response data through the capture pipeline.
+    ///
+    /// The pipeline is only present if the URL matched a configured host.
+    ///
+    /// # Parameters
+    /// - `data`: The chunk of response data that was read from the input buffer.
+    fn capture(&self, data: &[u8]) {
+        if let Ok(mut exchange) = self.exchange.lock() {
+            exchange.chunk(data);
+        }
+    }
+
+    /// Filters a document response through the HTML injector.
+    ///
+    /// All input is consumed on every call. The rewritten output is queued in `buffer`
+    /// and drained into `data_out` as space allows; `RESPONSE_FILTER_NEED_MORE_DATA` is
+    /// returned while output is queued or input is held back by the injector, so CEF
+    /// calls again with an empty input buffer once the response is complete.
+    fn filter_injected(
+        &self,
+        injector: &mut Injector,
+        data_in: Option<&mut Vec<u8>>,
+        data_in_read: &mut usize,
+        data_out: Option<&mut Vec<u8>>,
+        data_out_written: &mut usize,
+    ) -> ResponseFilterStatus {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        match data_in {
+            Some(data_in) if !data_in.is_empty() => {
+                self.capture(data_in);
+                buffer.extend(injector.push(data_in));
+                *data_in_read = data_in.len();
+            }
+            // An empty input buffer means the response is complete
+            _ => buffer.extend(injector.finish()),
+        }
+
+        if let Some(data_out) = data_out {
+            let bytes_to_copy = std::cmp::min(buffer.len(), data_out.len());
+            data_out[..bytes_to_copy].copy_from_slice(&buffer[..bytes_to_copy]);
+            buffer.drain(..bytes_to_copy);
+            *data_out_written = bytes_to_copy;
+        }
+
+        if buffer.is_empty() && !injector.is_pending() {
+            ResponseFilterStatus::from(RESPONSE_FILTER_DONE)
+        } else {
+            ResponseFilterStatus::from(RESPONSE_FILTER_NEED_MORE_DATA)
+        }
+    }
 }
 
 impl ImplResponseFilter for DemoResponseFilter {
@@ -117,6 +216,11 @@ impl ImplResponseFilter for DemoResponseFilter {
         let data_out_written = _data_out_written.unwrap_or(&mut binding2);
         *data_out_written = 0;
 
+        let mut injector = self.injector.lock().unwrap();
+        if let Some(injector) = injector.as_mut() {
+            return self.filter_injected(injector, _data_in, data_in_read, _data_out, data_out_written);
+        }
+
         if _data_in.is_none() {
             return ResponseFilterStatus::from(RESPONSE_FILTER_DONE);
         }
@@ -147,15 +251,7 @@ impl ImplResponseFilter for DemoResponseFilter {
         // eprintln!("data_out_written = {}", *data_out_written);
         // eprintln!("data_in_read = {}", *data_in_read);
 
-        self.config.as_ref().map(|config| {
-            config.host.iter().find(|host| {
-                self.url.contains(&host.xhr)  
-            }).map(|host| {
-                eprintln!("Host match: {}", host.xhr);
-                let buffer = data_in.clone();
-                eprintln!("{}\n\n\t<<- -->\n\n", String::from_utf8(buffer).unwrap_or(String::from("")));
-            });
-        });
+        self.capture(&data_in[..bytes_to_copy]);
 
         if bytes_to_copy == data_in.len() {
             ResponseFilterStatus::from(RESPONSE_FILTER_DONE)
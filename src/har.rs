@@ -0,0 +1,580 @@
+//! HAR 1.2 export of captured traffic.
+//!
+//! Captured exchanges are written as HTTP Archive entries so they can be opened in
+//! browser devtools and HAR viewers. Each entry combines the request recorded by
+//! `DemoResourceRequestHandler` with the body seen by `DemoResponseFilter`; redirects
+//! followed on the way become entries of their own. Entries are grouped into pages by
+//! the URL of the main frame that issued them.
+//!
+//! Two output modes are supported:
+//! - `final`: entries are kept in memory and the archive is written at shutdown
+//! - `stream`: entries are appended to the file as they complete, flushed at most
+//!   once per `FLUSH_INTERVAL`, and the archive is closed at shutdown
+//!
+//! With `compression` or `encryption` configured, the file gets a `.gz`/`.zst` and
+//! `.enc` extension.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::time::Instant;
+
+use crate::capture::{Exchange, RequestInfo, ResponseInfo, encode_body};
+use crate::compress::{Compressor, Encoder, FLUSH_INTERVAL};
+use crate::crypt::Sink;
+use std::sync::Arc;
+
+/// HAR output as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HarConfig {
+    /// Path of the HAR file
+    pub path: String,
+    /// When entries are written to the file
+    #[serde(default)]
+    pub mode: HarMode,
+}
+
+/// When entries are written to the HAR file.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HarMode {
+    /// Write the whole archive at shutdown
+    #[default]
+    Final,
+    /// Append entries as they complete
+    Stream,
+}
+
+/// Root of a HAR document.
+#[derive(Debug, Serialize)]
+pub struct Har {
+    pub log: Log,
+}
+
+/// The `log` object of a HAR document.
+#[derive(Debug, Serialize)]
+pub struct Log {
+    pub version: &'static str,
+    pub creator: Creator,
+    pub pages: Vec<Page>,
+    pub entries: Vec<Entry>,
+}
+
+/// Application that created the archive.
+#[derive(Debug, Serialize, Clone)]
+pub struct Creator {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+impl Default for Creator {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+/// A page, grouping the entries issued while it was loaded.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Page {
+    pub started_date_time: DateTime<Utc>,
+    pub id: String,
+    pub title: String,
+    pub page_timings: PageTimings,
+}
+
+/// Page load timings, which aren't known to the capture.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTimings {
+    pub on_content_load: f64,
+    pub on_load: f64,
+}
+
+/// A single request and its response.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pageref: Option<String>,
+    pub started_date_time: DateTime<Utc>,
+    /// Total time of the request in milliseconds
+    pub time: f64,
+    pub request: Request,
+    pub response: Response,
+    pub cache: Cache,
+    pub timings: Timings,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub http_version: &'static str,
+    pub cookies: Vec<NameValue>,
+    pub headers: Vec<NameValue>,
+    pub query_string: Vec<NameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<PostData>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: i32,
+    pub status_text: String,
+    pub http_version: &'static str,
+    pub cookies: Vec<NameValue>,
+    pub headers: Vec<NameValue>,
+    pub content: Content,
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+/// A header, cookie or query string parameter.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct NameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    pub mime_type: String,
+    pub text: String,
+    pub params: Vec<NameValue>,
+    /// Set to `base64` when the body isn't valid UTF-8 (custom field)
+    #[serde(rename = "_encoding", skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    pub size: i64,
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Cache usage, which isn't known to the capture.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Cache {}
+
+/// Request timings in milliseconds, `-1` when not known.
+#[derive(Debug, Serialize, Clone)]
+pub struct Timings {
+    pub blocked: f64,
+    pub dns: f64,
+    pub connect: f64,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+    pub ssl: f64,
+}
+
+/// HTTP version reported for every message, since CEF doesn't expose it.
+const HTTP_VERSION: &str = "unknown";
+
+/// Collects the entries of a session and writes them to a HAR file.
+#[derive(Debug)]
+pub struct HarWriter {
+    config: HarConfig,
+    compressor: Arc<Compressor>,
+    /// Path of the file, with the compression and encryption extensions
+    path: String,
+    pages: Vec<Page>,
+    entries: Vec<Entry>,
+    /// Open file, in `stream` mode
+    file: Option<Encoder<Sink>>,
+    /// Number of entries appended to the file
+    written: usize,
+    /// Time of the last flush, in `stream` mode
+    flushed: Instant,
+}
+
+impl HarWriter {
+    /// Creates a writer for the configured HAR output.
+    ///
+    /// In `stream` mode the file is created right away and the archive header written.
+    pub fn new(config: &HarConfig, compressor: Arc<Compressor>) -> Self {
+        let mut writer = Self {
+            config: config.clone(),
+            path: compressor.path(&config.path),
+            compressor,
+            pages: Vec::new(),
+            entries: Vec::new(),
+            file: None,
+            written: 0,
+            flushed: Instant::now(),
+        };
+
+        if config.mode == HarMode::Stream {
+            match writer.compressor.create(&writer.path).and_then(|mut file| {
+                file.write_all(br#"{"log":{"version":"1.2","creator":"#)?;
+                serde_json::to_writer(&mut file, &Creator::default())?;
+                file.write_all(br#","entries":["#)?;
+                file.flush()?;
+                Ok(file)
+            }) {
+                Ok(file) => writer.file = Some(file),
+                Err(e) => eprintln!("Failed to create HAR file {}: {}", writer.path, e),
+            }
+        }
+
+        writer
+    }
+
+    /// Returns the path of the HAR file.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Adds the entries of a completed exchange: one per redirect, then the final
+    /// response.
+    pub fn record(&mut self, exchange: &Exchange) {
+        let completed = Instant::now();
+
+        for (request, response) in &exchange.redirects {
+            let entry = self.entry(request, response, None, response.received);
+            self.add(entry);
+        }
+
+        if let (Some(request), Some(response)) = (exchange.request.as_ref(), exchange.response.as_ref()) {
+            let mut content = content(&response.mime_type, exchange.body.as_deref());
+            if exchange.truncated {
+                if let Some(length) = exchange.original_length {
+                    content.size = length as i64;
+                }
+                content.comment = Some(String::from("truncated"));
+            }
+
+            let entry = self.entry(request, response, Some(content), completed);
+            self.add(entry);
+        }
+    }
+
+    /// Closes the archive. In `final` mode this is when the file is written.
+    pub fn finish(&mut self) {
+        let result = match self.config.mode {
+            HarMode::Stream => self.close_stream(),
+            HarMode::Final => self.write_final(),
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to write HAR file {}: {}", self.path, e);
+        }
+    }
+
+    /// Builds an entry, creating its page on first use.
+    fn entry(
+        &mut self,
+        request: &RequestInfo,
+        response: &ResponseInfo,
+        content: Option<Content>,
+        completed: Instant,
+    ) -> Entry {
+        let pageref = request.page.as_ref().map(|page| self.page_id(page, request.started));
+
+        let wait = millis(response.received.saturating_duration_since(request.sent));
+        let receive = millis(completed.saturating_duration_since(response.received));
+
+        let post_data = request.body.as_ref().map(|body| {
+            let (text, encoding) = encode_body(body);
+            PostData {
+                mime_type: header(&request.headers, "content-type").unwrap_or_default(),
+                text,
+                params: Vec::new(),
+                encoding: (encoding != "utf8").then_some(encoding),
+            }
+        });
+
+        Entry {
+            pageref,
+            started_date_time: request.started,
+            time: wait + receive,
+            request: Request {
+                method: request.method.clone(),
+                url: request.url.clone(),
+                http_version: HTTP_VERSION,
+                cookies: Vec::new(),
+                headers: name_values(&request.headers),
+                query_string: query_string(&request.url),
+                body_size: request.body.as_ref().map_or(0, |body| body.len() as i64),
+                post_data,
+                headers_size: -1,
+            },
+            response: Response {
+                status: response.status,
+                status_text: response.status_text.clone(),
+                http_version: HTTP_VERSION,
+                cookies: Vec::new(),
+                headers: name_values(&response.headers),
+                content: content.unwrap_or_else(|| self::content(&response.mime_type, None)),
+                redirect_url: response.redirect_url.clone().unwrap_or_default(),
+                headers_size: -1,
+                body_size: -1,
+            },
+            cache: Cache::default(),
+            timings: Timings {
+                blocked: -1.0,
+                dns: -1.0,
+                connect: -1.0,
+                send: 0.0,
+                wait,
+                receive,
+                ssl: -1.0,
+            },
+        }
+    }
+
+    /// Returns the id of the page loaded from `url`.
+    fn page_id(&mut self, url: &str, started: DateTime<Utc>) -> String {
+        if let Some(page) = self.pages.iter().find(|page| page.title == url) {
+            return page.id.clone();
+        }
+
+        let id = format!("page_{}", self.pages.len() + 1);
+        self.pages.push(Page {
+            started_date_time: started,
+            id: id.clone(),
+            title: url.to_string(),
+            page_timings: PageTimings {
+                on_content_load: -1.0,
+                on_load: -1.0,
+            },
+        });
+        id
+    }
+
+    fn add(&mut self, entry: Entry) {
+        let Some(file) = self.file.as_mut() else {
+            if self.config.mode == HarMode::Final {
+                self.entries.push(entry);
+            }
+            return;
+        };
+
+        let separator = if self.written == 0 { "\n" } else { ",\n" };
+        let result = file
+            .write_all(separator.as_bytes())
+            .and_then(|_| serde_json::to_writer(&mut *file, &entry).map_err(std::io::Error::from))
+            .and_then(|_| {
+                if self.flushed.elapsed() < FLUSH_INTERVAL {
+                    return Ok(());
+                }
+                self.flushed = Instant::now();
+                file.flush()
+            });
+
+        match result {
+            Ok(()) => self.written += 1,
+            Err(e) => {
+                eprintln!("Failed to write HAR file {}: {}", self.path, e);
+                self.file = None;
+            }
+        }
+    }
+
+    fn close_stream(&mut self) -> std::io::Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+
+        file.write_all(b"\n],\"pages\":")?;
+        serde_json::to_writer(&mut file, &self.pages)?;
+        file.write_all(b"}}\n")?;
+        file.finish()?.finish()
+    }
+
+    fn write_final(&mut self) -> std::io::Result<()> {
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator::default(),
+                pages: std::mem::take(&mut self.pages),
+                entries: std::mem::take(&mut self.entries),
+            },
+        };
+
+        let mut file = self.compressor.create(&self.path)?;
+        serde_json::to_writer_pretty(&mut file, &har)?;
+        file.finish()?.finish()
+    }
+}
+
+/// Builds the content of a response from its body, base64-encoded if it's binary.
+fn content(mime_type: &str, body: Option<&[u8]>) -> Content {
+    let (text, encoding) = match body.map(encode_body) {
+        Some((text, "utf8")) => (Some(text), None),
+        Some((text, encoding)) => (Some(text), Some(encoding)),
+        None => (None, None),
+    };
+
+    Content {
+        size: body.map_or(0, |body| body.len() as i64),
+        mime_type: mime_type.to_string(),
+        text,
+        encoding,
+        comment: None,
+    }
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn name_values(pairs: &[(String, String)]) -> Vec<NameValue> {
+    pairs
+        .iter()
+        .map(|(name, value)| NameValue {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+/// Finds a header by its case-insensitive name.
+fn header(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+/// Splits the query string of a URL into its parameters, as they appear in the URL.
+fn query_string(url: &str) -> Vec<NameValue> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Vec::new();
+    };
+    let query = query.split('#').next().unwrap_or_default();
+
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            NameValue {
+                name: name.to_string(),
+                value: value.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+
+    fn exchange() -> Exchange {
+        let now = Instant::now();
+        let request = |url: &str| RequestInfo {
+            method: String::from("POST"),
+            url: url.to_string(),
+            headers: vec![(String::from("Content-Type"), String::from("application/json"))],
+            body: Some(b"{\"q\":1}".to_vec()),
+            page: Some(String::from("https://example.com/")),
+            started: Utc::now(),
+            sent: now,
+        };
+        let response = |status, redirect_url: Option<&str>| ResponseInfo {
+            status,
+            status_text: String::new(),
+            headers: Vec::new(),
+            mime_type: String::from("application/octet-stream"),
+            redirect_url: redirect_url.map(str::to_string),
+            received: now,
+        };
+
+        Exchange {
+            request: Some(request("https://example.com/api?a=1&b#frag")),
+            response: Some(response(200, None)),
+            redirects: vec![(
+                request("https://example.com/old"),
+                response(302, Some("https://example.com/api?a=1&b")),
+            )],
+            body: Some(vec![0xff, 0x00, 0x01]),
+            ..Default::default()
+        }
+    }
+
+    fn write(mode: HarMode) -> serde_json::Value {
+        let path = std::env::temp_dir().join(format!("udata-{}.har", uuid::Uuid::new_v4()));
+        let config = HarConfig {
+            path: path.to_string_lossy().into_owned(),
+            mode,
+        };
+        let mut writer = HarWriter::new(&config, Default::default());
+
+        writer.record(&exchange());
+        writer.finish();
+
+        let har = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        har
+    }
+
+    #[test]
+    fn test_har() {
+        for mode in [HarMode::Final, HarMode::Stream] {
+            let har = write(mode);
+            let log = &har["log"];
+            assert_eq!(log["version"], "1.2");
+            assert_eq!(log["pages"].as_array().unwrap().len(), 1);
+
+            let entries = log["entries"].as_array().unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0]["response"]["status"], 302);
+            assert_eq!(entries[0]["response"]["redirectURL"], "https://example.com/api?a=1&b");
+            assert_eq!(entries[1]["pageref"], "page_1");
+            assert_eq!(entries[1]["request"]["postData"]["text"], "{\"q\":1}");
+            assert_eq!(entries[1]["request"]["queryString"][1]["name"], "b");
+            assert_eq!(entries[1]["response"]["content"]["size"], 3);
+            assert_eq!(entries[1]["response"]["content"]["text"], "/wAB");
+            assert_eq!(entries[1]["response"]["content"]["encoding"], "base64");
+        }
+    }
+
+    #[test]
+    fn test_har_redacted() {
+        let path = std::env::temp_dir().join(format!("udata-{}.har", uuid::Uuid::new_v4()));
+        let config: crate::config::Config = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "host": [{
+                "host": "https://example.com",
+                "xhr": "/api",
+                "processors": [{ "redact": ["token"] }, "sink"]
+            }],
+            "har": { "path": path.to_string_lossy() }
+        }))
+        .unwrap();
+        let session = std::sync::Arc::new(crate::session::Session::new(Some(&config)).unwrap());
+
+        let mut exchange = exchange();
+        exchange.body = None;
+        exchange.host = Some(config.host[0].clone());
+        exchange.mime_type = String::from("application/json");
+        exchange.start(&session);
+        exchange.chunk(b"{\"token\":\"secret\",\"id\":1}");
+        exchange.complete();
+        session.finish();
+
+        let har = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!har.contains("secret"));
+        assert!(har.contains("REDACTED"));
+    }
+}
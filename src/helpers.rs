@@ -6,11 +6,21 @@ use cef::WrapResourceRequestHandler;
 use cef::WrapResponseFilter;
 use cef::WrapBrowserProcessHandler;
 use cef::WrapWindowDelegate;
+use cef::WrapRenderProcessHandler;
+use cef::WrapV8Handler;
+use cef::WrapV8ArrayBufferReleaseCallback;
 use cef::rc::Rc;
 use cef::rc::RcImpl;
 use cef::sys;
+use cef::{
+    CefString, CefStringMultimap, ImplPostData, ImplPostDataElement, ImplRequest, ImplResponse,
+    PostdataelementType, post_data_create, post_data_element_create,
+};
+
+use crate::capture::{RequestInfo, ResponseInfo};
 
 use crate::app::DemoApp;
+use crate::binding::{DemoArrayBufferRelease, DemoRenderProcessHandler, DemoV8Handler};
 use crate::client::DemoClient;
 use crate::filter::DemoResponseFilter;
 use crate::xhr::{DemoRequestHandler, DemoResourceRequestHandler};
@@ -37,6 +47,124 @@ pub fn fmt_cef_string_utf16_t(s: &cef::sys::_cef_string_utf16_t) -> String {
     String::from_utf16_lossy(slice)
 }
 
+/// Reads the bytes of a request's post data.
+///
+/// # Returns
+/// The concatenated contents of all byte elements, or `None` if the request has no post data.
+pub fn read_post_data(request: &impl ImplRequest) -> Option<Vec<u8>> {
+    let post_data = request.get_post_data()?;
+
+    let mut elements = Vec::with_capacity(post_data.get_element_count());
+    post_data.get_elements(Some(&mut elements));
+
+    let mut body = Vec::new();
+    for element in elements.iter().flatten() {
+        let count = element.get_bytes_count();
+        if count == 0 {
+            continue;
+        }
+
+        let offset = body.len();
+        body.resize(offset + count, 0);
+        let read = element.get_bytes(count, body[offset..].as_mut_ptr());
+        body.truncate(offset + read);
+    }
+
+    Some(body)
+}
+
+/// Checks that a request's post data is made of byte elements only, so that replacing
+/// it with `write_post_data` doesn't drop files.
+///
+/// # Returns
+/// `false` if the request has no post data, or any file or empty element.
+pub fn is_bytes_post_data(request: &impl ImplRequest) -> bool {
+    let Some(post_data) = request.get_post_data() else {
+        return false;
+    };
+
+    let mut elements = Vec::with_capacity(post_data.get_element_count());
+    post_data.get_elements(Some(&mut elements));
+
+    let bytes = PostdataelementType::from(sys::cef_postdataelement_type_t::PDE_TYPE_BYTES);
+    !elements.is_empty() && elements.iter().all(|element| element.as_ref().is_some_and(|element| element.get_type() == bytes))
+}
+
+/// Replaces a request's post data with a single byte element.
+///
+/// # Parameters
+/// - `request`: The request to modify.
+/// - `body`: The new request body.
+pub fn write_post_data(request: &impl ImplRequest, body: &[u8]) {
+    let (Some(mut post_data), Some(mut element)) = (post_data_create(), post_data_element_create()) else {
+        return;
+    };
+
+    element.set_to_bytes(body.len(), body.as_ptr());
+    post_data.add_element(Some(&mut element));
+    request.set_post_data(Some(&mut post_data));
+}
+
+/// Lists the entries of a header map.
+pub fn header_pairs(map: &CefStringMultimap) -> Vec<(String, String)> {
+    Vec::from(map)
+}
+
+/// Records the details of a request about to be sent.
+///
+/// # Parameters
+/// - `request`: The request.
+/// - `page`: URL of the page that issued the request.
+pub fn request_info(request: &impl ImplRequest, page: Option<String>) -> RequestInfo {
+    let mut headers = CefStringMultimap::new().unwrap();
+    request.get_header_map(Some(&mut headers));
+
+    RequestInfo {
+        method: CefString::from(&request.get_method()).to_string(),
+        url: CefString::from(&request.get_url()).to_string(),
+        headers: header_pairs(&headers),
+        body: read_post_data(request),
+        page,
+        started: chrono::Utc::now(),
+        sent: std::time::Instant::now(),
+    }
+}
+
+/// Records the details of a received response.
+///
+/// # Parameters
+/// - `response`: The response.
+/// - `redirect_url`: Target of the redirect, for redirect responses.
+pub fn response_info(response: &impl ImplResponse, redirect_url: Option<String>) -> ResponseInfo {
+    let mut headers = CefStringMultimap::new().unwrap();
+    response.get_header_map(Some(&mut headers));
+
+    ResponseInfo {
+        status: response.get_status(),
+        status_text: CefString::from(&response.get_status_text()).to_string(),
+        headers: header_pairs(&headers),
+        mime_type: CefString::from(&response.get_mime_type()).to_string(),
+        redirect_url,
+        received: std::time::Instant::now(),
+    }
+}
+
+/// Turns a host entry name into something usable in a file name.
+///
+/// The URL scheme is dropped and characters that don't belong in a file name are
+/// replaced with `_`.
+pub fn safe_file_name(name: &str) -> String {
+    let name = name
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect::<String>();
+    let name = name.trim_matches(|c| c == '_' || c == '.');
+
+    if name.is_empty() { String::from("capture") } else { name.to_string() }
+}
+
 #[allow(dead_code)]
 pub fn fmt_cef_string_utf16_userfree(s: &cef::CefStringUserfreeUtf16) -> String {
     let st = cef::CefString::from(s);
@@ -73,6 +201,7 @@ impl Clone for DemoRequestHandler {
         Self{
             base: self.base,
             config: self.config.clone(),
+            session: self.session.clone(),
         }
     }
 }
@@ -119,6 +248,8 @@ impl Clone for DemoResourceRequestHandler {
         Self {
             base: self.base,
             config: self.config.clone(),
+            exchange: self.exchange.clone(),
+            session: self.session.clone(),
         }
     }
 }
@@ -170,6 +301,10 @@ impl Clone for DemoResponseFilter {
             url: self.url.clone(),
             uuid: self.uuid,
             config: self.config.clone(),
+            mime_type: self.mime_type.clone(),
+            injector: self.injector.clone(),
+            exchange: self.exchange.clone(),
+            session: self.session.clone(),
         }
     }
 }
@@ -230,7 +365,7 @@ impl Clone for DemoBrowserProcessHandler {
 
         let window = self.window.clone();
 
-        Self { object, window, config: self.config.clone() }
+        Self { object, window, config: self.config.clone(), session: self.session.clone() }
     }
 }
 
@@ -319,7 +454,7 @@ impl Clone for DemoApp {
         };
         let window = self.window.clone();
 
-        Self { object, window, config: self.config.clone() }
+        Self { object, window, config: self.config.clone(), session: self.session.clone() }
     }
 }
 
@@ -371,6 +506,7 @@ impl Clone for DemoClient {
         Self {
             base: self.base,
             config: self.config.clone(),
+            session: self.session.clone(),
         }
     }
 }
@@ -390,4 +526,120 @@ impl Rc for DemoClient {
             std::mem::transmute(&base.cef_object)
         }
     }
+}
+
+//
+// DemoRenderProcessHandler
+//
+
+impl WrapRenderProcessHandler for DemoRenderProcessHandler {
+    /// Sets the raw CEF object pointer for this instance.
+    ///
+    /// # Arguments
+    /// * `object` - The raw CEF object pointer to set
+    fn wrap_rc(&mut self, object: *mut RcImpl<sys::_cef_render_process_handler_t, Self>) {
+        self.object = object;
+    }
+}
+
+impl Clone for DemoRenderProcessHandler {
+    /// Clones the handle by incrementing the CEF reference count.
+    fn clone(&self) -> Self {
+        unsafe {
+            let rc_impl = &mut *self.object;
+            rc_impl.interface.add_ref();
+        }
+
+        Self {
+            object: self.object,
+            enabled: self.enabled,
+        }
+    }
+}
+
+impl Rc for DemoRenderProcessHandler {
+    /// Accesses the base reference-counted object.
+    fn as_base(&self) -> &sys::cef_base_ref_counted_t {
+        unsafe {
+            let base = &*self.object;
+            std::mem::transmute(&base.cef_object)
+        }
+    }
+}
+
+//
+// DemoV8Handler
+//
+
+impl WrapV8Handler for DemoV8Handler {
+    /// Sets the raw CEF object pointer for this instance.
+    ///
+    /// # Arguments
+    /// * `object` - The raw CEF object pointer to set
+    fn wrap_rc(&mut self, object: *mut RcImpl<sys::_cef_v8_handler_t, Self>) {
+        self.object = object;
+    }
+}
+
+impl Clone for DemoV8Handler {
+    /// Clones the handle by incrementing the CEF reference count.
+    fn clone(&self) -> Self {
+        unsafe {
+            let rc_impl = &mut *self.object;
+            rc_impl.interface.add_ref();
+        }
+
+        Self {
+            object: self.object,
+        }
+    }
+}
+
+impl Rc for DemoV8Handler {
+    /// Accesses the base reference-counted object.
+    fn as_base(&self) -> &sys::cef_base_ref_counted_t {
+        unsafe {
+            let base = &*self.object;
+            std::mem::transmute(&base.cef_object)
+        }
+    }
+}
+
+//
+// DemoArrayBufferRelease
+//
+
+impl WrapV8ArrayBufferReleaseCallback for DemoArrayBufferRelease {
+    /// Sets the raw CEF object pointer for this instance.
+    ///
+    /// # Arguments
+    /// * `object` - The raw CEF object pointer to set
+    fn wrap_rc(&mut self, object: *mut RcImpl<sys::_cef_v8_array_buffer_release_callback_t, Self>) {
+        self.object = object;
+    }
+}
+
+impl Clone for DemoArrayBufferRelease {
+    /// Clones the handle by incrementing the CEF reference count.
+    fn clone(&self) -> Self {
+        unsafe {
+            let rc_impl = &mut *self.object;
+            rc_impl.interface.add_ref();
+        }
+
+        Self {
+            object: self.object,
+            length: self.length,
+        }
+    }
+}
+
+impl Rc for DemoArrayBufferRelease {
+    /// Accesses the base reference-counted object.
+    fn as_base(&self) -> &sys::cef_base_ref_counted_t {
+        unsafe {
+            let base = &*self.object;
+            std::mem::transmute(&base.cef_object)
+        }
+    }
 }
\ No newline at end of file
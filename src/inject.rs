@@ -0,0 +1,301 @@
+//! Injection of userscripts and stylesheets into HTML document responses.
+//!
+//! Rules are configured per host entry and reference files in `.udata/`. The
+//! `Injector` rewrites the document as it streams through the response filter,
+//! holding back just enough data to recognise insertion points that are split
+//! across chunk boundaries.
+use serde::Deserialize;
+use std::path::Path;
+
+/// Where injected content is placed within the document.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// Right after the opening `<head>` tag
+    HeadStart,
+    /// Right before the closing `</head>` tag
+    HeadEnd,
+    /// Right before the closing `</body>` tag
+    BodyEnd,
+}
+
+/// Kind of element wrapping the injected content.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InjectKind {
+    /// `<script>` element
+    Script,
+    /// `<style>` element
+    Style,
+}
+
+/// A single injection rule from the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct InjectRule {
+    /// File to inject, relative to `.udata/`
+    pub file: String,
+    /// Element kind; inferred from the file extension (`.css` is a style) when unset
+    #[serde(default)]
+    pub kind: Option<InjectKind>,
+    /// Insertion point within the document
+    pub placement: Placement,
+}
+
+impl InjectRule {
+    /// Loads the rule's file and wraps it in the appropriate element.
+    ///
+    /// # Returns
+    /// The markup to insert, or an error if the file can't be read.
+    pub fn load(&self) -> std::io::Result<Vec<u8>> {
+        let content = std::fs::read_to_string(Path::new(".udata").join(&self.file))?;
+
+        let kind = self.kind.unwrap_or_else(|| {
+            if self.file.to_ascii_lowercase().ends_with(".css") {
+                InjectKind::Style
+            } else {
+                InjectKind::Script
+            }
+        });
+
+        let markup = match kind {
+            InjectKind::Script => format!("<script>\n{}\n</script>", content),
+            InjectKind::Style => format!("<style>\n{}\n</style>", content),
+        };
+
+        Ok(markup.into_bytes())
+    }
+}
+
+/// Result of searching the pending buffer for an insertion point.
+enum Search {
+    /// Insert at this offset
+    Found(usize),
+    /// Not found; everything before this offset can be released
+    Pending(usize),
+}
+
+/// Streaming HTML rewriter inserting content at configured placements.
+#[derive(Debug)]
+pub struct Injector {
+    /// Markup per placement, in document order
+    inserts: Vec<(Placement, Vec<u8>)>,
+    /// Input that has not been released yet
+    pending: Vec<u8>,
+}
+
+impl Injector {
+    /// Creates an injector from a set of rules.
+    ///
+    /// Rules whose file can't be read are reported and skipped.
+    ///
+    /// # Returns
+    /// `None` if no rule could be loaded.
+    pub fn new(rules: &[InjectRule]) -> Option<Self> {
+        let mut inserts: Vec<(Placement, Vec<u8>)> = Vec::new();
+
+        for rule in rules {
+            match rule.load() {
+                Ok(markup) => match inserts.iter_mut().find(|(p, _)| *p == rule.placement) {
+                    Some((_, existing)) => existing.extend_from_slice(&markup),
+                    None => inserts.push((rule.placement, markup)),
+                },
+                Err(e) => eprintln!("Failed to load injection file {}: {}", rule.file, e),
+            }
+        }
+
+        if inserts.is_empty() {
+            return None;
+        }
+
+        inserts.sort_by_key(|(placement, _)| *placement);
+
+        Some(Self {
+            inserts,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Returns `true` while input is held back or content is still waiting to be inserted.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty() || !self.inserts.is_empty()
+    }
+
+    /// Feeds a chunk of the document.
+    ///
+    /// # Returns
+    /// The rewritten output that can be released so far.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        self.process(false)
+    }
+
+    /// Flushes the held back input once the document has ended.
+    ///
+    /// Content whose insertion point never appeared is appended at the end, so
+    /// scripts still run on documents without explicit `<head>` or `</body>` tags.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.process(true)
+    }
+
+    fn process(&mut self, eof: bool) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        while let Some((placement, _)) = self.inserts.first() {
+            match Self::search(&self.pending, *placement) {
+                Search::Found(at) => {
+                    let (_, markup) = self.inserts.remove(0);
+                    output.extend(self.pending.drain(..at));
+                    output.extend_from_slice(&markup);
+                }
+                Search::Pending(release) => {
+                    // A later insertion point means this one can no longer appear, as in a
+                    // document without `<head>`: insert the content there instead
+                    let later = self.inserts[1..]
+                        .iter()
+                        .find_map(|(placement, _)| match Self::search(&self.pending, *placement) {
+                            Search::Found(at) => Some(at),
+                            Search::Pending(_) => None,
+                        });
+                    if let Some(at) = later {
+                        let (_, markup) = self.inserts.remove(0);
+                        output.extend(self.pending.drain(..at));
+                        output.extend_from_slice(&markup);
+                        continue;
+                    }
+
+                    if !eof {
+                        // Keep what could be the start of any of the insertion points
+                        let release = self.inserts[1..]
+                            .iter()
+                            .map(|(placement, _)| match Self::search(&self.pending, *placement) {
+                                Search::Found(at) | Search::Pending(at) => at,
+                            })
+                            .fold(release, usize::min);
+                        output.extend(self.pending.drain(..release));
+                        return output;
+                    }
+
+                    // The insertion point never appeared
+                    let (_, markup) = self.inserts.remove(0);
+                    output.append(&mut self.pending);
+                    output.extend_from_slice(&markup);
+                }
+            }
+        }
+
+        output.append(&mut self.pending);
+        output
+    }
+
+    fn search(buffer: &[u8], placement: Placement) -> Search {
+        match placement {
+            Placement::HeadStart => Self::search_open_tag(buffer, b"<head"),
+            Placement::HeadEnd => Self::search_close_tag(buffer, b"</head"),
+            Placement::BodyEnd => Self::search_close_tag(buffer, b"</body"),
+        }
+    }
+
+    /// Finds the offset right after an opening tag such as `<head ...>`.
+    fn search_open_tag(buffer: &[u8], tag: &[u8]) -> Search {
+        let mut from = 0;
+
+        while let Some(at) = find_ignore_case(&buffer[from..], tag).map(|at| from + at) {
+            let Some(next) = buffer.get(at + tag.len()) else {
+                return Search::Pending(at);
+            };
+
+            // Skip longer tag names such as `<header>`
+            if !(next.is_ascii_whitespace() || *next == b'>' || *next == b'/') {
+                from = at + 1;
+                continue;
+            }
+
+            return match buffer[at..].iter().position(|b| *b == b'>') {
+                Some(end) => Search::Found(at + end + 1),
+                None => Search::Pending(at),
+            };
+        }
+
+        Search::Pending(buffer.len().saturating_sub(tag.len()).max(from))
+    }
+
+    /// Finds the offset right before a closing tag such as `</body>`.
+    fn search_close_tag(buffer: &[u8], tag: &[u8]) -> Search {
+        match find_ignore_case(buffer, tag) {
+            Some(at) => Search::Found(at),
+            None => Search::Pending(buffer.len().saturating_sub(tag.len() - 1)),
+        }
+    }
+}
+
+/// ASCII case-insensitive substring search.
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+
+    haystack
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn injector(inserts: &[(Placement, &str)]) -> Injector {
+        Injector {
+            inserts: inserts
+                .iter()
+                .map(|(placement, markup)| (*placement, markup.as_bytes().to_vec()))
+                .collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn run(injector: &mut Injector, input: &[u8], chunk_size: usize) -> String {
+        let mut output = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            output.extend(injector.push(chunk));
+        }
+        output.extend(injector.finish());
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_inject_across_chunks() {
+        let input = b"<!doctype html><HTML><Head lang=\"en\"><title>t</title></HEAD><body><header>h</header></Body></html>";
+        let expected = "<!doctype html><HTML><Head lang=\"en\">[A]<title>t</title>[B]</HEAD><body><header>h</header>[C]</Body></html>";
+
+        for chunk_size in 1..input.len() {
+            let mut injector = injector(&[
+                (Placement::HeadStart, "[A]"),
+                (Placement::HeadEnd, "[B]"),
+                (Placement::BodyEnd, "[C]"),
+            ]);
+            assert_eq!(run(&mut injector, input, chunk_size), expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_inject_missing_tags() {
+        let mut injector = injector(&[(Placement::HeadStart, "[A]"), (Placement::BodyEnd, "[C]")]);
+        let output = run(&mut injector, b"<header>plain</header>", 3);
+        assert_eq!(output, "<header>plain</header>[A][C]");
+    }
+
+    #[test]
+    fn test_inject_without_head() {
+        let input = b"<html><body><p>x</p></Body></html>";
+        let expected = "<html><body><p>x</p>[A][B][C]</Body></html>";
+
+        for chunk_size in 1..input.len() {
+            let mut injector = injector(&[
+                (Placement::HeadStart, "[A]"),
+                (Placement::HeadEnd, "[B]"),
+                (Placement::BodyEnd, "[C]"),
+            ]);
+            assert_eq!(run(&mut injector, input, chunk_size), expected, "chunk size {}", chunk_size);
+        }
+    }
+}
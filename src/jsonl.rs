@@ -0,0 +1,178 @@
+//! Durable JSON Lines output for capture records.
+//!
+//! A host entry with a `jsonl` output writes one capture record per line to files
+//! named after the entry and the session, instead of printing them to stderr:
+//!
+//! ```text
+//! <dir>/<name>-<session>-0001.jsonl[.gz|.zst]
+//! ```
+//!
+//! A new file is started once the current one reaches `max_bytes` of records or gets
+//! older than `max_age` seconds. Rotated files are synced to disk before the next one
+//! is opened. Records are flushed at most once per `FLUSH_INTERVAL`, and when the file
+//! is rotated or finished.
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::compress::{Compressor, Encoder, FLUSH_INTERVAL};
+use crate::crypt::Sink;
+use crate::helpers::safe_file_name;
+
+/// JSONL output of a host entry, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct JsonlConfig {
+    /// Directory the files are written to
+    pub dir: String,
+    /// File name prefix, defaults to the host entry's `host`
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Rotate once this many bytes of records were written to a file
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Rotate once a file is older than this many seconds
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+impl JsonlConfig {
+    /// Returns the file name prefix for records of `host`.
+    pub fn prefix(&self, host: &str) -> String {
+        safe_file_name(self.name.as_deref().unwrap_or(host))
+    }
+}
+
+/// Appends records to a rotating set of JSONL files.
+#[derive(Debug)]
+pub struct JsonlWriter {
+    config: JsonlConfig,
+    compressor: Arc<Compressor>,
+    prefix: String,
+    session: String,
+    /// Number of the current file, starting at 1
+    index: u32,
+    file: Option<Encoder<Sink>>,
+    /// Bytes of records written to the current file
+    bytes: u64,
+    /// Time at which the current file was opened
+    opened: Instant,
+    /// Time of the last flush
+    flushed: Instant,
+}
+
+impl JsonlWriter {
+    /// Creates a writer. The first file is only created once a record is written.
+    ///
+    /// # Parameters
+    /// - `config`: The output configuration.
+    /// - `prefix`: File name prefix, see `JsonlConfig::prefix`.
+    /// - `session`: Identifier of the session, included in every file name.
+    /// - `compressor`: Compresses and encrypts the files.
+    pub fn new(config: &JsonlConfig, prefix: String, session: &str, compressor: Arc<Compressor>) -> Self {
+        Self {
+            config: config.clone(),
+            compressor,
+            prefix,
+            session: session.to_string(),
+            index: 0,
+            file: None,
+            bytes: 0,
+            opened: Instant::now(),
+            flushed: Instant::now(),
+        }
+    }
+
+    /// Appends a record as a single line, rotating the file first if needed.
+    pub fn write(&mut self, record: &impl Serialize) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        if self.file.is_none() || self.needs_rotation() {
+            self.rotate()?;
+        }
+
+        let file = self.file.as_mut().expect("file opened by rotate");
+        file.write_all(&line)?;
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            file.flush()?;
+            self.flushed = Instant::now();
+        }
+        self.bytes += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Terminates, flushes and syncs the current file.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self.file.take() {
+            Some(file) => file.finish()?.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the path of the current file.
+    pub fn path(&self) -> PathBuf {
+        let name = format!("{}-{}-{:04}.jsonl", self.prefix, self.session, self.index);
+        PathBuf::from(&self.config.dir).join(self.compressor.path(&name))
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let full = self.config.max_bytes.is_some_and(|max| self.bytes >= max);
+        let old = self
+            .config
+            .max_age
+            .is_some_and(|max| self.opened.elapsed() >= Duration::from_secs(max));
+
+        full || old
+    }
+
+    /// Syncs the current file and opens the next one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.finish()?;
+        std::fs::create_dir_all(&self.config.dir)?;
+
+        self.index += 1;
+        self.file = Some(self.compressor.create(&self.path().to_string_lossy())?);
+        self.bytes = 0;
+        self.opened = Instant::now();
+        self.flushed = Instant::now();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let config = JsonlConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            name: None,
+            max_bytes: Some(16),
+            max_age: None,
+        };
+        assert_eq!(config.prefix("https://example.com/api/"), "example.com_api");
+
+        let mut writer = JsonlWriter::new(&config, config.prefix("example.com"), "s1", Default::default());
+        for record in ["a", "this line is longer than the limit", "b", "c"] {
+            writer.write(&serde_json::json!({ "body": record })).unwrap();
+        }
+        // Not flushed record by record
+        assert_eq!(std::fs::metadata(dir.join("example.com-s1-0002.jsonl")).unwrap().len(), 0);
+        writer.finish().unwrap();
+
+        let read = |index: u32| {
+            std::fs::read_to_string(dir.join(format!("example.com-s1-{:04}.jsonl", index))).unwrap()
+        };
+        assert_eq!(read(1).lines().count(), 2);
+        assert_eq!(read(2), "{\"body\":\"b\"}\n{\"body\":\"c\"}\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+//! Per-request capture files in a predictable directory structure.
+//!
+//! With a top-level `layout` section, every completed exchange is written as two files
+//! named after the `uuid` assigned by `DemoResponseFilter`:
+//!
+//! ```text
+//! <dir>/<host-entry>/<yyyy-mm-dd>/<uuid>.json   exchange details and capture records
+//! <dir>/<host-entry>/<yyyy-mm-dd>/<uuid>.body   processed response body
+//! <dir>/manifest-<session>.jsonl                one line per exchange of the session
+//! ```
+//!
+//! The location of the files comes from `template`, see `LayoutConfig`. Every
+//! placeholder is turned into a single safe path segment, so arbitrary URLs can't
+//! escape `dir`. The files get the extensions of the configured compression and
+//! encryption.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::capture::{CaptureRecord, Exchange};
+use crate::compress::{Compressor, Encoder};
+use crate::crypt::Sink;
+use crate::helpers::safe_file_name;
+
+/// Longest placeholder value kept in a path segment.
+const MAX_SEGMENT: usize = 80;
+
+/// Placeholders supported by `LayoutConfig::template`.
+const PLACEHOLDERS: [&str; 8] = ["host", "date", "hour", "session", "uuid", "method", "status", "path"];
+
+/// Per-request capture files, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LayoutConfig {
+    /// Root directory of the capture files
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// Path of the files under `dir`, without extension. Supports `{host}` (host
+    /// entry), `{date}` (`yyyy-mm-dd`), `{hour}`, `{session}`, `{uuid}`, `{method}`,
+    /// `{status}` and `{path}` (URL path), and must contain `{uuid}`
+    #[serde(default = "default_template")]
+    pub template: String,
+}
+
+fn default_dir() -> String {
+    String::from(".udata/captures")
+}
+
+fn default_template() -> String {
+    String::from("{host}/{date}/{uuid}")
+}
+
+impl LayoutConfig {
+    /// Checks that `template` yields a unique path under `dir` for every exchange.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.template.contains("{uuid}") {
+            return Err(format!("The layout template {:?} must contain {{uuid}}", self.template));
+        }
+
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in layout template {:?}", self.template))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!("Unknown placeholder {{{}}} in layout template {:?}", name, self.template));
+            }
+            rest = &rest[start + end + 1..];
+        }
+
+        let escapes = Path::new(&self.template)
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)));
+        if escapes {
+            return Err(format!("The layout template {:?} must be a relative path", self.template));
+        }
+
+        Ok(())
+    }
+}
+
+/// Exchange details written to `<uuid>.json`.
+#[derive(Debug, Serialize)]
+struct Capture<'a> {
+    uuid: uuid::Uuid,
+    session: &'a str,
+    /// Host entry (`HostEntry::host`) that matched the request
+    host: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<i32>,
+    mime_type: &'a str,
+    started: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    request_headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    response_headers: Vec<(String, String)>,
+    /// URLs of the redirects followed before the final response
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    redirects: Vec<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_length: Option<u64>,
+    /// Scheme of the decoded plaintext, see `decode::Scheme`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<&'static str>,
+    /// Name of the body file, next to this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    records: &'a [CaptureRecord],
+}
+
+/// Line of the session manifest.
+#[derive(Debug, Serialize)]
+struct ManifestEntry<'a> {
+    uuid: uuid::Uuid,
+    host: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<i32>,
+    started: DateTime<Utc>,
+    /// Paths relative to the layout directory
+    json: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+/// Writes the capture files of a session and its manifest.
+#[derive(Debug)]
+pub struct LayoutWriter {
+    config: LayoutConfig,
+    session: String,
+    compressor: Arc<Compressor>,
+    manifest: Option<Encoder<Sink>>,
+}
+
+impl LayoutWriter {
+    /// Creates a writer. The manifest is only created once an exchange is written.
+    ///
+    /// # Parameters
+    /// - `config`: The layout configuration, see `LayoutConfig::validate`.
+    /// - `session`: Identifier of the session.
+    /// - `compressor`: Compresses and encrypts the files.
+    pub fn new(config: &LayoutConfig, session: &str, compressor: Arc<Compressor>) -> Self {
+        Self {
+            config: config.clone(),
+            session: session.to_string(),
+            compressor,
+            manifest: None,
+        }
+    }
+
+    /// Returns the path of the session manifest.
+    pub fn manifest_path(&self) -> PathBuf {
+        let name = format!("manifest-{}.jsonl", self.session);
+        PathBuf::from(&self.config.dir).join(self.compressor.path(&name))
+    }
+
+    /// Writes the capture files of a completed exchange and adds it to the manifest.
+    pub fn record(&mut self, exchange: &Exchange) {
+        if let Err(e) = self.write(exchange) {
+            eprintln!("Failed to write capture files of {} to {}: {}", exchange.uuid, self.config.dir, e);
+        }
+    }
+
+    /// Terminates, flushes and syncs the manifest.
+    pub fn finish(&mut self) {
+        let result = match self.manifest.take() {
+            Some(manifest) => manifest.finish().and_then(Sink::finish),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write manifest {:?}: {}", self.manifest_path(), e);
+        }
+    }
+
+    fn write(&mut self, exchange: &Exchange) -> std::io::Result<()> {
+        let request = exchange.request.as_ref();
+        let response = exchange.response.as_ref();
+        let started = request.map(|request| request.started).unwrap_or_else(Utc::now);
+
+        let base = render(&self.config.template, &self.session, exchange, &started);
+        let dir = PathBuf::from(&self.config.dir);
+        if let Some(parent) = dir.join(&base).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let body = match exchange.body.as_deref() {
+            Some(body) => {
+                let path = self.compressor.path(&format!("{}.body", base));
+                self.create(&dir.join(&path), body)?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        let capture = Capture {
+            uuid: exchange.uuid,
+            session: &self.session,
+            host: exchange.host_name(),
+            url: &exchange.url,
+            method: request.map(|request| request.method.as_str()),
+            status: response.map(|response| response.status),
+            mime_type: &exchange.mime_type,
+            started,
+            duration_ms: request
+                .map(|request| Instant::now().saturating_duration_since(request.sent).as_secs_f64() * 1000.0),
+            request_headers: request.map(|request| request.headers.clone()).unwrap_or_default(),
+            response_headers: response.map(|response| response.headers.clone()).unwrap_or_default(),
+            redirects: exchange.redirects.iter().map(|(request, _)| request.url.as_str()).collect(),
+            truncated: exchange.truncated,
+            original_length: exchange.original_length,
+            decoded: exchange.decoded.as_ref().map(|decoded| decoded.scheme),
+            body: body.as_deref().and_then(|body| Path::new(body).file_name()).map(|name| name.to_string_lossy().into_owned()),
+            records: &exchange.records,
+        };
+        let json = self.compressor.path(&format!("{}.json", base));
+        self.create(&dir.join(&json), &serde_json::to_vec_pretty(&capture)?)?;
+
+        let entry = ManifestEntry {
+            uuid: exchange.uuid,
+            host: exchange.host_name(),
+            url: &exchange.url,
+            status: capture.status,
+            started,
+            json,
+            body,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        if self.manifest.is_none() {
+            std::fs::create_dir_all(&self.config.dir)?;
+            self.manifest = Some(self.compressor.create(&self.manifest_path().to_string_lossy())?);
+        }
+        let manifest = self.manifest.as_mut().expect("manifest opened above");
+        manifest.write_all(&line)?;
+        manifest.flush()
+    }
+
+    /// Writes a whole file, compressed and encrypted.
+    fn create(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let mut file = self.compressor.create(&path.to_string_lossy())?;
+        file.write_all(data)?;
+        file.finish()?.finish()
+    }
+}
+
+/// Fills in the placeholders of `template` for an exchange.
+///
+/// # Returns
+/// A relative path, with `/` separators and without extension.
+fn render(template: &str, session: &str, exchange: &Exchange, started: &DateTime<Utc>) -> String {
+    let path = exchange
+        .url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .map(|(_, path)| path.split(['?', '#']).next().unwrap_or_default())
+        .unwrap_or_default();
+    let values = [
+        ("host", exchange.host_name().to_string()),
+        ("date", started.format("%Y-%m-%d").to_string()),
+        ("hour", started.format("%H").to_string()),
+        ("session", session.to_string()),
+        ("uuid", exchange.uuid.to_string()),
+        ("method", exchange.request.as_ref().map(|request| request.method.clone()).unwrap_or_default()),
+        ("status", exchange.response.as_ref().map(|response| response.status.to_string()).unwrap_or_default()),
+        ("path", path.to_string()),
+    ];
+
+    values.iter().fold(template.to_string(), |path, (name, value)| {
+        path.replace(&format!("{{{}}}", name), &segment(value))
+    })
+}
+
+/// Turns a placeholder value into a single path segment.
+fn segment(value: &str) -> String {
+    safe_file_name(value).chars().take(MAX_SEGMENT).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture::{RequestInfo, ResponseInfo};
+
+    #[test]
+    fn test_layout() {
+        let config = |template: &str| LayoutConfig {
+            dir: String::new(),
+            template: template.to_string(),
+        };
+        assert!(config("{host}/{date}/{uuid}").validate().is_ok());
+        assert!(config("{host}/{date}").validate().is_err());
+        assert!(config("../{uuid}").validate().is_err());
+        assert!(config("/tmp/{uuid}").validate().is_err());
+        assert!(config("{url}/{uuid}").validate().is_err());
+
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let started = "2024-05-06T07:08:09Z".parse::<DateTime<Utc>>().unwrap();
+        let uuid = uuid::Uuid::new_v4();
+        let exchange = Exchange {
+            uuid,
+            url: String::from("https://example.com/../../etc/passwd?x=1"),
+            request: Some(RequestInfo {
+                method: String::from("GET"),
+                url: String::from("https://example.com/../../etc/passwd?x=1"),
+                headers: Vec::new(),
+                body: None,
+                page: None,
+                started,
+                sent: Instant::now(),
+            }),
+            response: Some(ResponseInfo {
+                status: 200,
+                status_text: String::from("OK"),
+                headers: Vec::new(),
+                mime_type: String::from("text/plain"),
+                redirect_url: None,
+                received: Instant::now(),
+            }),
+            body: Some(b"hello".to_vec()),
+            ..Default::default()
+        };
+
+        assert_eq!(render("{host}/{date}/{path}-{uuid}", "s1", &exchange, &started), format!("capture/2024-05-06/etc_passwd-{}", uuid));
+
+        let mut writer = LayoutWriter::new(
+            &LayoutConfig {
+                dir: dir.to_string_lossy().into_owned(),
+                template: default_template(),
+            },
+            "s1",
+            Default::default(),
+        );
+        writer.record(&exchange);
+        writer.finish();
+
+        let base = dir.join("capture").join("2024-05-06").join(uuid.to_string());
+        assert_eq!(std::fs::read(base.with_extension("body")).unwrap(), b"hello");
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(base.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["body"], format!("{}.body", uuid));
+
+        let manifest = std::fs::read_to_string(dir.join("manifest-s1.jsonl")).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(manifest.trim_end()).unwrap();
+        assert_eq!(entry["json"], format!("capture/2024-05-06/{}.json", uuid));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! Size limits and sampling for captured responses.
+//!
+//! Large media or JSON dumps would otherwise be buffered and recorded in full. A host
+//! entry can cap the number of captured bytes per response and decide what happens
+//! to bodies over the cap, or only capture a sample of its requests.
+//!
+//! Streaming responses may never complete, so the limits apply to each of their
+//! events instead, see `StreamProcessor`.
+use serde::Deserialize;
+
+/// Capture limits of a host entry.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Limits {
+    /// Maximum number of body bytes captured per response
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    /// What happens to bodies larger than `max_bytes`
+    #[serde(default)]
+    pub overflow: Overflow,
+    /// Capture only one in every `sample` matching requests
+    #[serde(default)]
+    pub sample: Option<u64>,
+}
+
+impl Limits {
+    /// Applies the limits to a body available at once, such as a stream event.
+    ///
+    /// # Returns
+    /// The captured part of `data` and, if some of it was dropped, its original
+    /// length. `None` if it must not be recorded.
+    pub fn apply(&self, data: Vec<u8>) -> Option<(Vec<u8>, Option<u64>)> {
+        let Some(mut limiter) = Limiter::new(self) else {
+            return Some((data, None));
+        };
+
+        let head = limiter.chunk(&data).to_vec();
+        let rest = limiter.finish()?;
+        let original_length = limiter.is_truncated().then(|| limiter.original_length());
+        Some(([head, rest].concat(), original_length))
+    }
+}
+
+/// Policy for bodies larger than `Limits::max_bytes`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Keep the first `max_bytes` bytes
+    #[default]
+    Truncate,
+    /// Don't record the response at all
+    Skip,
+    /// Keep the first and last `max_bytes / 2` bytes
+    HeadTail,
+}
+
+/// Applies `Limits` to the body of a single response as it streams in.
+#[derive(Debug)]
+pub struct Limiter {
+    max_bytes: usize,
+    overflow: Overflow,
+    /// Total number of body bytes seen
+    seen: u64,
+    /// Bytes held back until the body is complete
+    held: Vec<u8>,
+}
+
+impl Limiter {
+    /// Creates a limiter for a response.
+    ///
+    /// # Returns
+    /// `None` if the limits don't cap the body size.
+    pub fn new(limits: &Limits) -> Option<Self> {
+        Some(Self {
+            max_bytes: limits.max_bytes?,
+            overflow: limits.overflow,
+            seen: 0,
+            held: Vec::new(),
+        })
+    }
+
+    /// Feeds a chunk of the body.
+    ///
+    /// # Returns
+    /// The part of the chunk that can be passed on right away.
+    pub fn chunk<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        let offset = self.seen;
+        self.seen += data.len() as u64;
+
+        match self.overflow {
+            Overflow::Truncate => {
+                let allowed = (self.max_bytes as u64).saturating_sub(offset);
+                &data[..data.len().min(allowed as usize)]
+            }
+            Overflow::Skip => {
+                // Hold the body back until it is known to fit
+                if self.seen <= self.max_bytes as u64 {
+                    self.held.extend_from_slice(data);
+                } else {
+                    self.held = Vec::new();
+                }
+                &[]
+            }
+            Overflow::HeadTail => {
+                let head = self.max_bytes - self.max_bytes / 2;
+                let tail = self.max_bytes / 2;
+
+                let allowed = (head as u64).saturating_sub(offset) as usize;
+                let (now, rest) = data.split_at(data.len().min(allowed));
+
+                // Keep a sliding window of the last `tail` bytes
+                self.held.extend_from_slice(rest);
+                if self.held.len() > tail {
+                    self.held.drain(..self.held.len() - tail);
+                }
+                now
+            }
+        }
+    }
+
+    /// Flushes the held back part of the body once it is complete.
+    ///
+    /// # Returns
+    /// The remaining bytes to pass on, or `None` if the response must not be recorded.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        if self.is_skipped() {
+            return None;
+        }
+
+        Some(std::mem::take(&mut self.held))
+    }
+
+    /// Returns `true` if part of the body was dropped.
+    pub fn is_truncated(&self) -> bool {
+        self.overflow != Overflow::Skip && self.seen > self.max_bytes as u64
+    }
+
+    /// Returns `true` if the body exceeded the limit and must not be recorded.
+    pub fn is_skipped(&self) -> bool {
+        self.overflow == Overflow::Skip && self.seen > self.max_bytes as u64
+    }
+
+    /// Returns the total number of body bytes seen.
+    pub fn original_length(&self) -> u64 {
+        self.seen
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(overflow: Overflow, max_bytes: usize, body: &[u8]) -> (Option<Vec<u8>>, bool) {
+        let mut limiter = Limiter::new(&Limits {
+            max_bytes: Some(max_bytes),
+            overflow,
+            sample: None,
+        })
+        .unwrap();
+
+        let mut output = Vec::new();
+        for chunk in body.chunks(3) {
+            output.extend_from_slice(limiter.chunk(chunk));
+        }
+
+        let output = limiter.finish().map(|rest| [output, rest].concat());
+        assert_eq!(limiter.original_length(), body.len() as u64);
+        (output, limiter.is_truncated())
+    }
+
+    #[test]
+    fn test_limits() {
+        let body = b"0123456789abcdefghij";
+
+        assert_eq!(run(Overflow::Truncate, 8, body), (Some(b"01234567".to_vec()), true));
+        assert_eq!(run(Overflow::Truncate, 32, body), (Some(body.to_vec()), false));
+        assert_eq!(run(Overflow::Skip, 8, body), (None, false));
+        assert_eq!(run(Overflow::Skip, 20, body), (Some(body.to_vec()), false));
+        assert_eq!(run(Overflow::HeadTail, 9, body), (Some(b"01234ghij".to_vec()), true));
+        assert_eq!(run(Overflow::HeadTail, 20, body), (Some(body.to_vec()), false));
+    }
+
+    #[test]
+    fn test_stream_limits() {
+        use crate::capture::Exchange;
+        use crate::config::Config;
+        use crate::session::Session;
+        use std::sync::Arc;
+
+        let events = |overflow: &str| {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "version": 1,
+                "host": [{
+                    "host": "https://example.com",
+                    "xhr": "/events",
+                    "limits": { "max_bytes": 8, "overflow": overflow }
+                }],
+                "store": { "path": ":memory:" }
+            }))
+            .unwrap();
+            let session = Arc::new(Session::new(Some(&config)).unwrap());
+
+            let mut exchange = Exchange {
+                host: Some(config.host[0].clone()),
+                mime_type: String::from("text/event-stream"),
+                ..Default::default()
+            };
+            exchange.start(&session);
+            exchange.chunk(b"data: short\n\ndata: 0123456789abcdefghij\n\n");
+            exchange.chunk(b"data: tiny\n\n");
+            exchange.complete();
+
+            exchange
+                .records
+                .iter()
+                .map(|record| (record.event.as_ref().unwrap().sequence, record.body.clone(), record.truncated))
+                .collect::<Vec<_>>()
+        };
+
+        // Events are limited on their own, the stream as a whole exceeds the limit
+        let truncated = events("truncate");
+        assert_eq!(truncated[1], (1, String::from("01234567"), true));
+        assert_eq!(truncated[2], (2, String::from("tiny"), false));
+
+        let skipped = events("skip");
+        assert_eq!(skipped, [(0, String::from("short"), false), (2, String::from("tiny"), false)]);
+
+        let head_tail = events("head-tail");
+        assert_eq!(head_tail[1], (1, String::from("0123ghij"), true));
+    }
+}
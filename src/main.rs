@@ -1,4 +1,5 @@
 mod app;
+mod binding;
 mod client;
 mod filter;
 mod helpers;
@@ -6,8 +7,26 @@ mod process;
 mod tests;
 mod window;
 mod xhr;
-mod swizzle;
 mod config;
+mod capture;
+mod decode;
+mod inject;
+mod pipeline;
+mod processors;
+mod stream;
+mod limits;
+mod session;
+mod har;
+mod jsonl;
+mod store;
+mod search;
+mod warc;
+mod layout;
+mod blobs;
+mod compress;
+mod crypt;
+mod retention;
+mod cli;
 
 use std::sync::{Arc, Mutex};
 
@@ -19,6 +38,8 @@ use cef::rc::Rc;
 use cef::sandbox_info::SandboxInfo;
 use cef::{Settings, api_hash, execute_process, initialize, run_message_loop, shutdown, sys};
 use config::Config;
+use session::Session;
+use udata_swizzle::swizzle;
 
 ///
 /// In order for this example to work you must manually go to
@@ -31,6 +52,11 @@ use config::Config;
 /// sudo chmod 4755 chrome-sandbox
 ///
 fn main() {
+    // `udata query ...` and other subcommands don't start the browser
+    if let Some(code) = cli::run() {
+        std::process::exit(code);
+    }
+
     println!("Starting udata-rs program");
 
     let _ = api_hash(sys::CEF_API_VERSION_LAST, 0);
@@ -63,14 +89,29 @@ fn main() {
     }
 
     let window = Arc::new(Mutex::new(None));
-    let mut app = DemoApp::new(window.clone(), config);
+    let session_slot = Arc::new(Mutex::new(None));
+    let mut app = DemoApp::new(window.clone(), config.clone(), session_slot.clone());
 
-    let _ret = execute_process(
+    let ret = execute_process(
         Some(_args.as_main_args()),
         Some(&mut app),
         _sandbox.as_mut_ptr(),
     );
 
+    // Renderer, GPU and utility subprocesses stop here, before any output is opened
+    if ret >= 0 {
+        std::process::exit(ret);
+    }
+
+    let session = match Session::new(config.as_ref()) {
+        Ok(session) => Arc::new(session),
+        Err(e) => {
+            println!("[{}] {}", "error".red(), e);
+            std::process::exit(1);
+        }
+    };
+    *session_slot.lock().expect("Failed to lock session") = Some(session.clone());
+
     let settings = Settings::default();
     assert_eq!(
         initialize(
@@ -82,8 +123,12 @@ fn main() {
         1
     );
 
+    session.start_sweeper();
+
     run_message_loop();
 
+    session.finish();
+
     let window = window.lock().expect("Failed to lock window");
     let window = window.as_ref().expect("Window is None");
     assert!(window.has_one_ref());
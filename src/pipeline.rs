@@ -0,0 +1,268 @@
+//! Pluggable processing of captured responses.
+//!
+//! Every captured exchange runs through an ordered chain of `Processor`s, built per
+//! host entry from the configuration. Each processor sees the request start, every
+//! body chunk, the end of the body and any error. The output of one processor is the
+//! input of the next, so a chain such as
+//!
+//! ```json
+//! "processors": ["decompress", "charset", { "jsonpath": "$.data" }, { "redact": ["token"] }, "sink"]
+//! ```
+//!
+//! decompresses the body, converts it to UTF-8, extracts a subtree, masks secrets and
+//! finally records the result. Buffering processors return nothing from
+//! `on_body_chunk` and release their output from `on_body_complete`.
+use serde::Deserialize;
+
+use crate::capture::Exchange;
+use crate::config::HostEntry;
+use crate::processors::{
+    CharsetProcessor, DecodeProcessor, DecompressProcessor, JsonPathProcessor, RedactProcessor,
+    SinkProcessor, StreamProcessor,
+};
+use crate::stream::StreamMode;
+
+/// A stage of the capture pipeline.
+///
+/// All hooks have pass-through defaults, so a processor only implements what it needs.
+/// Returning an error from a body hook stops the pipeline and notifies every processor
+/// through `on_error`.
+pub trait Processor: Send {
+    /// Name of the processor, used in diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Called once, before any body data.
+    fn on_request_start(&mut self, _exchange: &mut Exchange) {}
+
+    /// Called for each chunk of body data produced by the previous processor.
+    ///
+    /// # Returns
+    /// The data to hand to the next processor.
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(chunk)
+    }
+
+    /// Called once the body is complete.
+    ///
+    /// # Parameters
+    /// - `rest`: Data flushed by the previous processor on completion.
+    ///
+    /// # Returns
+    /// The data to flush to the next processor.
+    fn on_body_complete(&mut self, _exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(rest)
+    }
+
+    /// Called when the request fails or a processor reports an error.
+    fn on_error(&mut self, _exchange: &mut Exchange, _error: &str) {}
+}
+
+/// A processor as written in the host entry configuration.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProcessorConfig {
+    /// Inflates gzip or zlib compressed bodies
+    Decompress,
+    /// Converts the body to UTF-8 using the response charset
+    Charset,
+    /// Detects swizzled bodies and attaches the decoded plaintext
+    Decode,
+    /// Replaces a JSON body with the values selected by a JSONPath expression
+    Jsonpath(String),
+    /// Masks the values of the listed JSON fields or form parameters
+    Redact(Vec<String>),
+    /// Records the body as a capture record
+    Sink,
+}
+
+impl ProcessorConfig {
+    /// Instantiates the configured processor.
+    ///
+    /// # Returns
+    /// The processor, or an error if its configuration is invalid.
+    pub fn build(&self) -> Result<Box<dyn Processor>, String> {
+        Ok(match self {
+            ProcessorConfig::Decompress => Box::new(DecompressProcessor::default()),
+            ProcessorConfig::Charset => Box::new(CharsetProcessor::default()),
+            ProcessorConfig::Decode => Box::new(DecodeProcessor::default()),
+            ProcessorConfig::Jsonpath(path) => Box::new(JsonPathProcessor::new(path)?),
+            ProcessorConfig::Redact(fields) => Box::new(RedactProcessor::new(fields.clone())),
+            ProcessorConfig::Sink => Box::new(SinkProcessor::default()),
+        })
+    }
+}
+
+/// An ordered chain of processors for a single exchange.
+#[derive(Default)]
+pub struct Pipeline {
+    processors: Vec<Box<dyn Processor>>,
+    failed: bool,
+}
+
+impl Pipeline {
+    /// Creates a pipeline from a list of processors.
+    pub fn new(processors: Vec<Box<dyn Processor>>) -> Self {
+        Self {
+            processors,
+            failed: false,
+        }
+    }
+
+    /// Builds the pipeline configured for a host entry.
+    ///
+    /// Streaming responses are split into events first. Without an explicit
+    /// `processors` list the chain is `decode` (when enabled) followed by `sink`.
+    /// Invalid processors are reported and left out of the chain.
+    ///
+    /// # Parameters
+    /// - `host`: The host entry that matched the request.
+    /// - `mime_type`: The mime type of the response.
+    pub fn for_host(host: &HostEntry, mime_type: &str) -> Self {
+        let mut processors: Vec<Box<dyn Processor>> = Vec::new();
+
+        if let Some(mode) = host.stream.or_else(|| StreamMode::from_mime_type(mime_type)) {
+            processors.push(Box::new(StreamProcessor::new(mode, &host.limits)));
+        }
+
+        if host.processors.is_empty() {
+            if host.decode {
+                processors.push(Box::new(DecodeProcessor::default()));
+            }
+            processors.push(Box::new(SinkProcessor::default()));
+        }
+
+        for config in &host.processors {
+            match config.build() {
+                Ok(processor) => processors.push(processor),
+                Err(e) => eprintln!("Invalid processor {:?} for {}: {}", config, host.host, e),
+            }
+        }
+
+        Self::new(processors)
+    }
+
+    /// Returns `true` once a processor failed or an error was reported, the rest of
+    /// the body is no longer processed then.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Notifies every processor that the exchange started.
+    pub fn start(&mut self, exchange: &mut Exchange) {
+        for processor in self.processors.iter_mut() {
+            processor.on_request_start(exchange);
+        }
+    }
+
+    /// Runs a chunk of body data through the chain.
+    pub fn chunk(&mut self, exchange: &mut Exchange, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+
+        let mut data = data.to_vec();
+        for index in 0..self.processors.len() {
+            if data.is_empty() {
+                break;
+            }
+
+            match self.processors[index].on_body_chunk(exchange, data) {
+                Ok(output) => data = output,
+                Err(e) => return self.fail(exchange, index, &e),
+            }
+        }
+    }
+
+    /// Flushes the chain once the body is complete.
+    pub fn complete(&mut self, exchange: &mut Exchange) {
+        if self.failed {
+            return;
+        }
+
+        let mut data = Vec::new();
+        for index in 0..self.processors.len() {
+            match self.processors[index].on_body_complete(exchange, data) {
+                Ok(output) => data = output,
+                Err(e) => return self.fail(exchange, index, &e),
+            }
+        }
+    }
+
+    /// Notifies every processor of an error.
+    pub fn error(&mut self, exchange: &mut Exchange, error: &str) {
+        if self.failed {
+            return;
+        }
+
+        self.failed = true;
+        for processor in self.processors.iter_mut() {
+            processor.on_error(exchange, error);
+        }
+    }
+
+    fn fail(&mut self, exchange: &mut Exchange, index: usize, error: &str) {
+        let error = format!("{}: {}", self.processors[index].name(), error);
+        self.error(exchange, &error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Appends its tag to every chunk and logs the hooks it sees.
+    struct Tag(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Processor for Tag {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn on_body_chunk(&mut self, _: &mut Exchange, mut chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+            if chunk == b"fail" {
+                return Err(String::from("bad chunk"));
+            }
+            chunk.extend_from_slice(self.0.as_bytes());
+            self.1.lock().unwrap().push(String::from_utf8(chunk.clone()).unwrap());
+            Ok(chunk)
+        }
+
+        fn on_error(&mut self, _: &mut Exchange, error: &str) {
+            self.1.lock().unwrap().push(format!("{} error {}", self.0, error));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut exchange = Exchange::default();
+        let mut pipeline = Pipeline::new(vec![
+            Box::new(Tag("a", log.clone())),
+            Box::new(Tag("b", log.clone())),
+        ]);
+
+        pipeline.chunk(&mut exchange, b"x");
+        assert!(!pipeline.failed());
+        pipeline.chunk(&mut exchange, b"fail");
+        assert!(pipeline.failed());
+        pipeline.chunk(&mut exchange, b"y");
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["xa", "xab", "a error a: bad chunk", "b error a: bad chunk"]
+        );
+    }
+
+    #[test]
+    fn test_processor_config() {
+        let processors: Vec<ProcessorConfig> = serde_json::from_str(
+            r#"["decompress", "charset", { "jsonpath": "$.data" }, { "redact": ["token"] }, "sink"]"#,
+        )
+        .unwrap();
+
+        assert_eq!(processors.len(), 5);
+        assert!(processors.iter().all(|p| p.build().is_ok()));
+        assert!(ProcessorConfig::Jsonpath(String::from("data")).build().is_err());
+    }
+}
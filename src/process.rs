@@ -14,6 +14,7 @@ use cef::{
 };
 
 use crate::config::Config;
+use crate::session::Session;
 use crate::{client::DemoClient, window::DemoWindowDelegate};
 
 /// Handler for browser process events.
@@ -25,10 +26,12 @@ use crate::{client::DemoClient, window::DemoWindowDelegate};
 /// # Fields
 /// * `object` - The raw CEF object pointer for reference counting
 /// * `window` - A thread-safe reference to the application's main window
+/// * `session` - Capture state shared by every request
 pub struct DemoBrowserProcessHandler {
     pub object: *mut RcImpl<sys::cef_browser_process_handler_t, Self>,
     pub window: Arc<Mutex<Option<Window>>>,
     pub config: Option<Config>,
+    pub session: Arc<Session>,
 }
 
 impl DemoBrowserProcessHandler {
@@ -36,14 +39,16 @@ impl DemoBrowserProcessHandler {
     ///
     /// # Arguments
     /// * `window` - A thread-safe reference to the application's main window (initially None)
+    /// * `session` - Capture state shared by every request
     ///
     /// # Returns
     /// A new `BrowserProcessHandler` instance wrapping the `DemoBrowserProcessHandler` implementation
-    pub fn new(window: Arc<Mutex<Option<Window>>>, config: Option<Config>) -> BrowserProcessHandler {
+    pub fn new(window: Arc<Mutex<Option<Window>>>, config: Option<Config>, session: Arc<Session>) -> BrowserProcessHandler {
         BrowserProcessHandler::new(Self {
             object: std::ptr::null_mut(),
             window,
             config,
+            session,
         })
     }
 }
@@ -65,7 +70,7 @@ impl ImplBrowserProcessHandler for DemoBrowserProcessHandler {
     fn on_context_initialized(&self) {
         println!("cef context intiialized");
         
-        let mut client = DemoClient::new(self.config.clone());
+        let mut client = DemoClient::new(self.config.clone(), self.session.clone());
         
         let url = {
             if let Some(config) = self.config.as_ref() {
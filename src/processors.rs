@@ -0,0 +1,662 @@
+//! Built-in processors for the capture pipeline.
+//!
+//! See `pipeline.rs` for how processors are chained and configured.
+use encoding_rs::{Encoding, UTF_8};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde_json::Value;
+use std::io::Read;
+
+use crate::capture::{CaptureRecord, DecodedBody, Exchange, encode_body};
+use crate::decode;
+use crate::limits::{Limiter, Limits};
+use crate::pipeline::Processor;
+use crate::store::decode_record_body;
+use crate::stream::{Segmenter, StreamEvent, StreamMode};
+
+//
+// Stream
+//
+
+/// Splits a streaming response into events and records each one separately.
+///
+/// Events are not passed downstream, since the response may never complete. The
+/// host's limits apply to each event, and events skipped by them leave a gap in the
+/// sequence. Once the segmenter gives up on the framing, the rest of the response
+/// passes through instead, limited as a whole.
+pub struct StreamProcessor {
+    segmenter: Segmenter,
+    sequence: u64,
+    limits: Limits,
+    /// Limits the data passed through, once the segmenter gave up
+    passthrough: Option<Limiter>,
+}
+
+impl StreamProcessor {
+    /// Creates a stream processor for the given framing.
+    ///
+    /// # Parameters
+    /// - `mode`: The framing of the stream.
+    /// - `limits`: Limits of the host entry, `max_bytes` also bounds length-prefixed
+    ///   records.
+    pub fn new(mode: StreamMode, limits: &Limits) -> Self {
+        Self {
+            segmenter: Segmenter::new(mode, limits.max_bytes),
+            sequence: 0,
+            limits: limits.clone(),
+            passthrough: Limiter::new(limits),
+        }
+    }
+
+    fn record(&mut self, exchange: &mut Exchange, events: Vec<StreamEvent>) {
+        for mut event in events {
+            let sequence = self.sequence;
+            self.sequence += 1;
+
+            let Some((data, original_length)) = self.limits.apply(std::mem::take(&mut event.data)) else {
+                continue;
+            };
+            event.data = data;
+
+            let mut record = CaptureRecord::from_event(exchange, sequence, event);
+            record.truncated = original_length.is_some();
+            record.original_length = original_length;
+            exchange.emit(record);
+        }
+    }
+
+    /// Applies the limits to the data passed through.
+    ///
+    /// # Parameters
+    /// - `complete`: Whether the response is complete, the held back data is released.
+    fn pass(&mut self, exchange: &mut Exchange, data: Vec<u8>, complete: bool) -> Vec<u8> {
+        let Some(limiter) = self.passthrough.as_mut() else {
+            return data;
+        };
+
+        let mut output = limiter.chunk(&data).to_vec();
+        if complete {
+            output.extend(limiter.finish().unwrap_or_default());
+            if limiter.is_truncated() {
+                exchange.truncated = true;
+                exchange.original_length = Some(limiter.original_length());
+            }
+        }
+        output
+    }
+}
+
+impl Processor for StreamProcessor {
+    fn name(&self) -> &'static str {
+        "stream"
+    }
+
+    fn on_body_chunk(&mut self, exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        let events = self.segmenter.push(&chunk);
+        self.record(exchange, events);
+        let pending = self.segmenter.take_pending();
+        Ok(self.pass(exchange, pending, false))
+    }
+
+    fn on_body_complete(&mut self, exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut events = self.segmenter.push(&rest);
+        events.extend(self.segmenter.finish());
+        self.record(exchange, events);
+        let pending = self.segmenter.take_pending();
+        Ok(self.pass(exchange, pending, true))
+    }
+}
+
+//
+// Decompress
+//
+
+/// Inflates bodies that are themselves gzip or zlib streams.
+///
+/// Transport compression is already removed by the browser; this handles payloads
+/// compressed by the application. Other bodies pass through unchanged.
+#[derive(Default)]
+pub struct DecompressProcessor {
+    body: Vec<u8>,
+}
+
+impl Processor for DecompressProcessor {
+    fn name(&self) -> &'static str {
+        "decompress"
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, _exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        let mut output = Vec::new();
+        match body.as_slice() {
+            [0x1f, 0x8b, ..] => GzDecoder::new(body.as_slice())
+                .read_to_end(&mut output)
+                .map_err(|e| e.to_string())?,
+            [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => ZlibDecoder::new(body.as_slice())
+                .read_to_end(&mut output)
+                .map_err(|e| e.to_string())?,
+            _ => return Ok(body),
+        };
+
+        Ok(output)
+    }
+}
+
+//
+// Charset
+//
+
+/// Converts the body to UTF-8 according to the response charset.
+///
+/// Any charset label known to browsers is supported, with the same mapping (Latin-1
+/// is decoded as windows-1252); bodies in unknown charsets pass through unchanged.
+#[derive(Default)]
+pub struct CharsetProcessor {
+    body: Vec<u8>,
+}
+
+impl Processor for CharsetProcessor {
+    fn name(&self) -> &'static str {
+        "charset"
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        Ok(to_utf8(&exchange.charset, body))
+    }
+}
+
+/// Converts `body` from `charset` to UTF-8.
+fn to_utf8(charset: &str, body: Vec<u8>) -> Vec<u8> {
+    // A byte order mark takes precedence over the declared charset
+    let encoding = match Encoding::for_bom(&body) {
+        Some((encoding, _)) => encoding,
+        None => match Encoding::for_label(charset.trim().as_bytes()) {
+            Some(encoding) if encoding != UTF_8 => encoding,
+            _ => return body,
+        },
+    };
+
+    encoding.decode(&body).0.into_owned().into_bytes()
+}
+
+//
+// Decode
+//
+
+/// Detects swizzled bodies and attaches the decoded plaintext to the exchange.
+///
+/// The body itself passes through unchanged, so the original is still recorded.
+#[derive(Default)]
+pub struct DecodeProcessor {
+    body: Vec<u8>,
+}
+
+impl Processor for DecodeProcessor {
+    fn name(&self) -> &'static str {
+        "decode"
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        let key = exchange.host.as_ref().and_then(|host| host.swizzle_key.as_deref()).map(str::as_bytes);
+        exchange.decoded = decode::detect(&body, key).map(|(scheme, plain)| DecodedBody::new(scheme, &plain));
+        Ok(body)
+    }
+}
+
+//
+// JSONPath
+//
+
+/// A single step of a JSONPath expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// `.name` or `['name']`
+    Field(String),
+    /// `[n]`, negative indices count from the end
+    Index(i64),
+    /// `.*` or `[*]`
+    Wildcard,
+    /// `..name`
+    Descend(String),
+}
+
+/// Replaces a JSON body with the values selected by a JSONPath expression.
+///
+/// Supports the common subset of JSONPath: `$`, `.name`, `['name']`, `[n]`, `[*]`,
+/// `.*` and recursive descent with `..name`. A single match is emitted as is, several
+/// matches as an array. Bodies that aren't JSON pass through unchanged.
+pub struct JsonPathProcessor {
+    steps: Vec<Step>,
+    body: Vec<u8>,
+}
+
+impl JsonPathProcessor {
+    /// Parses a JSONPath expression.
+    ///
+    /// # Returns
+    /// The processor, or an error describing why the expression is invalid.
+    pub fn new(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            steps: parse_path(path)?,
+            body: Vec::new(),
+        })
+    }
+}
+
+impl Processor for JsonPathProcessor {
+    fn name(&self) -> &'static str {
+        "jsonpath"
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, _exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        let Ok(value) = serde_json::from_slice::<Value>(&body) else {
+            return Ok(body);
+        };
+
+        let mut matches = select(&value, &self.steps);
+        let selected = if matches.len() == 1 {
+            matches.remove(0).clone()
+        } else {
+            Value::Array(matches.into_iter().cloned().collect())
+        };
+
+        serde_json::to_vec(&selected).map_err(|e| e.to_string())
+    }
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let mut rest = path
+        .trim()
+        .strip_prefix('$')
+        .ok_or_else(|| String::from("path must start with '$'"))?;
+    let mut steps = Vec::new();
+
+    let name_len = |s: &str| s.find(['.', '[']).unwrap_or(s.len());
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            let len = name_len(after);
+            if len == 0 {
+                return Err(String::from("expected a field name after '..'"));
+            }
+            steps.push(Step::Descend(after[..len].to_string()));
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let len = name_len(after);
+            match &after[..len] {
+                "" => return Err(String::from("expected a field name after '.'")),
+                "*" => steps.push(Step::Wildcard),
+                name => steps.push(Step::Field(name.to_string())),
+            }
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| String::from("unterminated '['"))?;
+            let inner = after[..end].trim();
+
+            let quoted = inner
+                .strip_prefix('\'')
+                .and_then(|s| s.strip_suffix('\''))
+                .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+
+            if inner == "*" {
+                steps.push(Step::Wildcard);
+            } else if let Some(name) = quoted {
+                steps.push(Step::Field(name.to_string()));
+            } else {
+                let index = inner
+                    .parse()
+                    .map_err(|_| format!("invalid index '{}'", inner))?;
+                steps.push(Step::Index(index));
+            }
+            rest = &after[end + 1..];
+        } else {
+            return Err(format!("unexpected '{}'", rest));
+        }
+    }
+
+    Ok(steps)
+}
+
+fn select<'a>(value: &'a Value, steps: &[Step]) -> Vec<&'a Value> {
+    let Some((step, rest)) = steps.split_first() else {
+        return vec![value];
+    };
+
+    let next: Vec<&Value> = match step {
+        Step::Field(name) => value.get(name).into_iter().collect(),
+        Step::Index(index) => value
+            .as_array()
+            .and_then(|array| {
+                let index = if *index < 0 { array.len() as i64 + index } else { *index };
+                usize::try_from(index).ok().and_then(|i| array.get(i))
+            })
+            .into_iter()
+            .collect(),
+        Step::Wildcard => match value {
+            Value::Array(array) => array.iter().collect(),
+            Value::Object(object) => object.values().collect(),
+            _ => Vec::new(),
+        },
+        Step::Descend(name) => {
+            let mut found = Vec::new();
+            descend(value, name, &mut found);
+            found
+        }
+    };
+
+    next.into_iter().flat_map(|value| select(value, rest)).collect()
+}
+
+fn descend<'a>(value: &'a Value, name: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(value) = object.get(name) {
+                found.push(value);
+            }
+            object.values().for_each(|value| descend(value, name, found));
+        }
+        Value::Array(array) => array.iter().for_each(|value| descend(value, name, found)),
+        _ => {}
+    }
+}
+
+//
+// Redact
+//
+
+/// Placeholder written in place of redacted values.
+const REDACTED: &str = "[REDACTED]";
+
+/// Masks the values of sensitive fields.
+///
+/// Field names are matched case-insensitively against JSON object keys at any depth,
+/// or against parameter names of form-encoded bodies. The request body is redacted too,
+/// each body being recognised as a form by its own type: the request's `Content-Type`
+/// header, or the response mime type.
+pub struct RedactProcessor {
+    fields: Vec<String>,
+    body: Vec<u8>,
+}
+
+impl RedactProcessor {
+    /// Creates a processor redacting the given field names.
+    pub fn new(fields: Vec<String>) -> Self {
+        Self {
+            fields: fields.iter().map(|f| f.to_ascii_lowercase()).collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn is_sensitive(&self, name: &str) -> bool {
+        self.fields.iter().any(|f| name.eq_ignore_ascii_case(f))
+    }
+
+    fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    if self.is_sensitive(key) {
+                        *value = Value::String(String::from(REDACTED));
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            Value::Array(array) => array.iter_mut().for_each(|value| self.redact_json(value)),
+            _ => {}
+        }
+    }
+
+    /// Redacts a JSON or form-encoded body, other bodies are returned unchanged.
+    ///
+    /// # Parameters
+    /// - `body`: The request or response body.
+    /// - `content_type`: Mime type of the body, parameters such as `charset` are ignored.
+    fn redact(&self, body: Vec<u8>, content_type: &str) -> Result<Vec<u8>, String> {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(&body) {
+            self.redact_json(&mut value);
+            return serde_json::to_vec(&value).map_err(|e| e.to_string());
+        }
+
+        let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+        if mime_type.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            && let Ok(text) = std::str::from_utf8(&body)
+        {
+            return Ok(self.redact_form(text).into_bytes());
+        }
+
+        Ok(body)
+    }
+
+    fn redact_form(&self, body: &str) -> String {
+        body.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.is_sensitive(key) => format!("{}={}", key, REDACTED),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+impl Processor for RedactProcessor {
+    fn name(&self) -> &'static str {
+        "redact"
+    }
+
+    fn on_request_start(&mut self, exchange: &mut Exchange) {
+        let Some(request) = exchange.request.as_mut() else {
+            return;
+        };
+        let content_type = request
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+
+        if let Some(body) = request.body.take() {
+            request.body = Some(self.redact(body.clone(), &content_type).unwrap_or(body));
+        }
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        if let Some(decoded) = exchange.decoded.as_mut() {
+            let plain = decode_record_body(&decoded.body, decoded.encoding);
+            (decoded.body, decoded.encoding) = encode_body(&self.redact(plain, &exchange.mime_type)?);
+        }
+
+        self.redact(body, &exchange.mime_type)
+    }
+}
+
+//
+// Sink
+//
+
+/// Records the body it receives as a capture record.
+#[derive(Default)]
+pub struct SinkProcessor {
+    body: Vec<u8>,
+}
+
+impl Processor for SinkProcessor {
+    fn name(&self) -> &'static str {
+        "sink"
+    }
+
+    fn on_body_chunk(&mut self, _exchange: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(chunk);
+        Ok(Vec::new())
+    }
+
+    fn on_body_complete(&mut self, exchange: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.body.extend(rest);
+        let body = std::mem::take(&mut self.body);
+
+        if !body.is_empty() {
+            exchange.emit_body(&body);
+        }
+        Ok(Vec::new())
+    }
+
+    fn on_error(&mut self, exchange: &mut Exchange, error: &str) {
+        eprintln!("Capture of {} ({}) failed: {}", exchange.url, exchange.uuid, error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture::RequestInfo;
+    use crate::pipeline::Pipeline;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects whatever reaches the end of the chain.
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl Processor for Collect {
+        fn name(&self) -> &'static str {
+            "collect"
+        }
+
+        fn on_body_chunk(&mut self, _: &mut Exchange, chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+            self.0.lock().unwrap().extend(chunk);
+            Ok(Vec::new())
+        }
+
+        fn on_body_complete(&mut self, _: &mut Exchange, rest: Vec<u8>) -> Result<Vec<u8>, String> {
+            self.0.lock().unwrap().extend(rest);
+            Ok(Vec::new())
+        }
+    }
+
+    fn run(mut processors: Vec<Box<dyn Processor>>, exchange: &mut Exchange, body: &[u8]) -> Vec<u8> {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        processors.push(Box::new(Collect(output.clone())));
+
+        let mut pipeline = Pipeline::new(processors);
+        for chunk in body.chunks(3) {
+            pipeline.chunk(exchange, chunk);
+        }
+        pipeline.complete(exchange);
+
+        output.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_decompress_jsonpath_redact() {
+        let body = br#"{"data":{"items":[{"id":1,"token":"a"},{"id":2,"Token":"b"}]},"meta":{}}"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut exchange = Exchange::default();
+        let output = run(
+            vec![
+                Box::new(DecompressProcessor::default()),
+                Box::new(JsonPathProcessor::new("$.data.items[*]").unwrap()),
+                Box::new(RedactProcessor::new(vec![String::from("token")])),
+            ],
+            &mut exchange,
+            &compressed,
+        );
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"[{"id":1,"token":"[REDACTED]"},{"Token":"[REDACTED]","id":2}]"#
+        );
+
+        // A form posted to a JSON API
+        let mut exchange = Exchange {
+            mime_type: String::from("application/json"),
+            request: Some(RequestInfo {
+                method: String::from("POST"),
+                url: String::from("https://example.com/login"),
+                headers: vec![(
+                    String::from("Content-Type"),
+                    String::from("application/x-www-form-urlencoded; charset=UTF-8"),
+                )],
+                body: Some(b"user=a&Token=b".to_vec()),
+                page: None,
+                started: chrono::Utc::now(),
+                sent: std::time::Instant::now(),
+            }),
+            ..Default::default()
+        };
+        let mut redact = RedactProcessor::new(vec![String::from("token")]);
+        redact.on_request_start(&mut exchange);
+        let output = redact.on_body_complete(&mut exchange, b"token=c".to_vec()).unwrap();
+        assert_eq!(exchange.request.unwrap().body.unwrap(), b"user=a&Token=[REDACTED]");
+        assert_eq!(output, b"token=c");
+    }
+
+    #[test]
+    fn test_jsonpath() {
+        let value: Value = serde_json::from_str(r#"{"a":[{"b":1},{"b":2,"c":{"b":3}}],"d":"x"}"#).unwrap();
+
+        let eval = |path: &str| {
+            select(&value, &parse_path(path).unwrap())
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(eval("$.d"), vec![Value::from("x")]);
+        assert_eq!(eval("$['a'][-1].b"), vec![Value::from(2)]);
+        assert_eq!(eval("$..b"), vec![Value::from(1), Value::from(2), Value::from(3)]);
+        assert_eq!(eval("$.a[*].c.b"), vec![Value::from(3)]);
+        assert!(eval("$.missing").is_empty());
+        assert!(parse_path("$.a[").is_err());
+    }
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(to_utf8("iso-8859-1", vec![0x63, 0x61, 0x66, 0xe9]), "café".as_bytes());
+        assert_eq!(to_utf8("", vec![0xff, 0xfe, b'h', 0, b'i', 0]), b"hi");
+        assert_eq!(to_utf8("utf-8", vec![0xef, 0xbb, 0xbf, b'o', b'k']), b"ok");
+        assert_eq!(to_utf8("windows-1252", vec![0x80, b'5', 0x93, b'x', 0x94]), "€5“x”".as_bytes());
+        assert_eq!(to_utf8("x-unknown", vec![0xe9]), [0xe9]);
+    }
+}
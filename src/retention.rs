@@ -0,0 +1,455 @@
+//! Retention of the capture files.
+//!
+//! A top-level `retention` section bounds the disk space used by the outputs. The
+//! sweeper runs at startup and then every `interval` seconds, and deletes, oldest
+//! first:
+//!
+//! 1. files older than `max_age` seconds
+//! 2. files of a host entry beyond `max_files_per_host`
+//! 3. files beyond `max_bytes` in total
+//!
+//! Only files the outputs write are considered: JSONL files of the host entries, the
+//! HAR file, WARC archives and indexes, blobs and per-request capture files, plus
+//! everything under the extra `dirs`. Files still being written by the session, the
+//! capture store and the search index count towards the limits but are never deleted.
+//!
+//! Blobs still referenced by a kept JSONL file, capture file or store record are kept
+//! too, whatever their age. A blob is only deleted once the records pointing to it are.
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::compress::{self, Codec};
+use crate::crypt::{self, Keyring};
+
+/// Retention policy, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionConfig {
+    /// Delete files older than this many seconds
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Keep the total size of the files under this many bytes
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Keep at most this many files per host entry
+    #[serde(default)]
+    pub max_files_per_host: Option<usize>,
+    /// Seconds between two sweeps
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Additional directories whose files are all subject to the policy
+    #[serde(default)]
+    pub dirs: Vec<String>,
+}
+
+fn default_interval() -> u64 {
+    600
+}
+
+/// Which files of a directory belong to an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// JSONL files starting with the given prefix
+    Jsonl(String),
+    /// WARC archives and their CDXJ indexes
+    Warc,
+    /// Blobs, stored under a two character directory
+    Blob,
+    /// Per-request capture files (`<uuid>.json`, `<uuid>.body`) and session manifests
+    /// (`manifest-<session>.jsonl`), possibly compressed and encrypted
+    Layout,
+    /// The file with the given name, possibly compressed and encrypted
+    File(String),
+    /// The SQLite database with the given name and its journal files, which are never
+    /// deleted
+    Database(String),
+    /// Every file
+    Any,
+}
+
+impl Kind {
+    fn matches(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        match self {
+            Kind::Jsonl(prefix) => name.starts_with(&format!("{}-", prefix)) && name.contains(".jsonl"),
+            Kind::Warc => name.contains(".warc") || name.contains(".cdxj"),
+            Kind::Blob => {
+                let parent = path.parent().and_then(Path::file_name).map(|name| name.to_string_lossy());
+                let is_hex = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit());
+                parent.is_some_and(|parent| parent.len() == 2 && is_hex(&parent))
+                    && is_hex(name.split('.').next().unwrap_or_default())
+            }
+            Kind::Layout => {
+                let name = name.strip_suffix(crypt::EXTENSION).unwrap_or(&name);
+                let name = [Codec::Gzip, Codec::Zstd]
+                    .iter()
+                    .find_map(|codec| name.strip_suffix(codec.extension()))
+                    .unwrap_or(name);
+                if name.starts_with("manifest-") {
+                    return name.ends_with(".jsonl");
+                }
+                // The template may add other segments around the uuid
+                let stem = name.strip_suffix(".json").or_else(|| name.strip_suffix(".body"));
+                stem.is_some_and(|stem| {
+                    (0..stem.len().saturating_sub(35))
+                        .any(|index| stem.get(index..index + 36).is_some_and(|uuid| uuid::Uuid::try_parse(uuid).is_ok()))
+                })
+            }
+            Kind::File(file) => name
+                .strip_prefix(file.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.')),
+            Kind::Database(file) => name
+                .strip_prefix(file.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-')),
+            Kind::Any => true,
+        }
+    }
+
+    /// Returns `true` if the files may hold capture records referencing blobs.
+    fn has_records(&self) -> bool {
+        matches!(self, Kind::Jsonl(_) | Kind::Layout)
+    }
+}
+
+/// A directory the policy applies to.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub dir: PathBuf,
+    /// Host entry the files belong to, for `max_files_per_host`
+    pub host: Option<String>,
+    pub kind: Kind,
+}
+
+/// A file found by the sweeper.
+#[derive(Debug, Clone)]
+struct File {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    host: Option<String>,
+    kind: Kind,
+    /// Still being written by the session
+    in_use: bool,
+}
+
+/// Result of a sweep.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sweep {
+    pub deleted: usize,
+    pub freed: u64,
+}
+
+/// Applies a retention policy to the output directories.
+#[derive(Debug)]
+pub struct Sweeper {
+    config: RetentionConfig,
+    targets: Vec<Target>,
+    /// Decrypts the files read for blob references
+    keyring: Option<Arc<Keyring>>,
+}
+
+impl Sweeper {
+    /// Creates a sweeper for the output directories and the configured `dirs`.
+    ///
+    /// # Parameters
+    /// - `config`: The retention policy.
+    /// - `targets`: The directories written by the outputs.
+    /// - `keyring`: Decrypts the capture files, to find the blobs they reference.
+    pub fn new(config: &RetentionConfig, mut targets: Vec<Target>, keyring: Option<Arc<Keyring>>) -> Self {
+        targets.extend(config.dirs.iter().map(|dir| Target {
+            dir: PathBuf::from(dir),
+            host: None,
+            kind: Kind::Any,
+        }));
+
+        Self {
+            config: config.clone(),
+            targets,
+            keyring,
+        }
+    }
+
+    /// Returns the time between two sweeps.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval.max(1))
+    }
+
+    /// Deletes the files the policy doesn't allow to keep.
+    ///
+    /// # Parameters
+    /// - `in_use`: Files still being written, which are kept regardless.
+    /// - `referenced`: Hashes of the blobs referenced by the capture store.
+    pub fn sweep(&self, in_use: &HashSet<PathBuf>, referenced: &HashSet<String>) -> Sweep {
+        let mut files = BTreeMap::new();
+        for target in &self.targets {
+            scan(&target.dir, target, &mut files);
+        }
+
+        let files = files
+            .into_values()
+            .map(|file| File {
+                in_use: file.in_use || in_use.contains(&file.path),
+                ..file
+            })
+            .collect::<Vec<_>>();
+
+        let mut expired = self.expired(&files, SystemTime::now());
+        if expired.iter().any(|&index| files[index].kind == Kind::Blob) {
+            let references = self.references(&files, &expired, referenced);
+            expired.retain(|&index| {
+                let file = &files[index];
+                file.kind != Kind::Blob
+                    || references.as_ref().is_some_and(|references| {
+                        blob_hash(&file.path).is_none_or(|hash| !references.contains(&hash))
+                    })
+            });
+        }
+
+        let mut sweep = Sweep::default();
+        for index in expired {
+            let file = &files[index];
+            match std::fs::remove_file(&file.path) {
+                Ok(()) => {
+                    sweep.deleted += 1;
+                    sweep.freed += file.size;
+                    remove_empty_parents(&file.path, &self.targets);
+                }
+                Err(e) => eprintln!("Failed to delete {:?}: {}", file.path, e),
+            }
+        }
+        sweep
+    }
+
+    /// Selects the files to delete.
+    ///
+    /// # Returns
+    /// Indexes into `files`.
+    fn expired(&self, files: &[File], now: SystemTime) -> Vec<usize> {
+        let mut order = (0..files.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| files[index].modified);
+
+        let mut deleted = vec![false; files.len()];
+
+        if let Some(max_age) = self.config.max_age.map(Duration::from_secs) {
+            for &index in &order {
+                let age = now.duration_since(files[index].modified).unwrap_or_default();
+                deleted[index] = age > max_age && !files[index].in_use;
+            }
+        }
+
+        if let Some(max_files) = self.config.max_files_per_host {
+            let mut kept = HashMap::new();
+            // Newest first, so the oldest files are the ones over the limit
+            for &index in order.iter().rev() {
+                let Some(host) = files[index].host.as_ref().filter(|_| !deleted[index]) else {
+                    continue;
+                };
+                let count = kept.entry(host).or_insert(0);
+                *count += 1;
+                deleted[index] = *count > max_files && !files[index].in_use;
+            }
+        }
+
+        if let Some(max_bytes) = self.config.max_bytes {
+            let mut total = (0..files.len()).filter(|&index| !deleted[index]).map(|index| files[index].size).sum::<u64>();
+            for &index in &order {
+                if total <= max_bytes {
+                    break;
+                }
+                if !deleted[index] && !files[index].in_use {
+                    deleted[index] = true;
+                    total -= files[index].size;
+                }
+            }
+        }
+
+        order.into_iter().filter(|&index| deleted[index]).collect()
+    }
+
+    /// Collects the blob hashes referenced by the record files that are kept.
+    ///
+    /// # Parameters
+    /// - `files`: The files found by the sweep.
+    /// - `expired`: Indexes of the files about to be deleted.
+    /// - `referenced`: Hashes referenced outside of `files`.
+    ///
+    /// # Returns
+    /// `None` if a kept file can't be read, no blob may be deleted then.
+    fn references(&self, files: &[File], expired: &[usize], referenced: &HashSet<String>) -> Option<HashSet<String>> {
+        let expired = expired.iter().collect::<HashSet<_>>();
+        let mut references = referenced.clone();
+
+        for (index, file) in files.iter().enumerate() {
+            if expired.contains(&index) || !file.kind.has_records() {
+                continue;
+            }
+            if let Err(e) = read_references(&file.path, self.keyring.as_deref(), &mut references) {
+                eprintln!("Retention: keeping blobs, failed to read {:?}: {}", file.path, e);
+                return None;
+            }
+        }
+        Some(references)
+    }
+}
+
+/// Lists the files of `target` under `dir`, recursively unless the target is a single
+/// file. An empty `dir` is the current directory.
+fn scan(dir: &Path, target: &Target, files: &mut BTreeMap<PathBuf, File>) {
+    let Ok(entries) = std::fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = dir.join(entry.file_name());
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            if !matches!(target.kind, Kind::File(_) | Kind::Database(_)) {
+                scan(&path, target, files);
+            }
+        } else if metadata.is_file() && target.kind.matches(&path) {
+            files.entry(path.clone()).or_insert(File {
+                path,
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                host: target.host.clone(),
+                kind: target.kind.clone(),
+                in_use: matches!(target.kind, Kind::Database(_)),
+            });
+        }
+    }
+}
+
+/// Adds the blob hashes found in a capture file to `references`.
+///
+/// A file still being written may end with a partial line, which is ignored.
+fn read_references(path: &Path, keyring: Option<&Keyring>, references: &mut HashSet<String>) -> std::io::Result<()> {
+    const PREFIX: &str = "sha256:";
+
+    let reader = BufReader::new(compress::open(path, keyring)?);
+    for line in reader.split(b'\n') {
+        let Ok(line) = line else {
+            break;
+        };
+        let line = String::from_utf8_lossy(&line);
+        for (index, _) in line.match_indices(PREFIX) {
+            let hex = &line[index + PREFIX.len()..];
+            let length = hex.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(hex.len());
+            references.insert(format!("{}{}", PREFIX, &hex[..length]));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the hash of the blob stored at `path`, see `BlobStore::path`.
+fn blob_hash(path: &Path) -> Option<String> {
+    let prefix = path.parent()?.file_name()?.to_string_lossy();
+    let name = path.file_name()?.to_string_lossy();
+    let rest = name.split('.').next().unwrap_or_default();
+    Some(format!("sha256:{}{}", prefix, rest))
+}
+
+/// Removes the directories left empty by a deletion, up to the target directory.
+fn remove_empty_parents(path: &Path, targets: &[Target]) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if targets.iter().any(|target| target.dir == current) || std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blobs::BlobStore;
+    use crate::compress::Compressor;
+
+    #[test]
+    fn test_sweep() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let now = SystemTime::now();
+        let write_data = |name: &str, data: &[u8], age: u64| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, data).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+            path
+        };
+        let write = |name: &str, size: usize, age: u64| write_data(name, &vec![b'x'; size], age);
+
+        let old = write("api-s1-0001.jsonl", 10, 10_000);
+        let first = write("api-s2-0001.jsonl", 10, 500);
+        let second = write("api-s2-0002.jsonl", 10, 400);
+        let third = write_data("api-s2-0003.jsonl", b"sha256:789", 300);
+        let current = write("api-s2-0004.jsonl", 10, 0);
+        let blob = write("blobs/ab/cdef", 100, 200);
+        // Old, but referenced by a kept JSONL file and by the store
+        let referenced = write("blobs/78/9.gz", 1, 10_000);
+        let stored = write("blobs/12/3456", 1, 10_000);
+        let unreferenced = write("blobs/cd/ef01", 1, 10_000);
+        // Stored long ago and still referenced by a new record
+        let blobs = BlobStore::new(&dir.join("blobs").to_string_lossy());
+        let compressor = Compressor::default();
+        let hash = blobs.put(b"body", &compressor, "api").unwrap();
+        let deduplicated = write(&blobs.path(&hash).strip_prefix(&dir).unwrap().to_string_lossy(), 4, 10_000);
+        blobs.put(b"body", &compressor, "api").unwrap();
+        let other = write("notes.txt", 10, 10_000);
+        let capture = write(&format!("layout/example.com/2024-05-06/api-{}.json.gz", uuid::Uuid::new_v4()), 10, 10_000);
+        let manifest = write("layout/manifest-s1.jsonl", 10, 10_000);
+        let settings = write("layout/settings.json", 10, 10_000);
+        let database = write("capture.db", 1, 10_000);
+
+        let config = RetentionConfig {
+            max_age: Some(3600),
+            max_bytes: Some(125),
+            max_files_per_host: Some(3),
+            interval: 60,
+            dirs: Vec::new(),
+        };
+        let targets = vec![
+            Target {
+                dir: dir.clone(),
+                host: Some(String::from("https://example.com")),
+                kind: Kind::Jsonl(String::from("api")),
+            },
+            Target {
+                dir: dir.join("blobs"),
+                host: None,
+                kind: Kind::Blob,
+            },
+            Target {
+                dir: dir.join("layout"),
+                host: None,
+                kind: Kind::Layout,
+            },
+            Target {
+                dir: dir.clone(),
+                host: None,
+                kind: Kind::Database(String::from("capture.db")),
+            },
+        ];
+
+        let sweep = Sweeper::new(&config, targets, None)
+            .sweep(&HashSet::from([current.clone()]), &HashSet::from([String::from("sha256:123456")]));
+
+        // Four too old, one over the per host limit as the file in use counts, and one
+        // more to get under the total size
+        assert_eq!(sweep, Sweep { deleted: 6, freed: 51 });
+        for path in [old, first, second, unreferenced, capture, manifest] {
+            assert!(!path.exists(), "{:?} should be deleted", path);
+        }
+        for path in [third, current, blob, referenced, stored, deduplicated, other, settings, database] {
+            assert!(path.exists(), "{:?} should be kept", path);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Full-text search index over captured exchanges.
+//!
+//! With a top-level `search` section, the URL and the text bodies of every completed
+//! exchange are added to a SQLite FTS5 index: the processed response body and the
+//! capture records, including bodies kept in the blob store. Binary bodies are left
+//! out, and text bodies are cut at `max_body` bytes.
+//!
+//! The index is queried by `udata search`, see `cli.rs`. FTS5 tokenizes the text it
+//! indexes and keeps the terms in plaintext, so unlike the capture store the index
+//! can't be combined with `encryption`.
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params_from_iter};
+use serde::{Deserialize, Serialize};
+
+use crate::blobs::BlobStore;
+use crate::capture::Exchange;
+use crate::compress::Compressor;
+use crate::store::{decode_record_body, format_time};
+
+/// Default location of the search index.
+pub const DEFAULT_PATH: &str = ".udata/search.db";
+
+/// Marks the start of a match in snippets.
+pub const MATCH_START: char = '\u{2}';
+
+/// Marks the end of a match in snippets.
+pub const MATCH_END: char = '\u{3}';
+
+const SCHEMA: &str = r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS captures USING fts5 (
+    url,
+    body,
+    uuid UNINDEXED,
+    session UNINDEXED,
+    host UNINDEXED,
+    status UNINDEXED,
+    started UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+"#;
+
+/// Search index, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SearchConfig {
+    /// Path of the index file
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Bytes of each body that are indexed
+    #[serde(default = "default_max_body")]
+    pub max_body: usize,
+}
+
+fn default_path() -> String {
+    String::from(DEFAULT_PATH)
+}
+
+fn default_max_body() -> usize {
+    1024 * 1024
+}
+
+/// Criteria for `SearchIndex::search`, besides the terms.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    /// Host entry (`HostEntry::host`)
+    pub host: Option<String>,
+    /// Earliest start time
+    pub since: Option<DateTime<Utc>>,
+    /// Latest start time
+    pub until: Option<DateTime<Utc>>,
+    /// Maximum number of results
+    pub limit: usize,
+}
+
+/// An exchange matching a search.
+#[derive(Debug, Serialize, Clone)]
+pub struct Hit {
+    pub uuid: String,
+    pub session: String,
+    pub host: String,
+    pub status: Option<i32>,
+    pub started: String,
+    /// URL, with the matches between `MATCH_START` and `MATCH_END`
+    pub url: String,
+    /// Part of the bodies around the best match, marked like `url`
+    pub snippet: String,
+}
+
+/// Connection to the search index.
+#[derive(Debug)]
+pub struct SearchIndex {
+    conn: Connection,
+    max_body: usize,
+}
+
+impl SearchIndex {
+    /// Opens the index, creating it if needed.
+    pub fn open(config: &SearchConfig) -> rusqlite::Result<Self> {
+        if let Some(dir) = std::path::Path::new(&config.path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(dir);
+        }
+
+        let conn = Connection::open(&config.path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn,
+            max_body: config.max_body,
+        })
+    }
+
+    /// Indexes a completed exchange.
+    ///
+    /// # Parameters
+    /// - `session`: Identifier of the session the exchange belongs to.
+    /// - `exchange`: The completed exchange.
+    /// - `compressor`: Reads the bodies kept in the blob store.
+    pub fn insert(&self, session: &str, exchange: &Exchange, compressor: &Compressor) -> rusqlite::Result<()> {
+        let blobs = exchange
+            .host
+            .as_ref()
+            .and_then(|host| host.dedup.as_ref())
+            .map(|dedup| BlobStore::new(&dedup.dir));
+
+        let mut bodies = Vec::new();
+        bodies.extend(exchange.body.clone());
+        for record in &exchange.records {
+            let body = match (record.blob.as_ref(), blobs.as_ref()) {
+                (Some(hash), Some(blobs)) => blobs.get(hash, compressor).unwrap_or_default(),
+                _ => decode_record_body(&record.body, record.encoding),
+            };
+            bodies.push(body);
+        }
+
+        let mut text = Vec::<&str>::new();
+        for body in &bodies {
+            let Some(body) = text_prefix(body, self.max_body) else {
+                continue;
+            };
+            // The sink's capture record usually holds the processed body again
+            if !body.is_empty() && !text.contains(&body) {
+                text.push(body);
+            }
+        }
+
+        let request = exchange.request.as_ref();
+        let started = request.map(|request| request.started).unwrap_or_else(Utc::now);
+        self.conn.execute(
+            "INSERT INTO captures (url, body, uuid, session, host, status, started) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                exchange.url,
+                text.join("\n"),
+                exchange.uuid.to_string(),
+                session,
+                exchange.host_name(),
+                exchange.response.as_ref().map(|response| response.status),
+                format_time(&started),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Finds the exchanges containing all of `terms`, best matches first.
+    ///
+    /// # Parameters
+    /// - `terms`: Words to look for. Double quotes keep several words together as a
+    ///   phrase.
+    /// - `filter`: Additional criteria.
+    pub fn search(&self, terms: &str, filter: &Filter) -> rusqlite::Result<Vec<Hit>> {
+        let query = match_query(terms);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conditions = vec!["captures MATCH ?"];
+        let mut values: Vec<rusqlite::types::Value> = vec![query.into()];
+
+        if let Some(host) = filter.host.as_ref() {
+            conditions.push("host = ?");
+            values.push(host.clone().into());
+        }
+        if let Some(since) = filter.since.as_ref() {
+            conditions.push("started >= ?");
+            values.push(format_time(since).into());
+        }
+        if let Some(until) = filter.until.as_ref() {
+            conditions.push("started <= ?");
+            values.push(format_time(until).into());
+        }
+        values.push((filter.limit as i64).into());
+
+        let sql = format!(
+            "SELECT uuid, session, host, status, started,
+                    highlight(captures, 0, char(2), char(3)),
+                    snippet(captures, 1, char(2), char(3), '…', 16)
+             FROM captures WHERE {} ORDER BY rank LIMIT ?",
+            conditions.join(" AND ")
+        );
+
+        let mut statement = self.conn.prepare(&sql)?;
+        statement
+            .query_map(params_from_iter(values), |row| {
+                Ok(Hit {
+                    uuid: row.get(0)?,
+                    session: row.get(1)?,
+                    host: row.get(2)?,
+                    status: row.get(3)?,
+                    started: row.get(4)?,
+                    url: row.get(5)?,
+                    snippet: row.get(6)?,
+                })
+            })?
+            .collect()
+    }
+}
+
+/// Returns the beginning of `body` if it's text.
+///
+/// # Returns
+/// At most `max` bytes, cut at a character boundary, or `None` for binary data.
+fn text_prefix(body: &[u8], max: usize) -> Option<&str> {
+    let body = &body[..body.len().min(max)];
+    match std::str::from_utf8(body) {
+        Ok(text) => Some(text),
+        // Only the last character was cut
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&body[..e.valid_up_to()]).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Turns search terms into an FTS5 query matching all of them.
+///
+/// Every term is quoted, so that punctuation in values such as e-mail addresses or
+/// ids isn't read as query syntax.
+fn match_query(terms: &str) -> String {
+    let mut phrases = Vec::new();
+    for (index, part) in terms.split('"').enumerate() {
+        // Odd parts were between double quotes
+        if index % 2 == 1 {
+            phrases.push(part.to_string());
+        } else {
+            phrases.extend(part.split_whitespace().map(str::to_string));
+        }
+    }
+
+    phrases
+        .iter()
+        .filter(|phrase| !phrase.trim().is_empty())
+        .map(|phrase| format!("\"{}\"", phrase))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture::CaptureRecord;
+
+    #[test]
+    fn test_search() {
+        assert_eq!(match_query(r#"id:42 "hello world" "#), r#""id:42" "hello world""#);
+        assert_eq!(text_prefix("héllo".as_bytes(), 2), Some("h"));
+        assert_eq!(text_prefix(&[0xff, 0xfe], 10), None);
+
+        let config = SearchConfig {
+            path: String::from(":memory:"),
+            max_body: 1024,
+        };
+        let index = SearchIndex::open(&config).unwrap();
+        let compressor = Compressor::default();
+
+        for (host, body) in [("https://a.com", r#"{"email":"jane@example.com"}"#), ("https://b.com", r#"{"email":"joe@example.com"}"#)] {
+            let mut exchange = Exchange {
+                uuid: uuid::Uuid::new_v4(),
+                url: format!("{}/api/users", host),
+                host: serde_json::from_value(serde_json::json!({ "host": host, "xhr": "/api" })).ok(),
+                ..Default::default()
+            };
+            let record = CaptureRecord::from_exchange(&exchange, body.as_bytes());
+            exchange.records.push(record);
+            index.insert("s1", &exchange, &compressor).unwrap();
+        }
+
+        let filter = Filter {
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(index.search("example.com", &filter).unwrap().len(), 2);
+        assert_eq!(index.search("users", &filter).unwrap().len(), 2);
+
+        let hits = index.search("jane@example.com", &filter).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].host, "https://a.com");
+        assert!(hits[0].snippet.contains("\u{2}jane@example.com\u{3}"), "{:?}", hits[0].snippet);
+
+        let filter = Filter {
+            host: Some(String::from("https://b.com")),
+            since: Some(Utc::now() - chrono::TimeDelta::hours(1)),
+            ..filter
+        };
+        assert_eq!(index.search("example", &filter).unwrap().len(), 1);
+        assert!(index.search("jane", &filter).unwrap().is_empty());
+    }
+}
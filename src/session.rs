@@ -0,0 +1,360 @@
+//! State shared by every request of a capture session.
+//!
+//! A single `Session` is created at startup and handed down from `DemoApp` to each
+//! response filter, alongside the configuration. It owns the outputs that span the
+//! whole session and is finished at shutdown.
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::JoinHandle;
+
+use crate::capture::{CaptureRecord, Exchange};
+use crate::compress::Compressor;
+use crate::config::{Config, HostEntry};
+use crate::crypt::Keyring;
+use crate::har::HarWriter;
+use crate::helpers::safe_file_name;
+use crate::jsonl::JsonlWriter;
+use crate::layout::LayoutWriter;
+use crate::retention::{Kind, Sweeper, Target};
+use crate::search::SearchIndex;
+use crate::store::Store;
+use crate::warc::WarcWriter;
+
+/// Capture state that outlives individual requests.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Identifier of the session, used in output file names
+    pub id: String,
+    /// Compression and encryption of the outputs
+    compressor: Arc<Compressor>,
+    /// Number of requests seen per host entry (`host`, `xhr`), for sampling
+    samples: Mutex<HashMap<(String, String), u64>>,
+    /// HAR export, when configured
+    har: Option<Mutex<HarWriter>>,
+    /// JSONL outputs of the host entries, by directory and file name prefix
+    jsonl: Mutex<HashMap<(String, String), JsonlWriter>>,
+    /// Hash of the last body recorded per URL pattern, for `only_on_change`
+    last_bodies: Mutex<HashMap<String, String>>,
+    /// SQLite store, when configured
+    store: Option<Mutex<Store>>,
+    /// WARC archive, when configured
+    warc: Option<Mutex<WarcWriter>>,
+    /// Per-request capture files, when configured
+    layout: Option<Mutex<LayoutWriter>>,
+    /// Full-text search index, when configured
+    search: Option<Mutex<SearchIndex>>,
+    /// Retention policy of the outputs, when configured
+    retention: Option<Sweeper>,
+    /// Background thread running `retention`, stopped by dropping the sender
+    sweeper: Mutex<Option<(mpsc::Sender<()>, JoinHandle<()>)>>,
+}
+
+impl Session {
+    /// Creates a session and opens the outputs enabled in the configuration.
+    ///
+    /// # Returns
+    /// An error if encryption is configured but can't be set up, or if the layout
+    /// template is invalid. Nothing may be written to disk then.
+    pub fn new(config: Option<&Config>) -> Result<Self, String> {
+        let id = Utc::now().format("%Y%m%dT%H%M%S").to_string();
+
+        let encryption = config.and_then(|config| config.encryption.as_ref());
+        let keyring = encryption.map(Keyring::new).transpose()?.map(Arc::new);
+        // The full-text index has to hold the indexed terms in plaintext
+        if keyring.is_some() && config.is_some_and(|config| config.search.is_some()) {
+            return Err(String::from("The search index can't be encrypted, remove `search` or `encryption`"));
+        }
+
+        let layout = config.and_then(|config| config.layout.as_ref());
+        if let Some(layout) = layout {
+            layout.validate()?;
+        }
+
+        let compression = config.and_then(|config| config.compression.clone()).unwrap_or_default();
+        let compressor = Arc::new(Compressor::new(&compression, keyring.clone()));
+
+        Ok(Self {
+            samples: Default::default(),
+            har: config
+                .and_then(|config| config.har.as_ref())
+                .map(|har| Mutex::new(HarWriter::new(har, compressor.clone()))),
+            jsonl: Default::default(),
+            last_bodies: Default::default(),
+            store: config.and_then(|config| config.store.as_ref()).and_then(|store| {
+                Store::open(&store.path, compressor.clone())
+                    .inspect_err(|e| eprintln!("Failed to open capture store {}: {}", store.path, e))
+                    .ok()
+                    .map(Mutex::new)
+            }),
+            warc: config
+                .and_then(|config| config.warc.as_ref())
+                .map(|warc| Mutex::new(WarcWriter::new(warc, &id, compressor.clone()))),
+            search: config.and_then(|config| config.search.as_ref()).and_then(|search| {
+                SearchIndex::open(search)
+                    .inspect_err(|e| eprintln!("Failed to open search index {}: {}", search.path, e))
+                    .ok()
+                    .map(Mutex::new)
+            }),
+            layout: layout.map(|layout| Mutex::new(LayoutWriter::new(layout, &id, compressor.clone()))),
+            retention: config.and_then(|config| {
+                let retention = config.retention.as_ref()?;
+                Some(Sweeper::new(retention, retention_targets(config), keyring))
+            }),
+            sweeper: Default::default(),
+            id,
+            compressor,
+        })
+    }
+
+    /// Decides whether a request to `host` is captured, according to its sampling rate.
+    ///
+    /// # Returns
+    /// `true` for the first request and every `sample`-th request after it.
+    pub fn sample(&self, host: &HostEntry) -> bool {
+        let Some(rate) = host.limits.sample.filter(|rate| *rate > 1) else {
+            return true;
+        };
+
+        let mut samples = self.samples.lock().unwrap();
+        let count = samples.entry((host.host.clone(), host.xhr.clone())).or_insert(0);
+        let sampled = count.is_multiple_of(rate);
+        *count += 1;
+        sampled
+    }
+
+    /// Remembers the body hash recorded for `key`.
+    ///
+    /// # Returns
+    /// `false` if it's the same as the previous hash for `key`.
+    pub fn changed(&self, key: String, hash: &str) -> bool {
+        let mut last_bodies = self.last_bodies.lock().unwrap();
+        last_bodies.insert(key, hash.to_string()).as_deref() != Some(hash)
+    }
+
+    /// Returns the compressor of the bodies written by the session.
+    pub fn compressor(&self) -> &Compressor {
+        &self.compressor
+    }
+
+    /// Returns `true` if an output records the processed response body.
+    pub fn needs_body(&self) -> bool {
+        self.har.is_some() || self.store.is_some() || self.warc.is_some() || self.layout.is_some() || self.search.is_some()
+    }
+
+    /// Returns `true` if an output records the capture records of each exchange.
+    pub fn keeps_records(&self) -> bool {
+        self.store.is_some() || self.layout.is_some() || self.search.is_some()
+    }
+
+    /// Records a completed exchange in the session outputs.
+    pub fn record(&self, exchange: &Exchange) {
+        if let Some(har) = self.har.as_ref() {
+            har.lock().unwrap().record(exchange);
+        }
+
+        if let Some(store) = self.store.as_ref()
+            && let Err(e) = store.lock().unwrap().insert(&self.id, exchange)
+        {
+            eprintln!("Failed to store exchange {}: {}", exchange.uuid, e);
+        }
+
+        if let Some(search) = self.search.as_ref()
+            && let Err(e) = search.lock().unwrap().insert(&self.id, exchange, &self.compressor)
+        {
+            eprintln!("Failed to index exchange {}: {}", exchange.uuid, e);
+        }
+
+        if let Some(warc) = self.warc.as_ref() {
+            warc.lock().unwrap().record(exchange);
+        }
+
+        if let Some(layout) = self.layout.as_ref() {
+            layout.lock().unwrap().record(exchange);
+        }
+    }
+
+    /// Writes a capture record to the JSONL output of `host`.
+    ///
+    /// # Returns
+    /// `false` if the host entry has no JSONL output.
+    pub fn write_record(&self, host: &HostEntry, record: &CaptureRecord) -> bool {
+        let Some(config) = host.jsonl.as_ref() else {
+            return false;
+        };
+
+        let prefix = config.prefix(&host.host);
+        let mut outputs = self.jsonl.lock().unwrap();
+        let writer = outputs
+            .entry((config.dir.clone(), prefix.clone()))
+            .or_insert_with(|| JsonlWriter::new(config, prefix, &self.id, self.compressor.clone()));
+
+        if let Err(e) = writer.write(record) {
+            eprintln!("Failed to write capture record {} to {:?}: {}", record.uuid, writer.path(), e);
+        }
+        true
+    }
+
+    /// Applies the retention policy now, then every `interval` on a background thread
+    /// until the session is finished.
+    pub fn start_sweeper(self: &Arc<Self>) {
+        let Some(interval) = self.retention.as_ref().map(Sweeper::interval) else {
+            return;
+        };
+
+        let session: Weak<Self> = Arc::downgrade(self);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            loop {
+                match session.upgrade() {
+                    Some(session) => session.sweep(),
+                    None => return,
+                }
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+            }
+        });
+
+        *self.sweeper.lock().unwrap() = Some((stop, thread));
+    }
+
+    /// Deletes the output files the retention policy doesn't allow to keep.
+    pub fn sweep(&self) {
+        let Some(retention) = self.retention.as_ref() else {
+            return;
+        };
+
+        let referenced = match self.store.as_ref().map(|store| store.lock().unwrap().blob_references()) {
+            Some(Ok(referenced)) => referenced,
+            Some(Err(e)) => {
+                eprintln!("Retention: skipped, failed to read the blobs referenced by the store: {}", e);
+                return;
+            }
+            None => HashSet::new(),
+        };
+
+        let sweep = retention.sweep(&self.files_in_use(), &referenced);
+        if sweep.deleted > 0 {
+            eprintln!("Retention: deleted {} file(s), {} bytes", sweep.deleted, sweep.freed);
+        }
+    }
+
+    /// Returns the files the outputs are currently writing.
+    fn files_in_use(&self) -> HashSet<PathBuf> {
+        let mut files = self
+            .jsonl
+            .lock()
+            .unwrap()
+            .values()
+            .map(JsonlWriter::path)
+            .collect::<HashSet<_>>();
+
+        if let Some(har) = self.har.as_ref() {
+            files.insert(PathBuf::from(har.lock().unwrap().path()));
+        }
+        if let Some(warc) = self.warc.as_ref() {
+            files.insert(PathBuf::from(warc.lock().unwrap().path()));
+        }
+        if let Some(layout) = self.layout.as_ref() {
+            files.insert(layout.lock().unwrap().manifest_path());
+        }
+        files
+    }
+
+    /// Flushes and closes the session outputs.
+    pub fn finish(&self) {
+        if let Some((stop, thread)) = self.sweeper.lock().unwrap().take() {
+            drop(stop);
+            let _ = thread.join();
+        }
+
+        if let Some(har) = self.har.as_ref() {
+            har.lock().unwrap().finish();
+        }
+
+        if let Some(warc) = self.warc.as_ref() {
+            warc.lock().unwrap().finish();
+        }
+
+        if let Some(layout) = self.layout.as_ref() {
+            layout.lock().unwrap().finish();
+        }
+
+        for writer in self.jsonl.lock().unwrap().values_mut() {
+            if let Err(e) = writer.finish() {
+                eprintln!("Failed to sync {:?}: {}", writer.path(), e);
+            }
+        }
+    }
+}
+
+/// Returns the retention target of a single output file.
+fn file_target(path: &str, kind: fn(String) -> Kind) -> Option<Target> {
+    let path = std::path::Path::new(path);
+    Some(Target {
+        dir: path.parent().map(PathBuf::from).unwrap_or_default(),
+        host: None,
+        kind: kind(path.file_name()?.to_string_lossy().into_owned()),
+    })
+}
+
+/// Lists the directories written by the outputs, for the retention policy.
+fn retention_targets(config: &Config) -> Vec<Target> {
+    let mut targets = Vec::new();
+
+    for host in &config.host {
+        if let Some(jsonl) = host.jsonl.as_ref() {
+            targets.push(Target {
+                dir: PathBuf::from(&jsonl.dir),
+                host: Some(host.host.clone()),
+                kind: Kind::Jsonl(jsonl.prefix(&host.host)),
+            });
+        }
+        if let Some(dedup) = host.dedup.as_ref() {
+            targets.push(Target {
+                dir: PathBuf::from(&dedup.dir),
+                host: None,
+                kind: Kind::Blob,
+            });
+        }
+    }
+
+    targets.extend(config.har.as_ref().and_then(|har| file_target(&har.path, Kind::File)));
+    // Counted towards `max_bytes`, but never deleted
+    targets.extend(config.store.as_ref().and_then(|store| file_target(&store.path, Kind::Database)));
+    targets.extend(config.search.as_ref().and_then(|search| file_target(&search.path, Kind::Database)));
+
+    if let Some(dir) = config
+        .warc
+        .as_ref()
+        .and_then(|warc| std::path::Path::new(&warc.path).parent().map(PathBuf::from))
+    {
+        targets.push(Target {
+            dir,
+            host: None,
+            kind: Kind::Warc,
+        });
+    }
+
+    if let Some(layout) = config.layout.as_ref() {
+        let dir = PathBuf::from(&layout.dir);
+        if layout.template.starts_with("{host}/") {
+            // One directory per host entry, so `max_files_per_host` applies
+            targets.extend(config.host.iter().map(|host| Target {
+                dir: dir.join(safe_file_name(&host.host)),
+                host: Some(host.host.clone()),
+                kind: Kind::Layout,
+            }));
+        }
+        targets.push(Target {
+            dir,
+            host: None,
+            kind: Kind::Layout,
+        });
+    }
+
+    targets
+}
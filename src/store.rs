@@ -0,0 +1,553 @@
+//! SQLite store for captured exchanges.
+//!
+//! Every completed exchange is written in a single transaction to a normalized schema:
+//!
+//! - `exchanges`: one row per request, indexed by host, URL, start time and status
+//! - `headers`: request and response headers
+//! - `bodies`: request body, processed response body and capture records;
+//!   records kept in the blob store only hold the hash of their body. Bodies are
+//!   compressed according to the `compression` configuration, then encrypted like
+//!   blobs with `encryption`
+//! - `fields`: scalar values of JSON capture records, by JSONPath (`$.data.items[0].id`),
+//!   left empty with `encryption` as they would hold the bodies in plaintext
+//!
+//! URLs, headers and the other exchange details stay in plaintext so they can be
+//! queried. The store is queried by `udata query`, see `cli.rs`.
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use rusqlite::{Connection, params, params_from_iter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::blobs::BlobStore;
+use crate::capture::{Exchange, encode_body};
+use crate::compress::{Codec, Compressor};
+
+/// Default location of the capture database.
+pub const DEFAULT_PATH: &str = ".udata/capture.db";
+
+/// Maximum number of fields extracted from a single capture record.
+const MAX_FIELDS: usize = 1000;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS exchanges (
+    id INTEGER PRIMARY KEY,
+    uuid TEXT NOT NULL UNIQUE,
+    session TEXT NOT NULL,
+    host TEXT NOT NULL,
+    url TEXT NOT NULL,
+    method TEXT,
+    status INTEGER,
+    mime_type TEXT NOT NULL,
+    started TEXT NOT NULL,
+    duration_ms REAL,
+    truncated INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS exchanges_host ON exchanges (host);
+CREATE INDEX IF NOT EXISTS exchanges_url ON exchanges (url);
+CREATE INDEX IF NOT EXISTS exchanges_started ON exchanges (started);
+CREATE INDEX IF NOT EXISTS exchanges_status ON exchanges (status);
+
+CREATE TABLE IF NOT EXISTS headers (
+    exchange_id INTEGER NOT NULL REFERENCES exchanges (id) ON DELETE CASCADE,
+    direction TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS headers_exchange ON headers (exchange_id);
+
+CREATE TABLE IF NOT EXISTS bodies (
+    exchange_id INTEGER NOT NULL REFERENCES exchanges (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    sequence INTEGER NOT NULL DEFAULT 0,
+    data BLOB NOT NULL,
+    compression TEXT,
+    blob TEXT,
+    encrypted INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS bodies_exchange ON bodies (exchange_id);
+
+CREATE TABLE IF NOT EXISTS fields (
+    exchange_id INTEGER NOT NULL REFERENCES exchanges (id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    value TEXT
+);
+CREATE INDEX IF NOT EXISTS fields_path_value ON fields (path, value);
+CREATE INDEX IF NOT EXISTS fields_exchange ON fields (exchange_id);
+"#;
+
+/// SQLite store, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StoreConfig {
+    /// Path of the database file
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    String::from(DEFAULT_PATH)
+}
+
+/// Criteria for `Store::query`. Unset criteria match everything.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    /// Host entry (`HostEntry::host`)
+    pub host: Option<String>,
+    /// URL pattern, `*` matches any sequence of characters
+    pub url: Option<String>,
+    /// Earliest start time
+    pub since: Option<DateTime<Utc>>,
+    /// Latest start time
+    pub until: Option<DateTime<Utc>>,
+    /// HTTP status
+    pub status: Option<i32>,
+    /// JSONPath and value pairs that must all be present
+    pub fields: Vec<(String, String)>,
+    /// Maximum number of results
+    pub limit: usize,
+}
+
+/// An exchange read back from the store.
+#[derive(Debug, Serialize, Clone)]
+pub struct StoredExchange {
+    #[serde(skip)]
+    pub id: i64,
+    pub uuid: String,
+    pub session: String,
+    pub host: String,
+    pub url: String,
+    pub method: Option<String>,
+    pub status: Option<i32>,
+    pub mime_type: String,
+    pub started: String,
+    pub duration_ms: Option<f64>,
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bodies: Vec<StoredBody>,
+}
+
+/// A body read back from the store.
+#[derive(Debug, Serialize, Clone)]
+pub struct StoredBody {
+    pub kind: String,
+    pub sequence: i64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub body: String,
+    pub encoding: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+/// Connection to the capture database.
+#[derive(Debug)]
+pub struct Store {
+    conn: Connection,
+    compressor: Arc<Compressor>,
+}
+
+impl Store {
+    /// Opens the database, creating it and its schema if needed.
+    ///
+    /// # Parameters
+    /// - `path`: Path of the database file.
+    /// - `compressor`: Compresses and encrypts the bodies written, and decrypts and
+    ///   decompresses the ones read.
+    pub fn open(path: &str, compressor: Arc<Compressor>) -> rusqlite::Result<Self> {
+        if let Some(dir) = std::path::Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(dir);
+        }
+
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(SCHEMA)?;
+
+        // Stores created before bodies could be encrypted
+        let sql = "SELECT COUNT(*) FROM pragma_table_info('bodies') WHERE name = 'encrypted'";
+        let encrypted: i64 = conn.query_row(sql, [], |row| row.get(0))?;
+        if encrypted == 0 {
+            conn.execute_batch("ALTER TABLE bodies ADD COLUMN encrypted INTEGER NOT NULL DEFAULT 0")?;
+        }
+
+        Ok(Self { conn, compressor })
+    }
+
+    /// Writes a completed exchange, along with the capture records it produced.
+    ///
+    /// # Parameters
+    /// - `session`: Identifier of the session the exchange belongs to.
+    /// - `exchange`: The completed exchange.
+    pub fn insert(&mut self, session: &str, exchange: &Exchange) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+
+        let request = exchange.request.as_ref();
+        let response = exchange.response.as_ref();
+        let started = request.map(|request| request.started).unwrap_or_else(Utc::now);
+        let duration = request.map(|request| {
+            Instant::now().saturating_duration_since(request.sent).as_secs_f64() * 1000.0
+        });
+
+        tx.execute(
+            "INSERT INTO exchanges (uuid, session, host, url, method, status, mime_type, started, duration_ms, truncated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                exchange.uuid.to_string(),
+                session,
+                exchange.host_name(),
+                exchange.url,
+                request.map(|request| &request.method),
+                response.map(|response| response.status),
+                exchange.mime_type,
+                format_time(&started),
+                duration,
+                exchange.truncated,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        {
+            let mut header = tx.prepare("INSERT INTO headers (exchange_id, direction, name, value) VALUES (?1, ?2, ?3, ?4)")?;
+            let headers = [
+                ("request", request.map(|request| &request.headers)),
+                ("response", response.map(|response| &response.headers)),
+            ];
+            for (direction, headers) in headers {
+                for (name, value) in headers.into_iter().flatten() {
+                    header.execute(params![id, direction, name, value])?;
+                }
+            }
+
+            let compressor = self.compressor.as_ref();
+            let name = exchange.host_name();
+            let keyring = compressor.keyring();
+            let mut body = tx.prepare(
+                "INSERT INTO bodies (exchange_id, kind, sequence, data, compression, blob, encrypted)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut insert_body = |kind: &str, sequence: usize, data: &[u8], blob: Option<&String>| {
+                let (data, compression) = match blob {
+                    Some(_) => (Vec::new(), None),
+                    None => {
+                        let mut data = compressor.compress(name, data);
+                        if let Some(keyring) = keyring {
+                            data = data.and_then(|data| keyring.encrypt(&data));
+                        }
+                        let data = data.map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                        (data, compressor.codec().name())
+                    }
+                };
+                let encrypted = blob.is_none() && keyring.is_some();
+                body.execute(params![id, kind, sequence as i64, data, compression, blob, encrypted])
+            };
+
+            if let Some(data) = request.and_then(|request| request.body.as_ref()) {
+                insert_body("request", 0, data, None)?;
+            }
+            if let Some(data) = exchange.body.as_ref() {
+                insert_body("response", 0, data, None)?;
+            }
+
+            let blobs = exchange
+                .host
+                .as_ref()
+                .and_then(|host| host.dedup.as_ref())
+                .map(|dedup| BlobStore::new(&dedup.dir));
+
+            let mut field = tx.prepare("INSERT INTO fields (exchange_id, path, value) VALUES (?1, ?2, ?3)")?;
+            for (sequence, record) in exchange.records.iter().enumerate() {
+                let data = match (record.blob.as_ref(), blobs.as_ref()) {
+                    (Some(hash), Some(blobs)) => {
+                        insert_body("capture", sequence, b"", Some(hash))?;
+                        blobs.get(hash, compressor).unwrap_or_default()
+                    }
+                    _ => {
+                        let data = decode_record_body(&record.body, record.encoding);
+                        insert_body("capture", sequence, &data, None)?;
+                        data
+                    }
+                };
+
+                if keyring.is_some() {
+                    continue;
+                }
+                let Ok(json) = serde_json::from_slice::<Value>(&data) else {
+                    continue;
+                };
+                let mut fields = Vec::new();
+                flatten(&json, String::from("$"), &mut fields);
+                for (path, value) in fields {
+                    field.execute(params![id, path, value])?;
+                }
+            }
+        }
+
+        tx.commit()
+    }
+
+    /// Returns the hashes of the blobs the stored capture records refer to.
+    pub fn blob_references(&self) -> rusqlite::Result<HashSet<String>> {
+        let mut statement = self.conn.prepare("SELECT DISTINCT blob FROM bodies WHERE blob IS NOT NULL")?;
+        statement.query_map([], |row| row.get(0))?.collect()
+    }
+
+    /// Finds the exchanges matching `filter`, most recent first.
+    ///
+    /// # Parameters
+    /// - `filter`: The criteria to match.
+    /// - `bodies`: Whether to load the bodies of the results.
+    pub fn query(&self, filter: &Filter, bodies: bool) -> rusqlite::Result<Vec<StoredExchange>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+
+        if let Some(host) = filter.host.as_ref() {
+            conditions.push("host = ?");
+            values.push(host.clone().into());
+        }
+        if let Some(url) = filter.url.as_ref() {
+            conditions.push("url GLOB ?");
+            values.push(url.clone().into());
+        }
+        if let Some(since) = filter.since.as_ref() {
+            conditions.push("started >= ?");
+            values.push(format_time(since).into());
+        }
+        if let Some(until) = filter.until.as_ref() {
+            conditions.push("started <= ?");
+            values.push(format_time(until).into());
+        }
+        if let Some(status) = filter.status {
+            conditions.push("status = ?");
+            values.push(i64::from(status).into());
+        }
+        for (path, value) in &filter.fields {
+            conditions.push("id IN (SELECT exchange_id FROM fields WHERE path = ? AND value = ?)");
+            values.push(path.clone().into());
+            values.push(value.clone().into());
+        }
+
+        let mut sql = String::from(
+            "SELECT id, uuid, session, host, url, method, status, mime_type, started, duration_ms, truncated FROM exchanges",
+        );
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY started DESC LIMIT ?");
+        values.push((filter.limit as i64).into());
+
+        let mut statement = self.conn.prepare(&sql)?;
+        let mut exchanges = statement
+            .query_map(params_from_iter(values), |row| {
+                Ok(StoredExchange {
+                    id: row.get(0)?,
+                    uuid: row.get(1)?,
+                    session: row.get(2)?,
+                    host: row.get(3)?,
+                    url: row.get(4)?,
+                    method: row.get(5)?,
+                    status: row.get(6)?,
+                    mime_type: row.get(7)?,
+                    started: row.get(8)?,
+                    duration_ms: row.get(9)?,
+                    truncated: row.get(10)?,
+                    bodies: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if bodies {
+            let mut statement = self.conn.prepare(
+                "SELECT kind, sequence, data, compression, blob, encrypted FROM bodies WHERE exchange_id = ?1 ORDER BY rowid",
+            )?;
+            for exchange in exchanges.iter_mut() {
+                exchange.bodies = statement
+                    .query_map(params![exchange.id], |row| {
+                        let data: Vec<u8> = row.get(2)?;
+                        let codec = Codec::from_name(row.get::<_, Option<String>>(3)?.as_deref());
+                        let data = if row.get::<_, bool>(5)? {
+                            self.compressor
+                                .keyring()
+                                .ok_or_else(|| std::io::Error::other("the body is encrypted and no key is configured"))
+                                .and_then(|keyring| keyring.decrypt(&data))
+                        } else {
+                            Ok(data)
+                        };
+                        let data = data.and_then(|data| self.compressor.decompress(codec, &data)).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Blob, Box::new(e))
+                        })?;
+                        let (body, encoding) = encode_body(&data);
+                        Ok(StoredBody {
+                            kind: row.get(0)?,
+                            sequence: row.get(1)?,
+                            body,
+                            encoding,
+                            blob: row.get(4)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+            }
+        }
+
+        Ok(exchanges)
+    }
+}
+
+/// Formats a time so that it sorts lexically in the database.
+pub fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Parses a time given on the command line: RFC 3339, a `YYYY-MM-DD` date, or a
+/// duration ago such as `90s`, `30m`, `2h`, `7d` or `1w`.
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    if let Some(unit) = value.chars().last().filter(|_| value.len() > 1)
+        && let Ok(count) = value[..value.len() - unit.len_utf8()].parse::<i64>()
+    {
+        let ago = match unit {
+            's' => TimeDelta::try_seconds(count),
+            'm' => TimeDelta::try_minutes(count),
+            'h' => TimeDelta::try_hours(count),
+            'd' => TimeDelta::try_days(count),
+            'w' => TimeDelta::try_weeks(count),
+            _ => None,
+        };
+        if let Some(time) = ago.and_then(|ago| Utc::now().checked_sub_signed(ago)) {
+            return Ok(time);
+        }
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        .map_err(|_| format!("invalid time {:?}, expected RFC 3339, YYYY-MM-DD or a duration like 2h", value))
+}
+
+/// Reverses the encoding applied to a capture record body.
+pub fn decode_record_body(body: &str, encoding: &str) -> Vec<u8> {
+    use base64::prelude::*;
+
+    match encoding {
+        "base64" => BASE64_STANDARD.decode(body).unwrap_or_default(),
+        _ => body.as_bytes().to_vec(),
+    }
+}
+
+/// Lists the scalar values of a JSON document with their JSONPath.
+fn flatten(value: &Value, path: String, fields: &mut Vec<(String, Option<String>)>) {
+    if fields.len() >= MAX_FIELDS {
+        return;
+    }
+
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten(value, format!("{}.{}", path, key), fields);
+            }
+        }
+        Value::Array(array) => {
+            for (index, value) in array.iter().enumerate() {
+                flatten(value, format!("{}[{}]", path, index), fields);
+            }
+        }
+        Value::Null => fields.push((path, None)),
+        Value::String(string) => fields.push((path, Some(string.clone()))),
+        other => fields.push((path, Some(other.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture::{CaptureRecord, RequestInfo};
+    use crate::crypt::{EncryptionConfig, KeySource, Keyring};
+
+    #[test]
+    fn test_store() {
+        let config = crate::compress::CompressionConfig {
+            codec: Codec::Gzip,
+            ..Default::default()
+        };
+        let compressor = Compressor::new(&config, None);
+        let mut store = Store::open(":memory:", Arc::new(compressor)).unwrap();
+
+        for (index, body) in [r#"{"user":{"id":42}}"#, r#"{"user":{"id":7}}"#].into_iter().enumerate() {
+            let mut exchange = Exchange {
+                uuid: uuid::Uuid::new_v4(),
+                url: format!("https://example.com/api/{}", index),
+                mime_type: String::from("application/json"),
+                request: Some(RequestInfo {
+                    method: String::from("GET"),
+                    url: String::new(),
+                    headers: vec![(String::from("Accept"), String::from("*/*"))],
+                    body: None,
+                    page: None,
+                    started: Utc::now(),
+                    sent: Instant::now(),
+                }),
+                body: Some(body.as_bytes().to_vec()),
+                ..Default::default()
+            };
+            let record = CaptureRecord::from_exchange(&exchange, body.as_bytes());
+            exchange.records.push(record);
+            store.insert("s1", &exchange).unwrap();
+        }
+
+        let filter = Filter {
+            url: Some(String::from("https://example.com/api/*")),
+            since: Some(parse_time("2000-01-01").unwrap()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(store.query(&filter, false).unwrap().len(), 2);
+        assert!(parse_time("2h").unwrap() < Utc::now() - TimeDelta::minutes(119));
+        assert!(parse_time("2x").is_err());
+
+        let filter = Filter {
+            fields: vec![(String::from("$.user.id"), String::from("42"))],
+            ..filter
+        };
+        let found = store.query(&filter, true).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].url, "https://example.com/api/0");
+        let kinds: Vec<_> = found[0].bodies.iter().map(|body| body.kind.as_str()).collect();
+        assert_eq!(kinds, ["response", "capture"]);
+        assert!(found[0].bodies.iter().all(|body| body.body == r#"{"user":{"id":42}}"#));
+
+        // With encryption the bodies are encrypted and no field is extracted
+        let keyfile = std::env::temp_dir().join(format!("udata-{}.key", uuid::Uuid::new_v4()));
+        std::fs::write(&keyfile, [7; 32]).unwrap();
+        let keyring = Keyring::new(&EncryptionConfig {
+            key: KeySource {
+                keyfile: Some(keyfile.to_string_lossy().into_owned()),
+                passphrase_env: None,
+            },
+            previous: Vec::new(),
+        })
+        .unwrap();
+        std::fs::remove_file(&keyfile).unwrap();
+        let compressor = Compressor::new(&Default::default(), Some(Arc::new(keyring)));
+        let mut store = Store::open(":memory:", Arc::new(compressor)).unwrap();
+
+        let body = r#"{"user":{"id":42}}"#;
+        let mut exchange = Exchange {
+            uuid: uuid::Uuid::new_v4(),
+            url: String::from("https://example.com/api/0"),
+            body: Some(body.as_bytes().to_vec()),
+            ..Default::default()
+        };
+        exchange.records.push(CaptureRecord::from_exchange(&exchange, body.as_bytes()));
+        store.insert("s2", &exchange).unwrap();
+
+        let data: Vec<u8> = store.conn.query_row("SELECT data FROM bodies", [], |row| row.get(0)).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("user"));
+        let fields: i64 = store.conn.query_row("SELECT COUNT(*) FROM fields", [], |row| row.get(0)).unwrap();
+        assert_eq!(fields, 0);
+        let found = store.query(&Filter { limit: 10, ..Default::default() }, true).unwrap();
+        assert!(found[0].bodies.iter().all(|body| body.body == r#"{"user":{"id":42}}"#));
+    }
+}
@@ -0,0 +1,357 @@
+//! Segmentation of long-lived streaming responses.
+//!
+//! Responses such as `text/event-stream` never complete, so they can't be captured
+//! as a single body. This module splits the raw byte stream seen by the response
+//! filter into discrete events, each of which is captured as its own record.
+//!
+//! Supported framings:
+//! - Server-Sent Events (`data:` frames terminated by a blank line)
+//! - Newline-delimited JSON (one document per line), and JSON text sequences whose
+//!   documents start with a record separator (RFC 7464)
+//! - Length-prefixed records (4-byte big-endian length followed by the payload)
+//!
+//! A length prefix larger than the body limit can't be a record of the stream, and the
+//! segmenter stops framing: the rest of the response passes through as is.
+use serde::Deserialize;
+
+/// Largest length-prefixed record when the host entry sets no `max_bytes`
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// Record separator starting each document of a JSON text sequence
+const RS: u8 = 0x1e;
+
+/// Framing used to split a streaming response into events.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamMode {
+    /// Server-Sent Events (`text/event-stream`)
+    Sse,
+    /// Newline-delimited JSON (`application/x-ndjson`, `application/json-seq`)
+    Ndjson,
+    /// Records prefixed with a 4-byte big-endian length
+    LengthPrefixed,
+}
+
+impl StreamMode {
+    /// Infers the stream framing from a response mime type.
+    ///
+    /// # Returns
+    /// `Some` for mime types that are known to be streamed, `None` otherwise.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.trim().to_ascii_lowercase().as_str() {
+            "text/event-stream" => Some(StreamMode::Sse),
+            "application/x-ndjson" | "application/jsonl" | "application/json-seq" => {
+                Some(StreamMode::Ndjson)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A single event extracted from a streaming response.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StreamEvent {
+    /// Event type (SSE `event:` field)
+    pub kind: Option<String>,
+    /// Event id (SSE `id:` field)
+    pub id: Option<String>,
+    /// Event payload
+    pub data: Vec<u8>,
+}
+
+/// Incremental splitter turning response chunks into `StreamEvent`s.
+///
+/// Chunks may end anywhere, including in the middle of a line, a `\r\n` pair or a
+/// length prefix. Incomplete input is kept until the next call to `push`.
+#[derive(Debug)]
+pub struct Segmenter {
+    mode: StreamMode,
+    /// Largest length-prefixed record
+    max_len: usize,
+    pending: Vec<u8>,
+    event: StreamEvent,
+    has_data: bool,
+    /// Set once a length prefix exceeded `max_len`
+    passthrough: bool,
+}
+
+impl Segmenter {
+    /// Creates a new segmenter for the given framing.
+    ///
+    /// # Parameters
+    /// - `mode`: The framing of the stream.
+    /// - `max_len`: Largest length-prefixed record, `MAX_RECORD_LEN` if `None`.
+    pub fn new(mode: StreamMode, max_len: Option<usize>) -> Self {
+        Self {
+            mode,
+            max_len: max_len.unwrap_or(MAX_RECORD_LEN),
+            pending: Vec::new(),
+            event: StreamEvent::default(),
+            has_data: false,
+            passthrough: false,
+        }
+    }
+
+    /// Takes the input to pass through once a length prefix exceeded `max_len`.
+    ///
+    /// # Returns
+    /// Nothing while the stream is still split into events.
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        if !self.passthrough {
+            return Vec::new();
+        }
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Feeds a chunk of response data.
+    ///
+    /// # Returns
+    /// All events completed by this chunk, in stream order.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        self.pending.extend_from_slice(chunk);
+        if self.passthrough {
+            return Vec::new();
+        }
+
+        match self.mode {
+            StreamMode::Sse | StreamMode::Ndjson => {
+                let mut events = Vec::new();
+                while let Some(line) = self.next_line(false) {
+                    self.line(line, &mut events);
+                }
+                events
+            }
+            StreamMode::LengthPrefixed => self.records(),
+        }
+    }
+
+    /// Flushes whatever is left once the stream has ended.
+    ///
+    /// # Returns
+    /// The final event, if the stream ended without a terminating delimiter.
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        if self.passthrough {
+            return events;
+        }
+
+        match self.mode {
+            StreamMode::Sse | StreamMode::Ndjson => {
+                while let Some(line) = self.next_line(true) {
+                    self.line(line, &mut events);
+                }
+                // A stream may end without the blank line that dispatches an SSE event
+                if self.mode == StreamMode::Sse {
+                    self.line(Vec::new(), &mut events);
+                }
+            }
+            StreamMode::LengthPrefixed => {
+                events = self.records();
+                if !self.pending.is_empty() && !self.passthrough {
+                    events.push(StreamEvent {
+                        data: std::mem::take(&mut self.pending),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Splits the next complete line off the pending buffer.
+    ///
+    /// A trailing `\r` is only treated as a terminator once the following byte is
+    /// known, since it may be the first half of a `\r\n` pair split across chunks.
+    fn next_line(&mut self, eof: bool) -> Option<Vec<u8>> {
+        let pos = self.pending.iter().position(|b| *b == b'\n' || *b == b'\r');
+
+        let Some(pos) = pos else {
+            if eof && !self.pending.is_empty() {
+                return Some(std::mem::take(&mut self.pending));
+            }
+            return None;
+        };
+
+        let skip = if self.pending[pos] == b'\r' {
+            match self.pending.get(pos + 1) {
+                Some(b'\n') => 2,
+                Some(_) => 1,
+                None if eof => 1,
+                None => return None,
+            }
+        } else {
+            1
+        };
+
+        let line = self.pending[..pos].to_vec();
+        self.pending.drain(..pos + skip);
+        Some(line)
+    }
+
+    /// Interprets a single line according to the framing.
+    fn line(&mut self, line: Vec<u8>, events: &mut Vec<StreamEvent>) {
+        if self.mode == StreamMode::Ndjson {
+            for document in line.split(|b| *b == RS) {
+                if !document.iter().all(u8::is_ascii_whitespace) {
+                    events.push(StreamEvent {
+                        data: document.to_vec(),
+                        ..Default::default()
+                    });
+                }
+            }
+            return;
+        }
+
+        // Blank line dispatches the current SSE event
+        if line.is_empty() {
+            if self.has_data {
+                events.push(std::mem::take(&mut self.event));
+            } else {
+                self.event = StreamEvent::default();
+            }
+            self.has_data = false;
+            return;
+        }
+
+        // Comment
+        if line[0] == b':' {
+            return;
+        }
+
+        let (field, value) = match line.iter().position(|b| *b == b':') {
+            Some(colon) => {
+                let value = &line[colon + 1..];
+                let value = value.strip_prefix(b" ").unwrap_or(value);
+                (&line[..colon], value)
+            }
+            None => (line.as_slice(), &[][..]),
+        };
+
+        match field {
+            b"data" => {
+                if self.has_data {
+                    self.event.data.push(b'\n');
+                }
+                self.event.data.extend_from_slice(value);
+                self.has_data = true;
+            }
+            b"event" => self.event.kind = Some(String::from_utf8_lossy(value).into_owned()),
+            b"id" => self.event.id = Some(String::from_utf8_lossy(value).into_owned()),
+            _ => {}
+        }
+    }
+
+    /// Extracts all complete length-prefixed records from the pending buffer.
+    fn records(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+
+        while self.pending.len() >= 4 {
+            let len = u32::from_be_bytes([
+                self.pending[0],
+                self.pending[1],
+                self.pending[2],
+                self.pending[3],
+            ]) as usize;
+
+            if len > self.max_len {
+                self.passthrough = true;
+                break;
+            }
+            if self.pending.len() - 4 < len {
+                break;
+            }
+
+            let data = self.pending[4..4 + len].to_vec();
+            self.pending.drain(..4 + len);
+            events.push(StreamEvent {
+                data,
+                ..Default::default()
+            });
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_bytewise(segmenter: &mut Segmenter, input: &[u8]) -> Vec<StreamEvent> {
+        input
+            .chunks(1)
+            .flat_map(|chunk| segmenter.push(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn test_sse() {
+        let input = b": keep-alive\r\nevent: tick\r\nid: 7\r\ndata: hello\r\ndata: world\r\n\r\ndata: {\"a\":1}\n\n";
+
+        let mut whole = Segmenter::new(StreamMode::Sse, None);
+        let events = whole.push(input);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind.as_deref(), Some("tick"));
+        assert_eq!(events[0].id.as_deref(), Some("7"));
+        assert_eq!(events[0].data, b"hello\nworld");
+        assert_eq!(events[1].kind, None);
+        assert_eq!(events[1].data, b"{\"a\":1}");
+
+        let mut split = Segmenter::new(StreamMode::Sse, None);
+        assert_eq!(push_bytewise(&mut split, input), events);
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let input = b"{\"a\":1}\n\n{\"b\":2}\r\n{\"c\":";
+
+        let mut segmenter = Segmenter::new(StreamMode::Ndjson, None);
+        let events = push_bytewise(&mut segmenter, input);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].data, b"{\"b\":2}");
+
+        let rest = segmenter.finish();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].data, b"{\"c\":");
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        let mut input = Vec::new();
+        for payload in [&b"abc"[..], b"", b"hello world"] {
+            input.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            input.extend_from_slice(payload);
+        }
+
+        let mut segmenter = Segmenter::new(StreamMode::LengthPrefixed, None);
+        let events = push_bytewise(&mut segmenter, &input);
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].data, b"abc");
+        assert!(events[1].data.is_empty());
+        assert_eq!(events[2].data, b"hello world");
+        assert!(segmenter.finish().is_empty());
+
+        // Not a record of the stream, the rest is passed through
+        let mut segmenter = Segmenter::new(StreamMode::LengthPrefixed, Some(8));
+        let events = push_bytewise(&mut segmenter, b"\x00\x00\x00\x03abc\x7f\xff\xff\xffrest");
+        assert_eq!(events.len(), 1);
+        assert!(segmenter.finish().is_empty());
+        assert_eq!(segmenter.take_pending(), b"\x7f\xff\xff\xffrest");
+    }
+
+    #[test]
+    fn test_json_seq() {
+        let input = b"\x1e{\"a\":1}\n\x1e{\"b\":2}\x1e{\"c\":3}\n";
+
+        let mut segmenter = Segmenter::new(StreamMode::from_mime_type("application/json-seq").unwrap(), None);
+        let events = push_bytewise(&mut segmenter, input);
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].data, b"{\"a\":1}");
+        assert_eq!(events[2].data, b"{\"c\":3}");
+    }
+}
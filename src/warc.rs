@@ -0,0 +1,495 @@
+//! WARC/1.1 output of captured exchanges.
+//!
+//! Each completed exchange is written as three records, every one of them compressed
+//! as its own gzip member (or zstd frame, when `compression` selects zstd) so that
+//! readers can seek straight to it:
+//!
+//! - `response`: the HTTP response, identified by the `uuid` assigned in
+//!   `DemoResponseFilter::new`
+//! - `request`: the HTTP request, concurrent to the response
+//! - `metadata`: capture details such as the host entry and truncation, referring
+//!   to the response
+//!
+//! Redirects followed on the way get their own request and response records. A CDXJ
+//! index of the response records is written next to the archive at shutdown.
+//!
+//! With `encryption` configured, the archive and its index are encrypted as a whole.
+//! CDXJ offsets then refer to the decrypted archive.
+//!
+//! CEF hands the response filter the decoded body, so `Content-Encoding` and
+//! `Transfer-Encoding` are dropped from the archived headers and `Content-Length`
+//! is set to the archived body length.
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::blobs::hash as digest;
+use crate::capture::{Exchange, RequestInfo, ResponseInfo};
+use crate::compress::{self, Codec, Compressor};
+use crate::crypt::{self, Sink};
+
+/// WARC output, as written in the configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WarcConfig {
+    /// Path of the archive, `{session}` is replaced by the session identifier (and a
+    /// `-1`, `-2`... suffix when that file already exists)
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    String::from(".udata/warc/{session}.warc.gz")
+}
+
+/// Writes exchanges to a compressed WARC file and collects its CDXJ index.
+#[derive(Debug)]
+pub struct WarcWriter {
+    /// Configured path, with `{session}` still in it
+    template: String,
+    path: String,
+    session: String,
+    /// Compression of each record, gzip or zstd
+    codec: Codec,
+    level: i32,
+    compressor: Arc<Compressor>,
+    file: Option<Sink>,
+    /// Whether the archive was created, on the first record
+    opened: bool,
+    /// Offset of the next record in the file
+    offset: u64,
+    /// CDXJ lines of the response records written so far
+    index: Vec<String>,
+}
+
+impl WarcWriter {
+    /// Prepares the archive of a session. The file is created, and its `warcinfo` record
+    /// written, with the first exchange.
+    ///
+    /// # Parameters
+    /// - `config`: The WARC output configuration.
+    /// - `session`: Identifier of the session.
+    /// - `compressor`: Compression and encryption of the outputs. Records are gzipped
+    ///   unless it selects zstd.
+    pub fn new(config: &WarcConfig, session: &str, compressor: Arc<Compressor>) -> Self {
+        let compression = compressor.config();
+        let (codec, level) = match compression.codec {
+            Codec::None => (Codec::Gzip, Codec::Gzip.default_level()),
+            codec => (codec, compression.level()),
+        };
+        let mut writer = Self {
+            template: config.path.clone(),
+            path: String::new(),
+            session: session.to_string(),
+            codec,
+            level,
+            compressor,
+            file: None,
+            opened: false,
+            offset: 0,
+            index: Vec::new(),
+        };
+        writer.path = writer.attempt_path(0);
+
+        writer
+    }
+
+    /// Returns a candidate path of the archive.
+    ///
+    /// # Parameters
+    /// - `attempt`: Number of paths already taken by other archives; from 1 on, it's
+    ///   appended to the session identifier.
+    fn attempt_path(&self, attempt: u32) -> String {
+        let session = match attempt {
+            0 => self.session.clone(),
+            attempt => format!("{}-{}", self.session, attempt),
+        };
+        let path = self.codec.path(&self.template.replace("{session}", &session));
+        match self.compressor.keyring() {
+            Some(_) => crypt::path(&path),
+            None => path,
+        }
+    }
+
+    /// Creates the archive and writes its `warcinfo` record. An existing file is never
+    /// truncated: another process started in the same second gets the next free name.
+    fn open(&mut self) {
+        self.opened = true;
+
+        if let Some(dir) = std::path::Path::new(&self.path).parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+
+        for attempt in 0..100 {
+            let path = self.attempt_path(attempt);
+            match Sink::create_new(&path, self.compressor.keyring()) {
+                Ok(file) => {
+                    self.path = path;
+                    self.file = Some(file);
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    eprintln!("Failed to create WARC file {}: {}", path, e);
+                    return;
+                }
+            }
+        }
+        if self.file.is_none() {
+            eprintln!("Failed to create WARC file {}: every name is taken", self.path);
+            return;
+        }
+
+        let info = format!(
+            "software: {}/{}\r\nformat: WARC File Format 1.1\r\nisPartOf: {}\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            self.session
+        );
+        let headers = vec![
+            ("WARC-Type", String::from("warcinfo")),
+            ("WARC-Record-ID", record_id(uuid::Uuid::new_v4())),
+            ("WARC-Date", warc_date(&Utc::now())),
+            ("WARC-Filename", file_name(&self.path)),
+            ("Content-Type", String::from("application/warc-fields")),
+        ];
+        self.write(headers, info.as_bytes());
+    }
+
+    /// Returns the path of the archive.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Writes the records of a completed exchange.
+    pub fn record(&mut self, exchange: &Exchange) {
+        for (request, response) in &exchange.redirects {
+            self.exchange(uuid::Uuid::new_v4(), request, response, b"", Vec::new());
+        }
+
+        let (Some(request), Some(response)) = (exchange.request.as_ref(), exchange.response.as_ref()) else {
+            return;
+        };
+
+        let mut metadata = vec![format!("host-entry: {}", exchange.host_name())];
+        if let Some(session) = exchange.session.as_ref() {
+            metadata.push(format!("capture-session: {}", session.id));
+        }
+        if exchange.truncated {
+            metadata.push(String::from("truncated: length"));
+            if let Some(length) = exchange.original_length {
+                metadata.push(format!("original-length: {}", length));
+            }
+        }
+        if let Some(decoded) = exchange.decoded.as_ref() {
+            metadata.push(format!("decoded-scheme: {}", decoded.scheme));
+        }
+
+        let body = exchange.body.as_deref().unwrap_or_default();
+        self.exchange(exchange.uuid, request, response, body, metadata);
+    }
+
+    /// Flushes the archive and writes its CDXJ index, sorted by URL key and time.
+    /// Nothing is written if no exchange was recorded.
+    pub fn finish(&mut self) {
+        if !self.opened {
+            return;
+        }
+
+        if let Some(file) = self.file.take()
+            && let Err(e) = file.finish()
+        {
+            eprintln!("Failed to write WARC file {}: {}", self.path, e);
+        }
+
+        self.index.sort();
+        let path = cdxj_path(&self.path);
+        let contents = self.index.iter().map(|line| format!("{}\n", line)).collect::<String>();
+        let result = Sink::create(&path, self.compressor.keyring())
+            .and_then(|mut file| file.write_all(contents.as_bytes()).and_then(|_| file.finish()));
+        if let Err(e) = result {
+            eprintln!("Failed to write CDXJ index {}: {}", path, e);
+        }
+    }
+
+    /// Writes the response, request and metadata records of a single request.
+    ///
+    /// # Parameters
+    /// - `id`: Record id of the response.
+    /// - `metadata`: `name: value` lines of the metadata record; none are written if empty.
+    fn exchange(
+        &mut self,
+        id: uuid::Uuid,
+        request: &RequestInfo,
+        response: &ResponseInfo,
+        body: &[u8],
+        metadata: Vec<String>,
+    ) {
+        let date = warc_date(&request.started);
+        let response_id = record_id(id);
+
+        let block = http_response(response, body);
+        let payload_digest = digest(body);
+        let offset = self.offset;
+        self.write(
+            vec![
+                ("WARC-Type", String::from("response")),
+                ("WARC-Record-ID", response_id.clone()),
+                ("WARC-Date", date.clone()),
+                ("WARC-Target-URI", request.url.clone()),
+                ("Content-Type", String::from("application/http;msgtype=response")),
+                ("WARC-Payload-Digest", payload_digest.clone()),
+            ],
+            &block,
+        );
+
+        let cdxj = serde_json::json!({
+            "url": request.url,
+            "mime": response.mime_type,
+            "status": response.status.to_string(),
+            "digest": payload_digest,
+            "length": (self.offset - offset).to_string(),
+            "offset": offset.to_string(),
+            "filename": file_name(&self.path),
+        });
+        self.index.push(format!(
+            "{} {} {}",
+            surt(&request.url),
+            request.started.format("%Y%m%d%H%M%S"),
+            cdxj
+        ));
+
+        self.write(
+            vec![
+                ("WARC-Type", String::from("request")),
+                ("WARC-Record-ID", record_id(uuid::Uuid::new_v4())),
+                ("WARC-Date", date.clone()),
+                ("WARC-Target-URI", request.url.clone()),
+                ("WARC-Concurrent-To", response_id.clone()),
+                ("Content-Type", String::from("application/http;msgtype=request")),
+            ],
+            &http_request(request),
+        );
+
+        if !metadata.is_empty() {
+            let mut block = metadata.join("\r\n");
+            block.push_str("\r\n");
+            self.write(
+                vec![
+                    ("WARC-Type", String::from("metadata")),
+                    ("WARC-Record-ID", record_id(uuid::Uuid::new_v4())),
+                    ("WARC-Date", date),
+                    ("WARC-Target-URI", request.url.clone()),
+                    ("WARC-Refers-To", response_id),
+                    ("Content-Type", String::from("application/warc-fields")),
+                ],
+                block.as_bytes(),
+            );
+        }
+    }
+
+    /// Appends a record as a gzip member or zstd frame.
+    fn write(&mut self, mut headers: Vec<(&str, String)>, block: &[u8]) {
+        if !self.opened {
+            self.open();
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+
+        headers.push(("WARC-Block-Digest", digest(block)));
+        headers.push(("Content-Length", block.len().to_string()));
+
+        let mut record = Vec::with_capacity(block.len() + 512);
+        record.extend_from_slice(b"WARC/1.1\r\n");
+        for (name, value) in headers {
+            record.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        record.extend_from_slice(b"\r\n");
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+
+        let result = compress::compress(self.codec, self.level, &record)
+            .and_then(|member| file.write_all(&member).map(|_| member.len()));
+
+        match result {
+            Ok(length) => self.offset += length as u64,
+            Err(e) => {
+                eprintln!("Failed to write WARC file {}: {}", self.path, e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// Serializes a response as an HTTP/1.1 message with the archived body.
+fn http_response(response: &ResponseInfo, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("HTTP/1.1 {} {}\r\n", response.status, response.status_text).into_bytes();
+
+    for (name, value) in &response.headers {
+        if ["content-encoding", "transfer-encoding", "content-length"]
+            .iter()
+            .any(|skip| name.eq_ignore_ascii_case(skip))
+        {
+            continue;
+        }
+        message.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+
+    message.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+/// Serializes a request as an HTTP/1.1 message.
+fn http_request(request: &RequestInfo) -> Vec<u8> {
+    let (authority, target) = split_url(&request.url);
+    let mut message = format!("{} {} HTTP/1.1\r\n", request.method, target).into_bytes();
+
+    if !request.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("host")) {
+        message.extend_from_slice(format!("Host: {}\r\n", authority).as_bytes());
+    }
+    for (name, value) in &request.headers {
+        message.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    message.extend_from_slice(b"\r\n");
+
+    if let Some(body) = request.body.as_ref() {
+        message.extend_from_slice(body);
+    }
+    message
+}
+
+/// Splits a URL into its authority and request target (path and query).
+fn split_url(url: &str) -> (&str, String) {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split('#').next().unwrap_or_default();
+
+    match rest.find(['/', '?']) {
+        Some(index) if rest[index..].starts_with('?') => (&rest[..index], format!("/{}", &rest[index..])),
+        Some(index) => (&rest[..index], rest[index..].to_string()),
+        None => (rest, String::from("/")),
+    }
+}
+
+/// Converts a URL to the Sort-friendly URI Reordering Transform used as CDXJ key,
+/// e.g. `https://www.example.com/a?b` becomes `com,example)/a?b`.
+fn surt(url: &str) -> String {
+    let (authority, target) = split_url(url);
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default().to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let mut labels = host.split('.').collect::<Vec<_>>();
+    labels.reverse();
+
+    format!("{}){}", labels.join(","), target.to_ascii_lowercase())
+}
+
+fn record_id(id: uuid::Uuid) -> String {
+    format!("<urn:uuid:{}>", id)
+}
+
+fn warc_date(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+}
+
+fn file_name(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the path of the CDXJ index of an archive.
+fn cdxj_path(path: &str) -> String {
+    if let Some(path) = path.strip_suffix(crypt::EXTENSION) {
+        return crypt::path(&cdxj_path(path));
+    }
+
+    let stem = path
+        .strip_suffix(".warc.gz")
+        .or_else(|| path.strip_suffix(".warc.zst"))
+        .or_else(|| path.strip_suffix(".warc"))
+        .unwrap_or(path);
+    format!("{}.cdxj", stem)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::fs::File;
+    use std::io::Read;
+    use std::time::Instant;
+
+    #[test]
+    fn test_surt() {
+        assert_eq!(surt("https://www.Example.com:443/A?b=1#c"), "com,example)/a?b=1");
+        assert_eq!(surt("http://api.example.com?x"), "com,example,api)/?x");
+        assert_eq!(split_url("https://example.com"), ("example.com", String::from("/")));
+    }
+
+    #[test]
+    fn test_warc() {
+        let dir = std::env::temp_dir().join(format!("udata-{}", uuid::Uuid::new_v4()));
+        let config = WarcConfig {
+            path: dir.join("{session}.warc.gz").to_string_lossy().into_owned(),
+        };
+
+        let uuid = uuid::Uuid::new_v4();
+        let exchange = Exchange {
+            uuid,
+            url: String::from("https://example.com/api"),
+            request: Some(RequestInfo {
+                method: String::from("GET"),
+                url: String::from("https://example.com/api"),
+                headers: Vec::new(),
+                body: None,
+                page: None,
+                started: Utc::now(),
+                sent: Instant::now(),
+            }),
+            response: Some(ResponseInfo {
+                status: 200,
+                status_text: String::from("OK"),
+                headers: vec![(String::from("Content-Encoding"), String::from("gzip"))],
+                mime_type: String::from("application/json"),
+                redirect_url: None,
+                received: Instant::now(),
+            }),
+            body: Some(b"{}".to_vec()),
+            truncated: true,
+            ..Default::default()
+        };
+
+        let mut unused = WarcWriter::new(&config, "s1", Default::default());
+        unused.finish();
+        assert!(!dir.exists());
+
+        let mut writer = WarcWriter::new(&config, "s1", Default::default());
+        let mut other = WarcWriter::new(&config, "s1", Default::default());
+        writer.record(&exchange);
+        other.record(&exchange);
+        writer.finish();
+        other.finish();
+        assert_eq!(other.path(), dir.join("s1-1.warc.gz").to_string_lossy());
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(File::open(dir.join("s1.warc.gz")).unwrap())
+            .read_to_string(&mut warc)
+            .unwrap();
+        assert_eq!(warc.matches("WARC/1.1\r\n").count(), 4);
+        assert!(warc.contains(&format!("WARC-Record-ID: <urn:uuid:{}>", uuid)));
+        assert!(warc.contains(&format!("WARC-Concurrent-To: <urn:uuid:{}>", uuid)));
+        assert!(warc.contains("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}"));
+        assert!(warc.contains("GET /api HTTP/1.1\r\nHost: example.com\r\n"));
+        assert!(warc.contains("truncated: length"));
+
+        let cdxj = std::fs::read_to_string(dir.join("s1.cdxj")).unwrap();
+        assert!(cdxj.starts_with("com,example)/api "));
+        assert!(cdxj.contains("\"status\":\"200\""));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
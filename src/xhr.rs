@@ -22,9 +22,15 @@ use cef::{
 };
 
 use cef::{CefStringMultimap, ImplCallback, ReturnValue};
+use serde::Deserialize;
+use std::sync::Arc;
 
-use crate::config::Config;
+use crate::capture::{Exchange, SharedExchange};
+use crate::config::{Config, HostEntry};
 use crate::filter::DemoResponseFilter;
+use crate::helpers::{is_bytes_post_data, request_info, response_info, write_post_data};
+use crate::session::Session;
+use crate::swizzle::{Encoding, swizzle, swizzle_keyed};
 //
 // RequestHandler
 //
@@ -37,6 +43,8 @@ use crate::filter::DemoResponseFilter;
 pub struct DemoRequestHandler {
     pub base: *mut RcImpl<sys::_cef_request_handler_t, Self>,
     pub config: Option<Config>,
+    /// Capture state shared by every request
+    pub session: Arc<Session>,
 }
 
 impl DemoRequestHandler {
@@ -45,10 +53,11 @@ impl DemoRequestHandler {
     /// # Returns
     /// A new `RequestHandler` instance wrapping the `DemoRequestHandler` implementation.
     ///
-    pub(crate) fn new(config: Option<Config>) -> RequestHandler {
+    pub(crate) fn new(config: Option<Config>, session: Arc<Session>) -> RequestHandler {
         RequestHandler::new(Self {
             base: std::ptr::null_mut(),
-            config 
+            config,
+            session,
         })
     }
 }
@@ -76,6 +85,7 @@ impl ImplRequestHandler for DemoRequestHandler {
     /// # Behavior
     /// - For XHR requests: Sets cache prevention headers and returns a specialized handler
     /// - For non-navigation, non-download requests: Returns a handler for monitoring
+    /// - For navigations to hosts with injection rules: Returns a handler so the document can be rewritten
     /// - For all other requests: Returns None to use default browser handling
     fn get_resource_request_handler(
         &self,
@@ -126,11 +136,19 @@ impl ImplRequestHandler for DemoRequestHandler {
 
             // eprintln!(">>       | Is download: {:}", _is_download);
             // eprintln!(">>       | Found XHR request");
-            return Some(DemoResourceRequestHandler::new(self.config.clone()));
+            return Some(DemoResourceRequestHandler::new(self.config.clone(), self.session.clone()));
         }
 
-        if _is_download == 0 && _is_navigation == 0 {
-            Some(DemoResourceRequestHandler::new(self.config.clone()))
+        // Documents only need a handler when there's something to inject into them
+        let inject = self.config.as_ref().is_some_and(|config| {
+            let url = CefString::from(&request.get_url()).to_string();
+            config.host.iter().any(|host| {
+                !host.inject.is_empty() && host.matches_document(&url)
+            })
+        });
+
+        if _is_download == 0 && (_is_navigation == 0 || inject) {
+            Some(DemoResourceRequestHandler::new(self.config.clone(), self.session.clone()))
         } else {
             None
         }
@@ -161,6 +179,10 @@ impl ImplRequestHandler for DemoRequestHandler {
 pub struct DemoResourceRequestHandler {
     pub base: *mut RcImpl<sys::_cef_resource_request_handler_t, Self>,
     pub config: Option<Config>,
+    /// Capture state shared with the response filter
+    pub exchange: SharedExchange,
+    /// Capture state shared by every request
+    pub session: Arc<Session>,
 }
 
 impl DemoResourceRequestHandler {
@@ -173,10 +195,12 @@ impl DemoResourceRequestHandler {
     /// ```
     /// let resource_handler = DemoResourceRequestHandler::new();
     /// ```
-    fn new(config: Option<Config>) -> ResourceRequestHandler {
+    fn new(config: Option<Config>, session: Arc<Session>) -> ResourceRequestHandler {
         ResourceRequestHandler::new(Self {
             base: std::ptr::null_mut(),
-            config
+            config,
+            exchange: Default::default(),
+            session,
         })
     }
 }
@@ -185,7 +209,9 @@ impl ImplResourceRequestHandler for DemoResourceRequestHandler {
     /// Called before a resource is loaded.
     ///
     /// This method allows examining and modification of request parameters before
-    /// the request is actually sent to the server.
+    /// the request is actually sent to the server. Request bodies sent to hosts with
+    /// `swizzle_requests` enabled are swizzled here, and the request is recorded in
+    /// the exchange with its plaintext body. It's called again for each redirect.
     ///
     /// # Parameters
     /// - `_browser`: The browser instance initiating the request.
@@ -205,9 +231,81 @@ impl ImplResourceRequestHandler for DemoResourceRequestHandler {
         _request: Option<&mut impl ImplRequest>,
         _callback: Option<&mut impl ImplCallback>,
     ) -> ReturnValue {
+        let Some(request) = _request else {
+            return ReturnValue::from(cef_return_value_t::RV_CONTINUE);
+        };
+
+        let url = CefString::from(&request.get_url()).to_string();
+
+        // Navigations start a new page, anything else belongs to the current one
+        let page = if request.get_resource_type() == ResourceType::from(sys::cef_resource_type_t::RT_MAIN_FRAME) {
+            Some(url.clone())
+        } else {
+            _browser
+                .and_then(|browser| browser.get_main_frame())
+                .map(|frame| CefString::from(&frame.get_url()).to_string())
+        };
+
+        // Read before swizzling, so the plaintext body is recorded
+        let mut info = request_info(request, page);
+
+        let Ok(mut exchange) = self.exchange.lock() else {
+            return ReturnValue::from(cef_return_value_t::RV_CONTINUE);
+        };
+
+        // Hosts expecting swizzled request bodies, multipart bodies with files are sent as is
+        if let Some(config) = self.config.as_ref()
+            && let Some(host) = config
+                .host
+                .iter()
+                .find(|host| host.swizzle_requests.is_some() && host.matches_xhr(&url))
+            && is_bytes_post_data(request)
+            && let Some(body) = info.body.as_ref()
+            && let Some(body) = swizzle_body(host, &mut exchange, body)
+        {
+            write_post_data(request, &body);
+        }
+
+        // 307 and 308 redirects send the already swizzled body again
+        if info.body.is_some()
+            && let Some(plaintext) = exchange.request_plaintext.as_ref()
+        {
+            info.body = Some(plaintext.clone());
+        }
+        exchange.request = Some(info);
+
         ReturnValue::from(cef_return_value_t::RV_CONTINUE)
     }
 
+    /// Called when a resource load is redirected.
+    ///
+    /// The redirect response is kept in the exchange, so it can be recorded along with
+    /// the final response.
+    ///
+    /// # Parameters
+    /// - `_browser`: The browser instance processing the request.
+    /// - `_frame`: The frame within the browser that made the request.
+    /// - `_request`: The request being redirected.
+    /// - `_response`: The redirect response.
+    /// - `_new_url`: The URL the request is redirected to.
+    fn on_resource_redirect(
+        &self,
+        _browser: Option<&mut impl ImplBrowser>,
+        _frame: Option<&mut impl ImplFrame>,
+        _request: Option<&mut impl ImplRequest>,
+        _response: Option<&mut impl ImplResponse>,
+        _new_url: Option<&mut CefString>,
+    ) {
+        let (Some(response), Ok(mut exchange)) = (_response, self.exchange.lock()) else {
+            return;
+        };
+
+        if let Some(request) = exchange.request.take() {
+            let response = response_info(response, _new_url.map(|url| url.to_string()));
+            exchange.redirects.push((request, response));
+        }
+    }
+
     /// Called when a resource response is received.
     ///
     /// This method allows examining the response headers and status before
@@ -269,13 +367,29 @@ impl ImplResourceRequestHandler for DemoResourceRequestHandler {
             }
         };
 
-        Some(DemoResponseFilter::new(headers, self.config.clone(), url))
+        let mime_type = {
+            if let Some(res) = _response.as_ref() {
+                let mime = res.get_mime_type();
+                CefString::from(&mime).to_string()
+            } else {
+                String::from("")
+            }
+        };
+
+        if let (Some(res), Ok(mut exchange)) = (_response.as_ref(), self.exchange.lock()) {
+            let charset = res.get_charset();
+            exchange.charset = CefString::from(&charset).to_string();
+            exchange.response = Some(response_info(*res, None));
+        }
+
+        Some(DemoResponseFilter::new(headers, self.config.clone(), url, mime_type, self.exchange.clone(), self.session.clone()))
     }
 
     /// Called when a resource load is complete.
     ///
     /// This method provides notification about the completion status of a request,
-    /// whether it was successful or failed.
+    /// whether it was successful or failed. The capture pipeline is completed here,
+    /// or notified of the error.
     ///
     /// # Parameters
     /// - `_browser`: The browser instance that initiated the request.
@@ -293,6 +407,17 @@ impl ImplResourceRequestHandler for DemoResourceRequestHandler {
         _status: UrlrequestStatus,
         _received_content_length: i64,
     ) {
+        let Ok(mut exchange) = self.exchange.lock() else {
+            return;
+        };
+
+        if _status == UrlrequestStatus::from(sys::cef_urlrequest_status_t::UR_SUCCESS) {
+            exchange.complete();
+        } else if _status == UrlrequestStatus::from(sys::cef_urlrequest_status_t::UR_CANCELED) {
+            exchange.error("request canceled");
+        } else {
+            exchange.error("request failed");
+        }
     }
 
     /// Returns the raw pointer to the underlying CEF resource request handler.
@@ -304,3 +429,91 @@ impl ImplResourceRequestHandler for DemoResourceRequestHandler {
         self.base as *mut sys::_cef_resource_request_handler_t
     }
 }
+
+/// Form of the swizzled request bodies a host expects: `raw`, or the name of a
+/// `swizzle::Encoding` such as `base64` to send them as text.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum RequestSwizzle {
+    /// Swizzled bytes
+    Raw,
+    /// Swizzled bytes written in a text encoding
+    Encoded(Encoding),
+}
+
+impl TryFrom<String> for RequestSwizzle {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.as_str() {
+            "raw" => Ok(RequestSwizzle::Raw),
+            _ => Encoding::from_name(&name)
+                .map(RequestSwizzle::Encoded)
+                .ok_or_else(|| format!("unknown request encoding `{}`", name)),
+        }
+    }
+}
+
+/// Swizzles a request body for a host expecting it. Chromium runs
+/// `on_before_resource_load` again for each redirect, and keeps the already swizzled
+/// body on 307 and 308, so it's only swizzled once per exchange.
+///
+/// # Returns
+/// The body to send instead, or `None` to leave the request unchanged.
+fn swizzle_body(host: &HostEntry, exchange: &mut Exchange, body: &[u8]) -> Option<Vec<u8>> {
+    let form = host.swizzle_requests?;
+    if exchange.request_plaintext.is_some() || body.is_empty() {
+        return None;
+    }
+    exchange.request_plaintext = Some(body.to_vec());
+
+    let swizzled = match host.swizzle_key.as_ref() {
+        Some(key) => swizzle_keyed(body, key.as_bytes()),
+        None => swizzle(body),
+    };
+    Some(match form {
+        RequestSwizzle::Raw => swizzled,
+        RequestSwizzle::Encoded(encoding) => encoding.encode(&swizzled).into_bytes(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_swizzle_body() {
+        let host: HostEntry = serde_json::from_value(serde_json::json!({
+            "host": "https://example.com",
+            "xhr": "/api",
+            "swizzle_requests": "raw",
+        }))
+        .unwrap();
+        let keyed = HostEntry { swizzle_key: Some(String::from("secret")), ..host.clone() };
+
+        let mut exchange = Exchange::default();
+        assert_eq!(swizzle_body(&host, &mut exchange, b""), None);
+        let body = swizzle_body(&host, &mut exchange, b"{\"a\":1}").unwrap();
+        assert_eq!(body, swizzle(b"{\"a\":1}"));
+        assert_eq!(exchange.request_plaintext.as_deref(), Some(&b"{\"a\":1}"[..]));
+
+        // A 307 redirect sends the swizzled body again
+        assert_eq!(swizzle_body(&host, &mut exchange, &body), None);
+
+        let mut exchange = Exchange::default();
+        let body = swizzle_body(&keyed, &mut exchange, b"{\"a\":1}").unwrap();
+        assert_eq!(body, swizzle_keyed(b"{\"a\":1}", b"secret"));
+        assert_eq!(swizzle_body(&keyed, &mut exchange, &body), None);
+
+        let encoded = HostEntry { swizzle_requests: Some(RequestSwizzle::Encoded(Encoding::Hex)), ..host.clone() };
+        let mut exchange = Exchange::default();
+        let body = swizzle_body(&encoded, &mut exchange, b"{\"a\":1}").unwrap();
+        assert_eq!(body, Encoding::Hex.encode(&swizzle(b"{\"a\":1}")).into_bytes());
+
+        assert_eq!(
+            RequestSwizzle::try_from(String::from("base64url")),
+            Ok(RequestSwizzle::Encoded(Encoding::Base64Url))
+        );
+        assert!(RequestSwizzle::try_from(String::from("rot13")).is_err());
+    }
+}